@@ -4,7 +4,6 @@ use futures::{StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
 use std::io::Write;
 use std::path::Path;
-use tempfile::NamedTempFile;
 
 use crate::command_handlers;
 use crate::utils;
@@ -26,77 +25,91 @@ struct SaveOptions {
 // API endpoint for finding songs
 // API endpoint for finding songs
 async fn api_find(mut payload: Multipart) -> Result<impl Responder, Error> {
-    // Save the uploaded file to a temporary location
-    let mut temp_file = NamedTempFile::new()
-        .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
-    let file_path = temp_file.path().to_string_lossy().to_string();
-    
-    // Process uploaded file
-    let mut filename = String::new();
-    while let Some(item) = payload.next().await {
-        let mut field = item.map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
-        
-        // Attempt to get the field name and filename
-        if let Some(content_disposition) = field.content_disposition() {
-            if let Some(name) = content_disposition.get_name() {
-                if name == "file" { // Assuming the field name is "file"
-                    if let Some(fname) = content_disposition.get_filename() {
-                        filename = fname.to_string();
-                    }
-                }
-            }
-        }
-        
-        // Read and write file data
-        while let Some(chunk) = field.next().await {
-            let data = chunk.map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
-            temp_file.write_all(&data)
-                .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
-        }
-    }
-    
-    // Make sure to flush the file to ensure all data is written
-    temp_file.flush()
-        .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
-    
+    // Every request gets its own scratch directory under tmp/, removed when it is dropped.
+    let scratch = wav::ScratchDir::new()
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
+    let (filename, file_path) = save_upload(&mut payload, &scratch).await?;
+
     // If file is not a WAV file, try to convert it
     let file_extension = Path::new(&filename).extension()
         .and_then(|ext| ext.to_str())
         .unwrap_or("").to_lowercase();
-    
-    let processing_path = if file_extension != "wav" {
-        // Convert to WAV if not already in WAV format
-        match web::block(move || {
-            wav::convert_to_wav(&file_path, 1)
-                .map_err(|e| e.to_string()) // Convert error to String to make it Send
-        }).await {
-            Ok(Ok(wav_path)) => wav_path,
-            Ok(Err(err_msg)) => return Err(actix_web::error::ErrorInternalServerError(err_msg)),
-            Err(e) => return Err(actix_web::error::ErrorInternalServerError(e))
-        }
-    } else {
-        file_path
-    };
-    
+
     // Run find in a blocking task as it's CPU intensive
     let results = web::block(move || {
+        // Keep the scratch directory, and any converted copy in it, alive until the search is done.
+        let _scratch = scratch;
+        let converted = if file_extension != "wav" {
+            // Convert to WAV if not already in WAV format
+            Some(wav::convert_to_wav(&file_path).map_err(|e| e.to_string())?)
+        } else {
+            None
+        };
+        let processing_path = converted.as_ref().map_or(file_path.as_str(), |converted| converted.path());
+
         let rt = tokio::runtime::Runtime::new().unwrap();
-        rt.block_on(async {
-            match shazam::find_matches_for_api(&processing_path).await {
-                Ok(matches) => matches,
+        Ok::<_, String>(rt.block_on(async {
+            match shazam::find_matches_for_api(processing_path).await {
+                Ok(recognition) => recognition,
                 Err(e) => {
                     println!("Error finding matches: {:?}", e);
-                    Vec::new()
+                    shazam::Recognition::NoMatch {
+                        reason: format!("error finding matches: {}", e),
+                        candidates: Vec::new(),
+                    }
                 }
             }
-        })
+        }))
     })
     .await
+    .map_err(|e| actix_web::error::ErrorInternalServerError(e))?
     .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
     
     Ok(HttpResponse::Ok().json(results))
 }
 
+/// Writes the uploaded file into the scratch directory, returning the client's file name
+/// (empty if none was given) and the path it was saved to.
+async fn save_upload(payload: &mut Multipart, scratch: &wav::ScratchDir) -> Result<(String, String), Error> {
+    let mut filename = String::new();
+    let mut file_path = scratch.file("upload");
+    let mut file = None;
+    while let Some(item) = payload.next().await {
+        let mut field = item.map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
+        
+        // Attempt to get the field name and filename
+        if let Some(content_disposition) = field.content_disposition()
+            && content_disposition.get_name() == Some("file") // Assuming the field name is "file"
+            && let Some(fname) = content_disposition.get_filename()
+        {
+            filename = fname.to_string();
+            if file.is_none() && !filename.is_empty() {
+                file_path = scratch.file(&filename);
+            }
+        }
+
+        let file = match file.as_mut() {
+            Some(file) => file,
+            None => file.insert(
+                std::fs::File::create(&file_path).map_err(|e| actix_web::error::ErrorInternalServerError(e))?,
+            ),
+        };
+        // Read and write file data
+        while let Some(chunk) = field.next().await {
+            let data = chunk.map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
+            file.write_all(&data)
+                .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
+        }
+    }
+    
+    // Make sure to flush the file to ensure all data is written
+    if let Some(mut file) = file {
+        file.flush()
+            .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
+    }
+    Ok((filename, file_path))
+}
+
 // API endpoint for downloading songs
 async fn api_download(url_data: web::Json<SpotifyUrl>) -> Result<impl Responder, Error> {
     web::block(move || {
@@ -111,22 +124,13 @@ async fn api_download(url_data: web::Json<SpotifyUrl>) -> Result<impl Responder,
 // API endpoint for saving songs
 async fn api_save(mut payload: Multipart, query: web::Query<SaveOptions>) -> Result<impl Responder, Error> {
     let force = query.force.unwrap_or(false);
-    let mut temp_file = NamedTempFile::new()
-        .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
-    let file_path = temp_file.path().to_string_lossy().to_string();
-    
-    // Process uploaded file
-    while let Some(item) = payload.next().await {
-        let mut field = item.map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
-        while let Some(chunk) = field.next().await {
-            let data = chunk.map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
-            temp_file.write_all(&data)
-                .map_err(|e| actix_web::error::ErrorInternalServerError(e))?;
-        }
-    }
+    let scratch = wav::ScratchDir::new()
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.to_string()))?;
+    let (_, file_path) = save_upload(&mut payload, &scratch).await?;
     
     // Run save in a blocking task
     web::block(move || {
+        let _scratch = scratch;
         command_handlers::save(&file_path, force)
     })
     .await
@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::env;
 use axum::http;
 use std::fs;
@@ -8,6 +9,8 @@ use slog::error;
 
 use colored::Colorize;
 use walkdir::WalkDir;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
 use crate::db;
 use crate::shazam;
@@ -22,44 +25,42 @@ use crate::models;
 
 const SONGS_DIR: &str = "songs";
 
-pub async fn find(file_path: &str) {
-    // Convert relative path to absolute for better error reporting
-    let absolute_path = std::path::Path::new(file_path)
-        .canonicalize()
-        .unwrap_or_else(|_| std::path::PathBuf::from(file_path));
-    
-    println!("Attempting to read file: {}", absolute_path.display());
-    
-    if !std::path::Path::new(file_path).exists() {
-        println!("{}", format!("Error: File '{}' does not exist", file_path).yellow());
-        return;
-    }
+/// Identifies the song in an audio file, or in audio read from stdin when `file_path` is "-".
+/// Headerless stdin input is interpreted with `raw_format`; a WAV stream carries its own.
+pub async fn find(file_path: &str, raw_format: &wav::RawPcmFormat) {
+    let result = if file_path == "-" {
+        let decoded = wav::read_pcm_stream(io::stdin().lock(), raw_format);
+        match decoded {
+            Ok((samples, sample_rate)) => shazam::find_matches(&samples, sample_rate).await,
+            Err(e) => {
+                println!("{}", format!("Error reading audio from stdin: {}", e).yellow());
+                return;
+            }
+        }
+    } else {
+        // Convert relative path to absolute for better error reporting
+        let absolute_path = std::path::Path::new(file_path)
+            .canonicalize()
+            .unwrap_or_else(|_| std::path::PathBuf::from(file_path));
 
-    let wav_info = match wav::read_wav_info(file_path) {
-        Ok(info) => info,
-        Err(e) => {
-            println!("{}", format!("Error reading wave info: {:?}", e).yellow());
+        println!("Attempting to read file: {}", absolute_path.display());
+
+        if !std::path::Path::new(file_path).exists() {
+            println!("{}", format!("Error: File '{}' does not exist", file_path).yellow());
             return;
         }
+        shazam::find_matches_in_file(file_path).await
     };
 
-    let samples = match wav::wav_bytes_to_samples(&wav_info.data) {
-        Ok(s) => s,
+    let (recognition, search_duration) = match result {
+        Ok(result) => result,
         Err(e) => {
-            println!("{}", format!("Error converting to samples: {:?}", e).yellow());
+            println!("{}", format!("Error finding matches: {:?}", e).yellow());
             return;
         }
     };
 
-    let (matches, search_duration) =
-        match shazam::find_matches(&samples, wav_info.duration, wav_info.sample_rate).await {
-            Ok(result) => result,
-            Err(e) => {
-                println!("{}", format!("Error finding matches: {:?}", e).yellow());
-                return;
-            }
-        };
-
+    let matches = recognition.candidates();
     if matches.is_empty() {
         println!("\nNo match found.");
         println!("\nSearch took: {:?}", search_duration);
@@ -69,23 +70,96 @@ pub async fn find(file_path: &str) {
     let (msg, top_matches) = if matches.len() >= 20 {
         ("Top 20 matches:", &matches[..20])
     } else {
-        ("Matches:", &matches[..])
+        ("Matches:", matches)
     };
 
     println!("{}", msg);
     for m in top_matches {
         println!(
-            "\t- {} by {}, score: {:.2}",
-            m.song_title, m.song_artist, m.score
+            "\t- {} by {}, score: {:.2}, confidence: {:.1}%, at {} (matched {}-{})",
+            m.song_title,
+            m.song_artist,
+            m.score,
+            m.confidence * 100.0,
+            utils::format_timestamp(m.timestamp),
+            utils::format_timestamp(m.match_start_ms),
+            utils::format_timestamp(m.match_end_ms)
         );
     }
     println!("\nSearch took: {:?}", search_duration);
 
-    let top_match = &top_matches[0];
-    println!(
-        "\nFinal prediction: {} by {} , score: {:.2}",
-        top_match.song_title, top_match.song_artist, top_match.score
-    );
+    match &recognition {
+        shazam::Recognition::Match { song, .. } => println!(
+            "\nFinal prediction: {} by {} , score: {:.2}, confidence: {:.1}%, you're {} into the track",
+            song.song_title,
+            song.song_artist,
+            song.score,
+            song.confidence * 100.0,
+            utils::format_timestamp(song.timestamp)
+        ),
+        shazam::Recognition::NoMatch { reason, .. } => {
+            println!("{}", format!("\nNo match: {}", reason).yellow())
+        }
+    }
+}
+
+/// Identifies every song in a long recording and prints (or writes to `output`) the
+/// timeline in the given format: "json", "csv" or "cue".
+pub async fn segment(file_path: &str, options: &shazam::SegmentOptions, format: &str, output: Option<&str>) {
+    if !Path::new(file_path).exists() {
+        println!("{}", format!("Error: File '{}' does not exist", file_path).yellow());
+        return;
+    }
+
+    let segments = match shazam::segment_file(file_path, options).await {
+        Ok(segments) => segments,
+        Err(e) => {
+            println!("{}", format!("Error segmenting recording: {:?}", e).yellow());
+            return;
+        }
+    };
+
+    let audio_file = Path::new(file_path).file_name().map_or(file_path.into(), |name| name.to_string_lossy());
+    let timeline = match format {
+        "csv" => shazam::timeline_to_csv(&segments),
+        "cue" => shazam::timeline_to_cue(&segments, &audio_file),
+        _ => match shazam::timeline_to_json(&segments) {
+            Ok(json) => json,
+            Err(e) => {
+                println!("{}", format!("Error encoding timeline: {:?}", e).yellow());
+                return;
+            }
+        },
+    };
+
+    match output {
+        Some(output) => match fs::write(output, timeline) {
+            Ok(()) => println!("Wrote {} segments to {}", segments.len(), output),
+            Err(e) => println!("{}", format!("Error writing {}: {:?}", output, e).yellow()),
+        },
+        None => print!("{}", timeline),
+    }
+}
+
+/// Monitors a continuous PCM stream read from `source` ("-" for stdin, or a file or FIFO)
+/// and logs every song played to the database and to `log_path`.
+pub async fn monitor(source: &str, options: &shazam::MonitorOptions, log_path: &str) {
+    let result = if source == "-" {
+        shazam::monitor_stream(io::stdin().lock(), options, log_path).await
+    } else {
+        match fs::File::open(source) {
+            Ok(file) => shazam::monitor_stream(file, options, log_path).await,
+            Err(e) => {
+                println!("{}", format!("Error opening {}: {:?}", source, e).yellow());
+                return;
+            }
+        }
+    };
+
+    match result {
+        Ok(plays) => println!("Stream ended; logged {} plays to {}", plays, log_path),
+        Err(e) => println!("{}", format!("Error monitoring stream: {:?}", e).yellow()),
+    }
 }
 
 pub fn download(spotify_url: &str) {
@@ -234,6 +308,18 @@ pub async fn erase(songs_dir: &str) {
 
     }
 
+    if let Err(e) = db_client.delete_collection("metadata") {
+        let msg = format!("Error deleting collection: {:?}", e);
+
+        error!(logger, "{}", msg; "error" => e.to_string());
+    }
+
+    if let Err(e) = db_client.delete_collection("plays") {
+        let msg = format!("Error deleting collection: {:?}", e);
+
+        error!(logger, "{}", msg; "error" => e.to_string());
+    }
+
     // Delete song files.
     if let Err(e) = WalkDir::new(songs_dir).into_iter().try_for_each(|entry| {
         let entry = entry?;
@@ -256,6 +342,291 @@ pub async fn erase(songs_dir: &str) {
     println!("Erase complete");
 }
 
+/// Re-fingerprints every song stored in `songs_dir` with the current pipeline into a fresh
+/// table and swaps it in once all files are done. Interrupted runs resume where they left off.
+pub async fn reindex(songs_dir: &str) {
+    let config = match shazam::FingerprintConfig::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            println!("{}", format!("Error loading fingerprint config: {:?}", e).yellow());
+            return;
+        }
+    };
+    let metadata = config.index_metadata();
+
+    let mut db_client = match db::new_db_client().await {
+        Ok(client) => client,
+        Err(e) => {
+            println!("{}", format!("Error creating DB client: {:?}", e).yellow());
+            return;
+        }
+    };
+
+    let done: HashSet<u32> = match db_client.begin_reindex(&metadata) {
+        Ok(done) => done.into_iter().collect(),
+        Err(e) => {
+            println!("{}", format!("Error preparing reindex: {:?}", e).yellow());
+            return;
+        }
+    };
+    if !done.is_empty() {
+        println!("Resuming reindex: {} songs already done", done.len());
+    }
+
+    let files: Vec<PathBuf> = WalkDir::new(songs_dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.into_path())
+        .filter(|path| path.extension().and_then(|s| s.to_str()) == Some("wav"))
+        .collect();
+
+    let mut reindexed: HashSet<u32> = done.clone();
+    let mut failed = 0;
+    for (i, path) in files.iter().enumerate() {
+        let progress = format!("[{}/{}]", i + 1, files.len());
+
+        let song = match song_for_file(db_client.as_ref(), path) {
+            Ok(Some(song)) => song,
+            Ok(None) => {
+                println!("{} Skipping {}: no matching song in the database", progress, path.display());
+                continue;
+            }
+            Err(e) => {
+                println!("{}", format!("{} Error looking up {}: {:?}", progress, path.display(), e).yellow());
+                failed += 1;
+                continue;
+            }
+        };
+
+        if done.contains(&song.id) {
+            println!("{} '{}' by '{}' already reindexed", progress, song.title, song.artist);
+            continue;
+        }
+
+        let fingerprints = match download::fingerprint_song_file(&path.to_string_lossy(), song.id, &config) {
+            Ok(fingerprints) => fingerprints,
+            Err(e) => {
+                println!("{}", format!("{} Error fingerprinting {}: {:?}", progress, path.display(), e).yellow());
+                failed += 1;
+                continue;
+            }
+        };
+
+        if let Err(e) = db_client.store_reindexed_fingerprints(song.id, &fingerprints) {
+            println!("{}", format!("{} Error storing fingerprints for {}: {:?}", progress, path.display(), e).yellow());
+            failed += 1;
+            continue;
+        }
+
+        reindexed.insert(song.id);
+        println!("{} Reindexed '{}' by '{}'", progress, song.title, song.artist);
+    }
+
+    if failed > 0 {
+        println!(
+            "{}",
+            format!("{} files failed; the old index is still live. Fix them and run reindex again to resume.", failed).yellow()
+        );
+        return;
+    }
+
+    if let Ok(total) = db_client.total_songs() {
+        let missing = total as usize - reindexed.len().min(total as usize);
+        if missing > 0 {
+            println!(
+                "{}",
+                format!("Warning: {} songs in the database have no audio file in {} and won't be matchable", missing, songs_dir).yellow()
+            );
+        }
+    }
+
+    if let Err(e) = db_client.finish_reindex(&metadata) {
+        println!("{}", format!("Error swapping in the new index: {:?}", e).yellow());
+        return;
+    }
+
+    println!("Reindex complete: {} songs fingerprinted", reindexed.len());
+}
+
+/// Finds the database row for a stored audio file, using its title/artist tags and falling back
+/// to the "<title> - <artist>" file names produced by the downloader.
+fn song_for_file(db_client: &dyn db::DBClient, path: &Path) -> Result<Option<db::Song>, Box<dyn Error>> {
+    let mut candidates = Vec::new();
+
+    if let Ok(tags) = wav::read_tags(&path.to_string_lossy()) {
+        // Files tagged by the downloader carry their YouTube ID, and their song ID for the
+        // database that fingerprinted them.
+        if let Some(yt_id) = &tags.youtube_id
+            && let (song, true) = db_client.get_song_by_ytid(yt_id)?
+        {
+            return Ok(Some(song));
+        }
+        if let Some(song_id) = tags.song_id
+            && let (song, true) = db_client.get_song_by_id(song_id)?
+            && tags.title.as_ref() == Some(&song.title)
+        {
+            return Ok(Some(song));
+        }
+        if let (Some(title), Some(artist)) = (tags.title.as_ref(), tags.artist()) {
+            candidates.push(utils::generate_song_key(title, &artist));
+        }
+    }
+
+    let file_stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+    if let Some((title, artist)) = file_stem.rsplit_once(" - ") {
+        candidates.push(utils::generate_song_key(title, artist));
+    }
+
+    for key in candidates {
+        let (song, song_exists) = db_client.get_song_by_key(&key)?;
+        if song_exists {
+            return Ok(Some(song));
+        }
+    }
+    Ok(None)
+}
+
+/// Cuts clips out of the songs in `songs_dir` for use as test queries: one for each
+/// `(file, offset in seconds)` in `segments`, then `options.count` more from random songs at
+/// random offsets. Each clip is degraded as configured and written to `output_dir`, along with
+/// a `manifest.json` recording the song and offset every clip was taken from.
+pub async fn clip(songs_dir: &str, segments: &[(String, f64)], options: &wav::ClipOptions, output_dir: &str) {
+    if let Err(e) = options.validate() {
+        println!("{}", format!("Error: {}", e).yellow());
+        return;
+    }
+
+    // Without a seed, pick one and print it so the set can be regenerated.
+    let seed = options.seed.unwrap_or_else(rand::random);
+    println!("Seed: {}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let mut plan: Vec<(PathBuf, Option<u32>)> = Vec::new();
+    for (file, seconds) in segments {
+        if !Path::new(file).exists() {
+            println!("{}", format!("Error: File '{}' does not exist", file).yellow());
+            return;
+        }
+        if !seconds.is_finite() || *seconds < 0.0 {
+            println!("{}", format!("Error: invalid offset {} for '{}'", seconds, file).yellow());
+            return;
+        }
+        plan.push((PathBuf::from(file), Some((seconds * 1000.0).round() as u32)));
+    }
+
+    if options.count > 0 {
+        let files: Vec<PathBuf> = WalkDir::new(songs_dir)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+            .map(|entry| entry.into_path())
+            .filter(|path| path.extension().and_then(|s| s.to_str()) == Some("wav"))
+            .collect();
+        if files.is_empty() {
+            println!("{}", format!("Error: no songs found in {}", songs_dir).yellow());
+            return;
+        }
+        let mut picks: Vec<PathBuf> = (0..options.count)
+            .map(|_| files[rng.random_range(0..files.len())].clone())
+            .collect();
+        // Clips from the same song are cut one after another so it is only decoded once.
+        picks.sort();
+        plan.extend(picks.into_iter().map(|path| (path, None)));
+    }
+
+    if plan.is_empty() {
+        println!("{}", "Nothing to clip: give --count or --segment".yellow());
+        return;
+    }
+
+    if let Err(e) = utils::create_folder(output_dir) {
+        println!("{}", format!("Error creating {}: {:?}", output_dir, e).yellow());
+        return;
+    }
+
+    let db_client = match db::new_db_client().await {
+        Ok(client) => client,
+        Err(e) => {
+            println!("{}", format!("Error creating DB client: {:?}", e).yellow());
+            return;
+        }
+    };
+
+    let sample_rate = wav::CLIP_SAMPLE_RATE;
+    let mut decoded: Option<(PathBuf, Vec<f64>)> = None;
+    let mut manifest = Vec::new();
+    for (i, (path, offset_ms)) in plan.iter().enumerate() {
+        let progress = format!("[{}/{}]", i + 1, plan.len());
+
+        if decoded.as_ref().is_none_or(|(decoded_path, _)| decoded_path != path) {
+            match wav::decode_audio(&path.to_string_lossy(), sample_rate) {
+                Ok(samples) => decoded = Some((path.clone(), samples)),
+                Err(e) => {
+                    println!("{}", format!("{} Error decoding {}: {:?}", progress, path.display(), e).yellow());
+                    decoded = None;
+                    continue;
+                }
+            }
+        }
+        let Some((_, samples)) = decoded.as_ref() else { continue };
+
+        let song_ms = (samples.len() as u64 * 1000 / sample_rate as u64) as u32;
+        let offset_ms = offset_ms.unwrap_or_else(|| rng.random_range(0..=song_ms.saturating_sub(options.length_ms)));
+        let segment = match wav::extract_segment(samples, sample_rate, offset_ms, options.length_ms) {
+            Ok(segment) => segment,
+            Err(e) => {
+                println!("{}", format!("{} Error cutting {}: {}", progress, path.display(), e).yellow());
+                continue;
+            }
+        };
+        let duration_ms = (segment.len() as u64 * 1000 / sample_rate as u64) as u32;
+
+        let degraded = match wav::degrade(&segment, sample_rate, &options.degrade, &mut rng) {
+            Ok(degraded) => degraded,
+            Err(e) => {
+                println!("{}", format!("{} Error degrading clip of {}: {}", progress, path.display(), e).yellow());
+                continue;
+            }
+        };
+
+        let file = format!("clip_{:03}.wav", i + 1);
+        let clip_path = Path::new(output_dir).join(&file);
+        if let Err(e) = wav::write_clip(&clip_path.to_string_lossy(), &degraded, sample_rate) {
+            println!("{}", format!("{} Error writing {}: {:?}", progress, clip_path.display(), e).yellow());
+            continue;
+        }
+
+        let song = match song_for_file(db_client.as_ref(), path) {
+            Ok(song) => song,
+            Err(e) => {
+                println!("{}", format!("{} Error looking up {}: {:?}", progress, path.display(), e).yellow());
+                None
+            }
+        };
+        println!("{} {} <- {} at {:.1}s", progress, file, path.display(), offset_ms as f64 / 1000.0);
+        manifest.push(wav::ClipManifestEntry {
+            file,
+            source: path.to_string_lossy().into_owned(),
+            song_id: song.as_ref().map(|song| song.id),
+            song_title: song.as_ref().map(|song| song.title.clone()),
+            song_artist: song.as_ref().map(|song| song.artist.clone()),
+            offset_ms,
+            duration_ms,
+            degradations: options.degrade.describe(),
+        });
+    }
+
+    let manifest_path = Path::new(output_dir).join("manifest.json");
+    let written = serde_json::to_string_pretty(&manifest)
+        .map_err(|e| e.to_string())
+        .and_then(|json| fs::write(&manifest_path, json).map_err(|e| e.to_string()));
+    match written {
+        Ok(()) => println!("Wrote {} clips and {}", manifest.len(), manifest_path.display()),
+        Err(e) => println!("{}", format!("Error writing {}: {}", manifest_path.display(), e).yellow()),
+    }
+}
+
 pub fn save(path: &str, force: bool) {
     let metadata = match fs::metadata(path) {
         Ok(m) => m,
@@ -287,38 +658,26 @@ pub fn save(path: &str, force: bool) {
 }
 
 pub fn save_song(file_path: &Path, force: bool) -> Result<(), Box<dyn Error>> {
-
-    let file_ext = file_path.extension()
-    .and_then(|s| s.to_str())
-    .unwrap_or_default();
-
-if file_ext.to_lowercase() == "mp3" {
-    // First convert MP3 to WAV before proceeding
-    let wav_path = match wav::convert_to_wav(file_path.to_str().unwrap_or_default(), 1) {
-        Ok(path) => path,
-        Err(e) => {
-            return Err(format!("Failed to convert MP3 to WAV: {:?}", e).into());
-        }
+    let file_path_str = file_path.to_str().ok_or("Invalid path")?;
+    let tags = wav::read_tags(file_path_str)?;
+    let duration_float = match tags.duration {
+        Some(duration) => duration,
+        None => wav::decoder_for(file_path_str)?
+            .probe(file_path_str)
+            .map_err(|e| format!("failed to read audio properties: {:?}", e))?
+            .duration,
     };
-    // Continue with the converted file
-    return save_song(&Path::new(&wav_path), force);
-}
-
-    let metadata = wav::get_metadata(file_path.to_str().ok_or("Invalid path")?)?;
-    let duration_float: f64 = metadata.format.duration.parse().map_err(|e| {
-        format!("failed to parse duration to float: {:?}", e)
-    })?;
 
-    let tags = metadata.format.tags.unwrap_or_default();
     let track = models::Track {
-        album: tags.get("album").cloned().unwrap_or_default(),
-        artist: tags.get("artist").cloned().unwrap_or_default(),
-        artists: Vec::new(),
-        title: tags.get("title").cloned().unwrap_or_default(),
-        duration: duration_float.round() as f64,
+        album: tags.album.clone().unwrap_or_default(),
+        artist: tags.artist().unwrap_or_default(),
+        artists: tags.artists.clone(),
+        title: tags.title.clone().unwrap_or_default(),
+        duration: duration_float.round(),
     };
 
-    let yt_id = match download::get_youtube_id(&track) {
+    // Files tagged by the downloader already name their video.
+    let yt_id = match tags.youtube_id.clone().map_or_else(|| download::get_youtube_id(&track), Ok) {
         Ok(id) => id,
         Err(e) if !force => {
             return Err(Box::new(io::Error::new(
@@ -342,17 +701,17 @@ if file_ext.to_lowercase() == "mp3" {
         )));
     }
 
-    download::process_and_save_song(file_path.to_str().ok_or("Invalid path")?, &track.title, &track.artist, &yt_id)
+    // Work on a converted copy in a scratch directory; the input file is left untouched.
+    let converted = wav::convert_to_wav(file_path_str)?;
+    let song_id = download::process_and_save_song(converted.path(), &track.title, &track.artist, &yt_id)
         .map_err(|e| format!("failed to process or save song: {:?}", e))?;
+    download::add_tags(converted.path(), &track, song_id, &yt_id)?;
 
     let file_stem = file_path
         .file_stem()
         .and_then(|s| s.to_str())
         .unwrap_or_default();
-    let wav_file = format!("{}.wav", file_stem);
-    let source_path = file_path.with_file_name(&wav_file);
-    let new_file_path = Path::new(SONGS_DIR).join(&wav_file);
-    fs::rename(source_path, new_file_path)
-        .map_err(|e| format!("failed to rename temporary file to output file: {:?}", e))?;
+    utils::create_folder(SONGS_DIR)?;
+    converted.persist(Path::new(SONGS_DIR).join(format!("{}.wav", file_stem)))?;
     Ok(())
 }
@@ -2,14 +2,17 @@ use std::collections::HashMap;
 use std::error::Error;
 
 use crate::models;
+use crate::shazam::FingerprintConfig;
 use crate::utils;
 
 /// The DBClient trait defines the interface for database operations.
 pub trait DBClient {
     fn close(&mut self) -> Result<(), Box<dyn Error>>;
-    fn store_fingerprints(&mut self, fingerprints: &HashMap<u32, models::Couple>) -> Result<(), Box<dyn Error>>;
+    fn store_fingerprints(&mut self, fingerprints: &[(u32, models::Couple)]) -> Result<(), Box<dyn Error>>;
     fn get_couples(&self, addresses: &[u32]) -> Result<HashMap<u32, Vec<models::Couple>>, Box<dyn Error>>;
     fn total_songs(&self) -> Result<i32, Box<dyn Error>>;
+    /// Reports whether any fingerprints are stored.
+    fn has_fingerprints(&self) -> Result<bool, Box<dyn Error>>;
     fn register_song(&mut self, song_title: &str, song_artist: &str, yt_id: &str) -> Result<u32, Box<dyn Error>>;
     fn get_song(&self, filter_key: &str, value: &str) -> Result<(Song, bool), Box<dyn Error>>;
     fn get_song_by_id(&self, song_id: u32) -> Result<(Song, bool), Box<dyn Error>>;
@@ -17,11 +20,47 @@ pub trait DBClient {
     fn get_song_by_key(&self, key: &str) -> Result<(Song, bool), Box<dyn Error>>;
     fn delete_song_by_id(&mut self, song_id: u32) -> Result<(), Box<dyn Error>>;
     fn delete_collection(&mut self, collection_name: &str) -> Result<(), Box<dyn Error>>;
+    fn get_index_metadata(&self) -> Result<Option<models::IndexMetadata>, Box<dyn Error>>;
+    fn set_index_metadata(&mut self, metadata: &models::IndexMetadata) -> Result<(), Box<dyn Error>>;
+    /// Prepares a fresh fingerprints table to be filled for `metadata` and returns the IDs of songs
+    /// already reindexed by an interrupted run with the same metadata.
+    fn begin_reindex(&mut self, metadata: &models::IndexMetadata) -> Result<Vec<u32>, Box<dyn Error>>;
+    /// Stores a song's regenerated fingerprints in the fresh table and marks the song as done.
+    fn store_reindexed_fingerprints(&mut self, song_id: u32, fingerprints: &[(u32, models::Couple)]) -> Result<(), Box<dyn Error>>;
+    /// Atomically replaces the live fingerprints with the fresh table and records `metadata`.
+    fn finish_reindex(&mut self, metadata: &models::IndexMetadata) -> Result<(), Box<dyn Error>>;
+    /// Appends a detected play to the play log.
+    fn record_play(&mut self, play: &models::Play) -> Result<(), Box<dyn Error>>;
 }
 
-/// A simple Song struct with title, artist, and YouTubeID.
+/// Fails unless the stored fingerprints were produced by the pipeline `config` describes.
+/// An empty index is compatible with any pipeline. Fingerprints stored without any metadata
+/// come from a build that predates it, so they are refused rather than assumed to match.
+/// Never writes to the database; use it on paths that only query the index.
+pub fn check_index_compatible(db_client: &dyn DBClient, config: &FingerprintConfig) -> Result<(), Box<dyn Error>> {
+    match db_client.get_index_metadata()? {
+        Some(stored) => config.check_index_metadata(&stored),
+        None if !db_client.has_fingerprints()? => Ok(()),
+        None => Err("the fingerprint index has no pipeline metadata, so it was built by an older version \
+                     and may not match the current one; run `reindex` to re-fingerprint the library"
+            .into()),
+    }
+}
+
+/// Like `check_index_compatible`, but an empty index adopts the current pipeline's metadata.
+/// Use it before adding fingerprints to the index.
+pub fn ensure_index_compatible(db_client: &mut dyn DBClient, config: &FingerprintConfig) -> Result<(), Box<dyn Error>> {
+    check_index_compatible(db_client, config)?;
+    if db_client.get_index_metadata()?.is_none() {
+        db_client.set_index_metadata(&config.index_metadata())?;
+    }
+    Ok(())
+}
+
+/// A simple Song struct with its ID, title, artist, and YouTubeID.
 #[derive(Debug, Clone)]
 pub struct Song {
+    pub id: u32,
     pub title: String,
     pub artist: String,
     pub youtube_id: String,
@@ -73,3 +112,30 @@ fn new_mongo_client(db_uri: &str) -> Result<Box<dyn DBClient>, Box<dyn Error>> {
 fn new_sqlite_client(db_file: &str) -> Result<Box<dyn DBClient>, Box<dyn Error>> {
     Err(format!("SQLite client not implemented. db_file: {}", db_file).into())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::SQLiteClient;
+
+    #[test]
+    fn test_ensure_index_compatible() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = FingerprintConfig::default();
+
+        // Querying an empty index leaves it untouched; ingesting adopts the current pipeline.
+        let mut empty = SQLiteClient::new(&dir.path().join("empty.sqlite3").to_string_lossy()).unwrap();
+        check_index_compatible(&empty, &config).unwrap();
+        assert_eq!(empty.get_index_metadata().unwrap(), None);
+        ensure_index_compatible(&mut empty, &config).unwrap();
+        assert_eq!(empty.get_index_metadata().unwrap(), Some(config.index_metadata()));
+
+        // Fingerprints without metadata are not claimed for the current pipeline.
+        let mut legacy = SQLiteClient::new(&dir.path().join("legacy.sqlite3").to_string_lossy()).unwrap();
+        legacy.store_fingerprints(&[(42, models::Couple { anchor_time_ms: 0, song_id: 1 })]).unwrap();
+        let err = ensure_index_compatible(&mut legacy, &config).unwrap_err();
+        assert!(err.to_string().contains("reindex"));
+        assert!(check_index_compatible(&legacy, &config).is_err());
+        assert_eq!(legacy.get_index_metadata().unwrap(), None);
+    }
+}
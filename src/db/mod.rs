@@ -8,8 +8,6 @@ pub use sqlite::*;
 use std::error::Error;
 use std::env;
 
-use client::DBClient;
-
 pub async fn new_db_client() -> Result<Box<dyn DBClient>, Box<dyn Error>> {
     // Get database type from environment or use SQLite as default
     let db_type = env::var("DB_TYPE").unwrap_or_else(|_| "sqlite".to_string());
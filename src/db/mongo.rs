@@ -4,6 +4,7 @@ use crate::db::client::Song;
 use tokio::runtime::Runtime;
 use crate::db::client::DBClient;
 
+use futures::TryStreamExt;
 use mongodb::{
     bson::{doc, Bson, Document},
     options::{ClientOptions, IndexOptions},
@@ -45,34 +46,25 @@ impl MongoClient {
     fn songs_collection(&self) -> Collection<Document> {
         self.client.database("song-recognition").collection("songs")
     }
+
+    /// Returns the metadata collection.
+    fn metadata_collection(&self) -> Collection<Document> {
+        self.client.database("song-recognition").collection("metadata")
+    }
+
+    /// Returns the plays collection.
+    fn plays_collection(&self) -> Collection<Document> {
+        self.client.database("song-recognition").collection("plays")
+    }
 }
 
 impl MongoClient {
     /// Stores fingerprints into the "fingerprints" collection.
     pub async fn store_fingerprints(
         &self,
-        fingerprints: &std::collections::HashMap<u32, models::Couple>,
+        fingerprints: &[(u32, models::Couple)],
     ) -> Result<(), Box<dyn Error>> {
-        let collection = self.fingerprints_collection();
-
-        for (&address, couple) in fingerprints.iter() {
-            let filter = doc! { "_id": address as i64 };
-            let update = doc! {
-                "$push": {
-                    "couples": {
-                        "anchorTimeMs": couple.anchor_time_ms as i64,
-                        "songID": couple.song_id as i64,
-                    }
-                }
-            };
-            collection.update_one(filter, update)
-                .upsert(true)
-                .await
-                .map_err(|e| {
-                    format!("error upserting document: {}", e)
-                })?;
-        }
-        Ok(())
+        push_couples(&self.fingerprints_collection(), fingerprints).await
     }
 
     /// Retrieves fingerprint couples for the given addresses.
@@ -119,6 +111,12 @@ impl MongoClient {
         Ok(count as i32)
     }
 
+    /// Reports whether the "fingerprints" collection has any documents.
+    pub async fn has_fingerprints(&self) -> Result<bool, Box<dyn Error>> {
+        let first = self.fingerprints_collection().find_one(doc! {}).await?;
+        Ok(first.is_some())
+    }
+
     /// Registers a new song by inserting it into the "songs" collection.
     /// A unique song ID is generated using `utils::generate_unique_id()`.
     pub async fn register_song(
@@ -187,6 +185,7 @@ impl MongoClient {
                 return Err("invalid key format".into());
             }
             let song_instance = Song {
+                id: doc.get_i64("_id")? as u32,
                 title: parts[0].to_string(),
                 artist: parts[1].to_string(),
                 youtube_id: yt_id,
@@ -219,6 +218,127 @@ impl MongoClient {
         Ok(())
     }
 
+    /// Returns the algorithm version and config hash the fingerprints were built with, if recorded.
+    pub async fn get_index_metadata(&self) -> Result<Option<models::IndexMetadata>, Box<dyn Error>> {
+        let collection = self.metadata_collection();
+        let result = collection.find_one(doc! { "_id": "index" }).await?;
+        if let Some(doc) = result {
+            Ok(Some(models::IndexMetadata {
+                algorithm_version: doc.get_i64("algorithmVersion")? as u32,
+                config_hash: doc.get_str("configHash")?.to_string(),
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Records the algorithm version and config hash the fingerprints are built with.
+    pub async fn set_index_metadata(&self, metadata: &models::IndexMetadata) -> Result<(), Box<dyn Error>> {
+        let collection = self.metadata_collection();
+        let replacement = doc! {
+            "_id": "index",
+            "algorithmVersion": metadata.algorithm_version as i64,
+            "configHash": &metadata.config_hash,
+        };
+        collection.replace_one(doc! { "_id": "index" }, replacement)
+            .upsert(true)
+            .await
+            .map_err(|e| format!("error storing index metadata: {}", e))?;
+        Ok(())
+    }
+
+    /// Appends a detected play to the "plays" collection.
+    pub async fn record_play(&self, play: &models::Play) -> Result<(), Box<dyn Error>> {
+        let doc = doc! {
+            "songID": play.song_id as i64,
+            "title": &play.song_title,
+            "artist": &play.song_artist,
+            "firstSeen": &play.first_seen,
+            "lastSeen": &play.last_seen,
+            "detections": play.detections as i64,
+            "confidence": play.confidence,
+        };
+        self.plays_collection()
+            .insert_one(doc)
+            .await
+            .map_err(|e| format!("failed to record play: {}", e))?;
+        Ok(())
+    }
+
+    /// Creates the reindex collections, discarding leftovers from a run with different metadata,
+    /// and returns the IDs of songs that are already reindexed.
+    pub async fn begin_reindex(&self, metadata: &models::IndexMetadata) -> Result<Vec<u32>, Box<dyn Error>> {
+        let database = self.client.database("song-recognition");
+        let metadata_collection = self.metadata_collection();
+        let progress = database.collection::<Document>("reindex_progress");
+
+        let target = doc! {
+            "_id": "reindex",
+            "algorithmVersion": metadata.algorithm_version as i64,
+            "configHash": &metadata.config_hash,
+        };
+        let previous = metadata_collection.find_one(doc! { "_id": "reindex" }).await?;
+        if previous.as_ref() != Some(&target) {
+            database.collection::<Document>("fingerprints_reindex").drop().await?;
+            progress.drop().await?;
+            metadata_collection.replace_one(doc! { "_id": "reindex" }, target)
+                .upsert(true)
+                .await
+                .map_err(|e| format!("error storing reindex metadata: {}", e))?;
+        }
+
+        let docs: Vec<Document> = progress.find(doc! {}).await?.try_collect().await?;
+        let mut done = Vec::with_capacity(docs.len());
+        for doc in docs {
+            done.push(doc.get_i64("_id")? as u32);
+        }
+        Ok(done)
+    }
+
+    /// Stores a song's regenerated fingerprints and marks the song as reindexed.
+    /// Couples left by an interrupted attempt for the same song are pulled first, so a resumed
+    /// run does not store them twice.
+    pub async fn store_reindexed_fingerprints(
+        &self,
+        song_id: u32,
+        fingerprints: &[(u32, models::Couple)],
+    ) -> Result<(), Box<dyn Error>> {
+        let database = self.client.database("song-recognition");
+        let collection = database.collection::<Document>("fingerprints_reindex");
+        // The fingerprints are regenerated with the same config, so a previous attempt wrote
+        // to the same addresses.
+        let addresses: Vec<i64> = fingerprints.iter().map(|&(address, _)| address as i64).collect();
+        collection.update_many(
+                doc! { "_id": { "$in": addresses } },
+                doc! { "$pull": { "couples": { "songID": song_id as i64 } } },
+            )
+            .await
+            .map_err(|e| format!("error removing stale reindexed couples: {}", e))?;
+        push_couples(&collection, fingerprints).await?;
+        database.collection::<Document>("reindex_progress")
+            .replace_one(doc! { "_id": song_id as i64 }, doc! { "_id": song_id as i64 })
+            .upsert(true)
+            .await
+            .map_err(|e| format!("error storing reindex progress: {}", e))?;
+        Ok(())
+    }
+
+    /// Renames the reindexed collection over the live fingerprints and records the new metadata.
+    pub async fn finish_reindex(&self, metadata: &models::IndexMetadata) -> Result<(), Box<dyn Error>> {
+        self.client.database("admin")
+            .run_command(doc! {
+                "renameCollection": "song-recognition.fingerprints_reindex",
+                "to": "song-recognition.fingerprints",
+                "dropTarget": true,
+            })
+            .await
+            .map_err(|e| format!("error swapping in reindexed fingerprints: {}", e))?;
+        self.client.database("song-recognition").collection::<Document>("reindex_progress").drop().await?;
+        self.set_index_metadata(metadata).await?;
+        self.metadata_collection().delete_one(doc! { "_id": "reindex" }).await?;
+        Ok(())
+    }
+
     /// Drops the specified collection from the "song-recognition" database.
     pub async fn delete_collection(&self, collection_name: &str) -> Result<(), Box<dyn Error>> {
         let collection = self.client.database("song-recognition").collection::<Document>(collection_name);
@@ -238,7 +358,7 @@ impl DBClient for MongoClient {
         rt.block_on(<MongoClient>::register_song(self, song_title, song_artist, yt_id))
     }
     
-    fn store_fingerprints(&mut self, fingerprints: &std::collections::HashMap<u32, models::Couple>) -> Result<(), Box<dyn Error>> {
+    fn store_fingerprints(&mut self, fingerprints: &[(u32, models::Couple)]) -> Result<(), Box<dyn Error>> {
         let rt = Runtime::new()?;
         rt.block_on(<MongoClient>::store_fingerprints(self, fingerprints))
     }
@@ -252,6 +372,11 @@ impl DBClient for MongoClient {
         let rt = Runtime::new()?;
         rt.block_on(<MongoClient>::total_songs(self))
     }
+
+    fn has_fingerprints(&self) -> Result<bool, Box<dyn Error>> {
+        let rt = Runtime::new()?;
+        rt.block_on(<MongoClient>::has_fingerprints(self))
+    }
     
     fn get_song(&self, filter_key: &str, value: &str) -> Result<(Song, bool), Box<dyn Error>> {
         // Convert string value to BsonValue based on filter_key
@@ -292,11 +417,66 @@ impl DBClient for MongoClient {
         rt.block_on(<MongoClient>::delete_collection(self, collection_name))
     }
     
+    fn get_index_metadata(&self) -> Result<Option<models::IndexMetadata>, Box<dyn Error>> {
+        let rt = Runtime::new()?;
+        rt.block_on(<MongoClient>::get_index_metadata(self))
+    }
+
+    fn set_index_metadata(&mut self, metadata: &models::IndexMetadata) -> Result<(), Box<dyn Error>> {
+        let rt = Runtime::new()?;
+        rt.block_on(<MongoClient>::set_index_metadata(self, metadata))
+    }
+
+    fn begin_reindex(&mut self, metadata: &models::IndexMetadata) -> Result<Vec<u32>, Box<dyn Error>> {
+        let rt = Runtime::new()?;
+        rt.block_on(<MongoClient>::begin_reindex(self, metadata))
+    }
+
+    fn store_reindexed_fingerprints(&mut self, song_id: u32, fingerprints: &[(u32, models::Couple)]) -> Result<(), Box<dyn Error>> {
+        let rt = Runtime::new()?;
+        rt.block_on(<MongoClient>::store_reindexed_fingerprints(self, song_id, fingerprints))
+    }
+
+    fn finish_reindex(&mut self, metadata: &models::IndexMetadata) -> Result<(), Box<dyn Error>> {
+        let rt = Runtime::new()?;
+        rt.block_on(<MongoClient>::finish_reindex(self, metadata))
+    }
+
+    fn record_play(&mut self, play: &models::Play) -> Result<(), Box<dyn Error>> {
+        let rt = Runtime::new()?;
+        rt.block_on(<MongoClient>::record_play(self, play))
+    }
+
     fn close(&mut self) -> Result<(), Box<dyn Error>> {
         let rt = Runtime::new()?;
         rt.block_on(<MongoClient>::close(self))
     }
 }
+/// Appends each fingerprint's couple to the document for its address in `collection`.
+async fn push_couples(
+    collection: &Collection<Document>,
+    fingerprints: &[(u32, models::Couple)],
+) -> Result<(), Box<dyn Error>> {
+    for &(address, ref couple) in fingerprints.iter() {
+        let filter = doc! { "_id": address as i64 };
+        let update = doc! {
+            "$push": {
+                "couples": {
+                    "anchorTimeMs": couple.anchor_time_ms as i64,
+                    "songID": couple.song_id as i64,
+                }
+            }
+        };
+        collection.update_one(filter, update)
+            .upsert(true)
+            .await
+            .map_err(|e| {
+                format!("error upserting document: {}", e)
+            })?;
+    }
+    Ok(())
+}
+
 /// A helper enum to represent BSON value types for filtering.
 pub enum BsonValue {
     Int64(i64),
@@ -325,9 +505,39 @@ impl BsonValue {
 impl Default for Song {
     fn default() -> Self {
         Song {
+            id: 0,
             title: "".to_string(),
             artist: "".to_string(),
             youtube_id: "".to_string(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Needs a MongoDB server; set MONGO_TEST_URI to point at a disposable one.
+    #[tokio::test]
+    #[ignore]
+    async fn test_store_reindexed_fingerprints_twice_keeps_one_copy() {
+        let uri = std::env::var("MONGO_TEST_URI").unwrap_or_else(|_| "mongodb://localhost:27017".to_string());
+        let client = MongoClient::new(&uri).await.unwrap();
+        client.delete_collection("fingerprints_reindex").await.unwrap();
+
+        let couple = |anchor_time_ms| models::Couple { anchor_time_ms, song_id: 7 };
+        let fingerprints = vec![(1, couple(100)), (2, couple(200))];
+        // A resumed run stores the same song again.
+        client.store_reindexed_fingerprints(7, &fingerprints).await.unwrap();
+        client.store_reindexed_fingerprints(7, &fingerprints).await.unwrap();
+
+        let collection = client.client.database("song-recognition").collection::<Document>("fingerprints_reindex");
+        for address in [1i64, 2] {
+            let document = collection.find_one(doc! { "_id": address }).await.unwrap().unwrap();
+            assert_eq!(document.get_array("couples").unwrap().len(), 1);
+        }
+
+        client.delete_collection("fingerprints_reindex").await.unwrap();
+        client.delete_collection("reindex_progress").await.unwrap();
+    }
+}
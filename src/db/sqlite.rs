@@ -31,14 +31,14 @@ impl SQLiteClient {
     /// Stores fingerprints into the fingerprints table.
     pub fn store_fingerprints(
         &mut self,
-        fingerprints: &std::collections::HashMap<u32, models::Couple>,
+        fingerprints: &[(u32, models::Couple)],
     ) -> Result<(), Box<dyn Error>> {
         let tx = self.db.transaction()?;
         {
             let mut stmt = tx.prepare(
                 "INSERT OR REPLACE INTO fingerprints (address, anchorTimeMs, songID) VALUES (?, ?, ?)",
             )?;
-            for (&address, couple) in fingerprints.iter() {
+            for &(address, ref couple) in fingerprints.iter() {
                 stmt.execute(params![address as i64, couple.anchor_time_ms as i64, couple.song_id as i64])?;
             }
         }
@@ -80,6 +80,12 @@ impl SQLiteClient {
         Ok(count)
     }
 
+    /// Reports whether the fingerprints table has any rows.
+    pub fn has_fingerprints(&self) -> Result<bool, Box<dyn Error>> {
+        let exists: bool = self.db.query_row("SELECT EXISTS (SELECT 1 FROM fingerprints)", [], |row| row.get(0))?;
+        Ok(exists)
+    }
+
     /// Registers a new song in the songs table.
     pub fn register_song(
         &mut self,
@@ -129,13 +135,15 @@ impl SQLiteClient {
             return Err("invalid filter key".into());
         }
 
-        let query = format!("SELECT title, artist, ytID FROM songs WHERE {} = ?", filter_key);
+        let query = format!("SELECT id, title, artist, ytID FROM songs WHERE {} = ?", filter_key);
         let mut stmt = self.db.prepare(&query)?;
         let song_opt = stmt.query_row(&[value], |row| {
+            let id: i64 = row.get(0)?;
             Ok(Song {
-                title: row.get(0)?,
-                artist: row.get(1)?,
-                youtube_id: row.get(2)?,
+                id: id as u32,
+                title: row.get(1)?,
+                artist: row.get(2)?,
+                youtube_id: row.get(3)?,
             })
         }).optional()?;
 
@@ -165,6 +173,129 @@ impl SQLiteClient {
         Ok(())
     }
 
+    /// Returns the algorithm version and config hash the fingerprints were built with, if recorded.
+    pub fn get_index_metadata(&self) -> Result<Option<models::IndexMetadata>, Box<dyn Error>> {
+        let mut stmt = self.db.prepare("SELECT value FROM metadata WHERE key = ?")?;
+        let version: Option<String> = stmt.query_row(params!["algorithm_version"], |row| row.get(0)).optional()?;
+        let config_hash: Option<String> = stmt.query_row(params!["config_hash"], |row| row.get(0)).optional()?;
+
+        match (version, config_hash) {
+            (Some(version), Some(config_hash)) => {
+                let algorithm_version = version
+                    .parse()
+                    .map_err(|e| format!("invalid algorithm version in metadata: {}", e))?;
+                Ok(Some(models::IndexMetadata { algorithm_version, config_hash }))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Records the algorithm version and config hash the fingerprints are built with.
+    pub fn set_index_metadata(&mut self, metadata: &models::IndexMetadata) -> Result<(), Box<dyn Error>> {
+        let tx = self.db.transaction()?;
+        {
+            let mut stmt = tx.prepare("INSERT OR REPLACE INTO metadata (key, value) VALUES (?, ?)")?;
+            stmt.execute(params!["algorithm_version", metadata.algorithm_version.to_string()])?;
+            stmt.execute(params!["config_hash", metadata.config_hash])?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Creates the reindex tables, discarding leftovers from a run with different metadata,
+    /// and returns the IDs of songs that are already reindexed.
+    pub fn begin_reindex(&mut self, metadata: &models::IndexMetadata) -> Result<Vec<u32>, Box<dyn Error>> {
+        let target = format!("{}:{}", metadata.algorithm_version, metadata.config_hash);
+        let previous: Option<String> = self.db
+            .query_row("SELECT value FROM metadata WHERE key = 'reindex_target'", [], |row| row.get(0))
+            .optional()?;
+
+        let tx = self.db.transaction()?;
+        if previous.as_deref() != Some(target.as_str()) {
+            tx.execute("DROP TABLE IF EXISTS fingerprints_reindex", [])?;
+            tx.execute("DROP TABLE IF EXISTS reindex_progress", [])?;
+        }
+        tx.execute(
+            "CREATE TABLE IF NOT EXISTS fingerprints_reindex (
+                address INTEGER NOT NULL,
+                anchorTimeMs INTEGER NOT NULL,
+                songID INTEGER NOT NULL,
+                PRIMARY KEY (address, anchorTimeMs, songID)
+            )",
+            [],
+        )?;
+        tx.execute("CREATE TABLE IF NOT EXISTS reindex_progress (songID INTEGER PRIMARY KEY)", [])?;
+        tx.execute(
+            "INSERT OR REPLACE INTO metadata (key, value) VALUES ('reindex_target', ?)",
+            params![target],
+        )?;
+        tx.commit()?;
+
+        let mut stmt = self.db.prepare("SELECT songID FROM reindex_progress")?;
+        let done = stmt
+            .query_map([], |row| row.get::<_, i64>(0))?
+            .map(|id| id.map(|id| id as u32))
+            .collect::<Result<Vec<u32>, _>>()?;
+        Ok(done)
+    }
+
+    /// Stores a song's regenerated fingerprints and marks the song as reindexed in one transaction.
+    pub fn store_reindexed_fingerprints(
+        &mut self,
+        song_id: u32,
+        fingerprints: &[(u32, models::Couple)],
+    ) -> Result<(), Box<dyn Error>> {
+        let tx = self.db.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR REPLACE INTO fingerprints_reindex (address, anchorTimeMs, songID) VALUES (?, ?, ?)",
+            )?;
+            for &(address, ref couple) in fingerprints.iter() {
+                stmt.execute(params![address as i64, couple.anchor_time_ms as i64, couple.song_id as i64])?;
+            }
+        }
+        tx.execute("INSERT OR REPLACE INTO reindex_progress (songID) VALUES (?)", params![song_id as i64])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Swaps the reindexed table in place of the live fingerprints and records the new metadata.
+    pub fn finish_reindex(&mut self, metadata: &models::IndexMetadata) -> Result<(), Box<dyn Error>> {
+        let tx = self.db.transaction()?;
+        tx.execute("DROP TABLE IF EXISTS fingerprints", [])?;
+        tx.execute("ALTER TABLE fingerprints_reindex RENAME TO fingerprints", [])?;
+        tx.execute("DROP TABLE IF EXISTS reindex_progress", [])?;
+        tx.execute("DELETE FROM metadata WHERE key = 'reindex_target'", [])?;
+        tx.execute(
+            "INSERT OR REPLACE INTO metadata (key, value) VALUES ('algorithm_version', ?)",
+            params![metadata.algorithm_version.to_string()],
+        )?;
+        tx.execute(
+            "INSERT OR REPLACE INTO metadata (key, value) VALUES ('config_hash', ?)",
+            params![metadata.config_hash],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Appends a detected play to the plays table.
+    pub fn record_play(&self, play: &models::Play) -> Result<(), Box<dyn Error>> {
+        self.db.execute(
+            "INSERT INTO plays (songID, title, artist, firstSeen, lastSeen, detections, confidence) VALUES (?, ?, ?, ?, ?, ?, ?)",
+            params![
+                play.song_id as i64,
+                play.song_title,
+                play.song_artist,
+                play.first_seen,
+                play.last_seen,
+                play.detections as i64,
+                play.confidence
+            ],
+        )
+        .map_err(|e| format!("failed to record play: {}", e))?;
+        Ok(())
+    }
+
     /// Drops a table (collection) from the database.
     pub fn delete_collection(&self, collection_name: &str) -> Result<(), Box<dyn Error>> {
         let query = format!("DROP TABLE IF EXISTS {}", collection_name);
@@ -178,7 +309,7 @@ impl DBClient for SQLiteClient {
         self.register_song(song_title, song_artist, yt_id)
     }
 
-    fn store_fingerprints(&mut self, fingerprints: &std::collections::HashMap<u32, models::Couple>) -> Result<(), Box<dyn Error>> {
+    fn store_fingerprints(&mut self, fingerprints: &[(u32, models::Couple)]) -> Result<(), Box<dyn Error>> {
         self.store_fingerprints(fingerprints)
     }
 
@@ -190,6 +321,10 @@ impl DBClient for SQLiteClient {
         self.total_songs()
     }
 
+    fn has_fingerprints(&self) -> Result<bool, Box<dyn Error>> {
+        self.has_fingerprints()
+    }
+
     fn get_song_by_id(&self, song_id: u32) -> Result<(Song, bool), Box<dyn Error>> {
         self.get_song_by_id(song_id)
     }
@@ -209,6 +344,30 @@ impl DBClient for SQLiteClient {
     fn delete_collection(&mut self, collection_name: &str) -> Result<(), Box<dyn Error>> {
         SQLiteClient::delete_collection(self, collection_name)
     }
+    fn get_index_metadata(&self) -> Result<Option<models::IndexMetadata>, Box<dyn Error>> {
+        self.get_index_metadata()
+    }
+
+    fn set_index_metadata(&mut self, metadata: &models::IndexMetadata) -> Result<(), Box<dyn Error>> {
+        self.set_index_metadata(metadata)
+    }
+
+    fn begin_reindex(&mut self, metadata: &models::IndexMetadata) -> Result<Vec<u32>, Box<dyn Error>> {
+        self.begin_reindex(metadata)
+    }
+
+    fn store_reindexed_fingerprints(&mut self, song_id: u32, fingerprints: &[(u32, models::Couple)]) -> Result<(), Box<dyn Error>> {
+        self.store_reindexed_fingerprints(song_id, fingerprints)
+    }
+
+    fn finish_reindex(&mut self, metadata: &models::IndexMetadata) -> Result<(), Box<dyn Error>> {
+        self.finish_reindex(metadata)
+    }
+
+    fn record_play(&mut self, play: &models::Play) -> Result<(), Box<dyn Error>> {
+        SQLiteClient::record_play(self, play)
+    }
+
     fn close(&mut self) -> Result<(), Box<dyn Error>> {
         // We can't directly call self.close() because it consumes self
         // Instead, we'll handle it differently for the trait implementation
@@ -250,10 +409,66 @@ fn create_tables(db: &Connection) -> Result<(), Box<dyn Error>> {
         );
     "#;
 
+    let create_metadata_table = r#"
+        CREATE TABLE IF NOT EXISTS metadata (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );
+    "#;
+
+    let create_plays_table = r#"
+        CREATE TABLE IF NOT EXISTS plays (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            songID INTEGER NOT NULL,
+            title TEXT NOT NULL,
+            artist TEXT NOT NULL,
+            firstSeen TEXT NOT NULL,
+            lastSeen TEXT NOT NULL,
+            detections INTEGER NOT NULL,
+            confidence REAL NOT NULL
+        );
+    "#;
+
     db.execute(create_songs_table, [])
         .map_err(|e| format!("error creating songs table: {}", e))?;
     db.execute(create_fingerprints_table, [])
         .map_err(|e| format!("error creating fingerprints table: {}", e))?;
+    db.execute(create_metadata_table, [])
+        .map_err(|e| format!("error creating metadata table: {}", e))?;
+    db.execute(create_plays_table, [])
+        .map_err(|e| format!("error creating plays table: {}", e))?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reindex_resumes_restarts_and_swaps_in() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut client = SQLiteClient::new(&dir.path().join("reindex.sqlite3").to_string_lossy()).unwrap();
+        let couple = |anchor_time_ms, song_id| models::Couple { anchor_time_ms, song_id };
+        client.store_fingerprints(&[(1, couple(0, 7))]).unwrap();
+
+        let target = models::IndexMetadata { algorithm_version: 2, config_hash: "new".to_string() };
+        assert!(client.begin_reindex(&target).unwrap().is_empty());
+        client.store_reindexed_fingerprints(7, &[(2, couple(500, 7))]).unwrap();
+
+        // Resuming with the same metadata keeps the finished song.
+        assert_eq!(client.begin_reindex(&target).unwrap(), vec![7]);
+
+        // A different target discards the progress.
+        let other = models::IndexMetadata { algorithm_version: 2, config_hash: "other".to_string() };
+        assert!(client.begin_reindex(&other).unwrap().is_empty());
+        client.store_reindexed_fingerprints(7, &[(3, couple(250, 7))]).unwrap();
+
+        client.finish_reindex(&other).unwrap();
+        let couples = client.get_couples(&[1, 2, 3]).unwrap();
+        // Only the last run's fingerprints survive; the old table and the discarded run are gone.
+        assert!(couples[&1].is_empty() && couples[&2].is_empty());
+        assert_eq!(couples[&3], vec![couple(250, 7)]);
+        assert_eq!(client.get_index_metadata().unwrap(), Some(other));
+    }
+}
@@ -14,7 +14,7 @@ use crate::db;
 use crate::shazam;
 use crate::utils;
 use crate::wav;
-use crate::models::Track; // Assume Track is defined in your models module
+use crate::models::{Couple, Track}; // Assume Track is defined in your models module
 
 const DELETE_SONG_FILE: bool = false;
 
@@ -119,27 +119,44 @@ fn dl_track(tracks: &[Track], path: &str) -> Result<i32, Box<dyn Error>> {
                 return;
             }
 
-            if let Err(e) = process_and_save_song(file_path.to_str().unwrap(), &track_copy.title, &track_copy.artist, &yt_id) {
-                let log_message = format!("Failed to process song ('{}' by '{}')", track_copy.title, track_copy.artist);
-                slog::error!(logger, "{} error :{}", log_message,e);
-                // logger.error_context(&log_message, &e);
-                return;
-            }
+            // The WAV is converted in a scratch directory and only moved next to the
+            // download once it has been fingerprinted and tagged.
+            let converted = match wav::convert_to_wav(file_path.to_str().unwrap()) {
+                Ok(converted) => converted,
+                Err(e) => {
+                    let log_message = format!("'{}' by '{}' could not be converted to WAV", track_copy.title, track_copy.artist);
+                    slog::error!(logger, "{} error :{}", log_message,e);
+                    return;
+                }
+            };
+
+            let song_id = match process_and_save_song(converted.path(), &track_copy.title, &track_copy.artist, &yt_id) {
+                Ok(song_id) => song_id,
+                Err(e) => {
+                    let log_message = format!("Failed to process song ('{}' by '{}')", track_copy.title, track_copy.artist);
+                    slog::error!(logger, "{} error :{}", log_message,e);
+                    // logger.error_context(&log_message, &e);
+                    return;
+                }
+            };
 
             // Delete the downloaded m4a file.
             let m4a_path = Path::new(&path).join(format!("{}.m4a", file_name));
             let _ = utils::delete_file(m4a_path.to_str().unwrap());
 
-            let wav_file_path = Path::new(&path).join(format!("{}.wav", file_name));
-            if let Err(e) = add_tags(wav_file_path.to_str().unwrap(), &track_copy) {
+            if let Err(e) = add_tags(converted.path(), &track_copy, song_id, &yt_id) {
                 let log_message = format!("Error adding tags: {}.wav", file_name);
                 slog::error!(logger, "{} error :{}", log_message,e);
                 // logger.error_context(&log_message, &e);
                 return;
             }
 
-            if DELETE_SONG_FILE {
-                let _ = utils::delete_file(wav_file_path.to_str().unwrap());
+            if !DELETE_SONG_FILE {
+                let wav_path = Path::new(&path).join(format!("{}.wav", file_name));
+                if let Err(e) = converted.persist(&wav_path) {
+                    slog::error!(logger, "Error saving {}: {}", wav_path.display(), e);
+                    return;
+                }
             }
 
             println!("'{}' by '{}' was downloaded", track_copy.title, track_copy.artist);
@@ -183,56 +200,44 @@ fn download_yt_audio(id: &str, path: &str, file_path: &str) -> Result<(), Box<dy
     Ok(())
 }
 
-/// Executes an FFmpeg command to add metadata tags to the given file.
-/// It creates a temporary file and renames it to the original file.
-fn add_tags(file: &str, track: &Track) -> Result<(), Box<dyn Error>> {
-    // Create temporary file name by inserting "2" before the ".wav" extension.
-    let temp_file = if let Some(index) = file.rfind(".wav") {
-        format!("{}2.wav", &file[..index])
-    } else {
-        return Err("Invalid file name".into());
+/// Embeds the track's tags, its song ID and its YouTube ID into the downloaded file, so that it
+/// can be re-ingested without the database.
+pub fn add_tags(file: &str, track: &Track, song_id: u32, yt_id: &str) -> Result<(), Box<dyn Error>> {
+    let tags = wav::AudioTags {
+        title: Some(track.title.clone()),
+        artists: vec![track.artist.clone()],
+        album: Some(track.album.clone()),
+        album_artist: Some(track.artist.clone()),
+        song_id: Some(song_id),
+        youtube_id: Some(yt_id.to_string()),
+        ..wav::AudioTags::default()
     };
-
-    let output = Command::new("ffmpeg")
-        .args(&[
-            "-i", file,
-            "-c", "copy",
-            "-metadata", &format!("album_artist={}", track.artist),
-            "-metadata", &format!("title={}", track.title),
-            "-metadata", &format!("artist={}", track.artist),
-            "-metadata", &format!("album={}", track.album),
-            &temp_file,
-        ])
-        .output()?;
-
-    if !output.status.success() {
-        return Err(format!(
-            "failed to add tags: {}",
-            String::from_utf8_lossy(&output.stdout)
-        )
-        .into());
-    }
-
-    fs::rename(&temp_file, file)?;
-    Ok(())
+    wav::write_tags(file, &tags)
 }
 
 /// Processes and saves a song by converting it to WAV, creating its spectrogram,
 /// extracting peaks and fingerprints, and then storing the fingerprints in the database.
-pub fn process_and_save_song(song_file_path: &str, song_title: &str, song_artist: &str, yt_id: &str) -> Result<(), Box<dyn Error>> {
+/// Returns the ID the song was registered under.
+pub fn process_and_save_song(song_file_path: &str, song_title: &str, song_artist: &str, yt_id: &str) -> Result<u32, Box<dyn Error>> {
     // Create a runtime to run async code in sync context
     let rt = tokio::runtime::Runtime::new()?;
     
     // Use the runtime to block on the async db client creation
     let mut db_client = rt.block_on(db::new_db_client())?;
     
-    let wav_file_path = wav::convert_to_wav(song_file_path, 1)?;
-    let wav_info = wav::read_wav_info(&wav_file_path)?;
-    let samples = wav::wav_bytes_to_samples(&wav_info.data)?;
-    let spectro = shazam::spectrogram(&samples, wav_info.sample_rate)?;
+    let config = shazam::FingerprintConfig::from_env()?;
+
+    // Refuse to mix fingerprints from different pipelines in one index.
+    db::ensure_index_compatible(db_client.as_mut(), &config)?;
+
     let song_id = db_client.register_song(song_title, song_artist, yt_id)?;
-    let peaks = shazam::extract_peaks(&spectro, wav_info.duration);
-    let fingerprints = shazam::fingerprint(&peaks, song_id);
+    let fingerprints = match fingerprint_song_file(song_file_path, song_id, &config) {
+        Ok(fingerprints) => fingerprints,
+        Err(e) => {
+            let _ = db_client.delete_song_by_id(song_id);
+            return Err(e);
+        }
+    };
 
     db_client.store_fingerprints(&fingerprints).map_err(|e| {
         let _ = db_client.delete_song_by_id(song_id);
@@ -240,7 +245,17 @@ pub fn process_and_save_song(song_file_path: &str, song_title: &str, song_artist
     })?;
 
     println!("Fingerprint for {} by {} saved in DB successfully", song_title, song_artist);
-    Ok(())
+    Ok(song_id)
+}
+
+/// Decodes the song and runs it through the fingerprinting pipeline, attributing the
+/// fingerprints to `song_id`.
+pub fn fingerprint_song_file(
+    song_file_path: &str,
+    song_id: u32,
+    config: &shazam::FingerprintConfig,
+) -> Result<Vec<(u32, Couple)>, Box<dyn Error>> {
+    shazam::fingerprint_audio_file(song_file_path, song_id, config)
 }
 
 /// Retrieves a YouTube ID for the given track.
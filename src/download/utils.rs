@@ -1,13 +1,14 @@
 use std::env;
 use std::fs;
 use std::io::{self, Read};
-use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::path::PathBuf;
 use std::time::Duration;
 
 use urlencoding::encode;
 
 use crate::db; // Assumes your db module is available.
+use crate::utils;
+use crate::wav;
 // use crate::models::Song; // Assumes your models module defines Song-related types.
 
 /// Encodes a parameter for URL usage.
@@ -68,62 +69,25 @@ pub fn correct_filename(title: &str, artist: &str) -> (String, String) {
     }
 }
 
-/// Converts a stereo audio file to mono by using ffprobe to check the number of channels
-/// and, if necessary, invoking ffmpeg to perform the conversion. Returns the audio bytes.
+/// Converts a multi-channel audio file to mono. Files that are already mono are returned as
+/// they are; others are decoded, averaged down to one channel and returned as the bytes of a
+/// 16-bit WAV file at the original sample rate.
 pub fn convert_stereo_to_mono(stereo_file_path: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    let stereo_path = Path::new(stereo_file_path);
-    let file_ext = stereo_path.extension().and_then(|s| s.to_str()).unwrap_or("");
-    let mut mono_file_path = stereo_path.with_file_name(
-        format!("{}{}_mono.{}", 
-            stereo_path.file_stem().and_then(|s| s.to_str()).unwrap_or(""),
-            "",
-            file_ext
-        )
-    );
-    // Ensure temporary file removal at the end.
-    let cleanup = || {
-        let _ = fs::remove_file(&mono_file_path);
-    };
+    let decoder = wav::decoder_for(stereo_file_path)?;
+    let info = decoder
+        .probe(stereo_file_path)
+        .map_err(|e| format!("error getting number of channels: {}", e))?;
 
-    // Check number of channels using ffprobe.
-    let ffprobe_output = Command::new("ffprobe")
-        .args(&[
-            "-v", "error",
-            "-show_entries", "stream=channels",
-            "-of", "default=noprint_wrappers=1:nokey=1",
-            stereo_file_path,
-        ])
-        .output()?;
-    if !ffprobe_output.status.success() {
-        cleanup();
-        return Err(format!(
-            "error getting number of channels: {}",
-            String::from_utf8_lossy(&ffprobe_output.stdout)
-        ).into());
+    if info.channels == 1 {
+        return Ok(fs::read(stereo_file_path).map_err(|e| format!("error reading stereo file: {}", e))?);
     }
-    let channels = String::from_utf8_lossy(&ffprobe_output.stdout).trim().to_string();
-
-    // Read the original audio bytes.
-    let mut audio_bytes = fs::read(stereo_file_path)
-        .map_err(|e| format!("error reading stereo file: {}", e))?;
 
-    if channels != "1" {
-        // Convert stereo to mono using ffmpeg.
-        let ffmpeg_status = Command::new("ffmpeg")
-            .args(&[
-                "-i", stereo_file_path,
-                "-af", "pan=mono|c0=c0",
-                mono_file_path.to_str().unwrap(),
-            ])
-            .status()?;
-        if !ffmpeg_status.success() {
-            cleanup();
-            return Err(format!("error converting stereo to mono: {}", ffmpeg_status).into());
-        }
-        // Read the mono file.
-        audio_bytes = fs::read(&mono_file_path)
-            .map_err(|e| format!("error reading mono file: {}", e))?;
-    }
-    cleanup();
+    let samples = decoder
+        .decode(stereo_file_path, info.sample_rate)
+        .map_err(|e| format!("error converting stereo to mono: {}", e))?;
+    let data = utils::floats_to_bytes(&samples, 16)?;
+    let mut audio_bytes = Vec::with_capacity(44 + data.len());
+    wav::write_wav_header(&mut audio_bytes, &data, info.sample_rate, 1, 16)?;
+    audio_bytes.extend_from_slice(&data);
     Ok(audio_bytes)
 }
@@ -22,7 +22,7 @@ pub mod api;
 
 fn main() {
     // Create "tmp" folder
-    if let Err(e) = utils::create_folder("tmp") {
+    if let Err(e) = utils::create_folder(utils::TMP_DIR) {
         let logger = utils::get_logger();
         let wrapped_err = format!("Error: {}", e);
         // In a real project you might use a proper context object; here we simply log.
@@ -41,22 +41,275 @@ fn main() {
 
     let args: Vec<String> = env::args().collect();
     if args.len() < 2 {
-        println!("Expected 'find', 'download', 'erase', 'save', 'serve', or 'api-server' subcommands");
+        println!("Expected 'find', 'segment', 'monitor', 'clip', 'download', 'erase', 'reindex', 'save', 'serve', or 'api-server' subcommands");
         process::exit(1);
     }
 
     match args[1].as_str() {
         "find" => {
-            if args.len() < 3 {
-                println!("Usage: main.rs find <path_to_wav_file>");
-                process::exit(1);
-            }
-            let file_path = &args[2];
-            // command_handlers::find(file_path);
+            let find_cmd = Command::new("find")
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .default_value("s16le")
+                        .value_parser(["s16le", "f32le"])
+                        .help("Sample format of headerless PCM read from stdin"),
+                )
+                .arg(
+                    Arg::new("rate")
+                        .long("rate")
+                        .default_value("44100")
+                        .value_parser(clap::value_parser!(u32))
+                        .help("Sample rate of headerless PCM read from stdin"),
+                )
+                .arg(
+                    Arg::new("channels")
+                        .long("channels")
+                        .default_value("1")
+                        .value_parser(clap::value_parser!(u16))
+                        .help("Number of interleaved channels in headerless PCM read from stdin"),
+                )
+                .arg(
+                    Arg::new("path")
+                        .required(true)
+                        .help("Audio file to identify, or - to read a WAV or raw PCM stream from stdin"),
+                );
+            let matches = find_cmd.get_matches_from(&args[1..]);
+            let raw_format = wav::RawPcmFormat {
+                encoding: wav::PcmEncoding::parse(matches.get_one::<String>("format").unwrap()).unwrap(),
+                sample_rate: *matches.get_one::<u32>("rate").unwrap(),
+                channels: *matches.get_one::<u16>("channels").unwrap(),
+            };
+            let file_path = matches.get_one::<String>("path").unwrap();
 
             // Create a runtime and block on the async function
             let rt = tokio::runtime::Runtime::new().unwrap();
-            rt.block_on(command_handlers::find(file_path));
+            rt.block_on(command_handlers::find(file_path, &raw_format));
+        }
+        "segment" => {
+            let segment_cmd = Command::new("segment")
+                .arg(
+                    Arg::new("window")
+                        .long("window")
+                        .default_value("10")
+                        .value_parser(clap::value_parser!(u32))
+                        .help("Length of each query window, in seconds"),
+                )
+                .arg(
+                    Arg::new("step")
+                        .long("step")
+                        .default_value("5")
+                        .value_parser(clap::value_parser!(u32))
+                        .help("Distance between window starts, in seconds"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .default_value("json")
+                        .value_parser(["json", "csv", "cue"])
+                        .help("Timeline format"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .help("Write the timeline to this file instead of stdout"),
+                )
+                .arg(
+                    Arg::new("path")
+                        .required(true)
+                        .help("Path to the wav recording"),
+                );
+            let matches = segment_cmd.get_matches_from(&args[1..]);
+            let options = shazam::SegmentOptions {
+                window_ms: matches.get_one::<u32>("window").unwrap().saturating_mul(1000),
+                step_ms: matches.get_one::<u32>("step").unwrap().saturating_mul(1000),
+                ..shazam::SegmentOptions::default()
+            };
+            let format = matches.get_one::<String>("format").unwrap();
+            let output = matches.get_one::<String>("output").map(String::as_str);
+            let file_path = matches.get_one::<String>("path").unwrap();
+
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            rt.block_on(command_handlers::segment(file_path, &options, format, output));
+        }
+        "monitor" => {
+            let monitor_cmd = Command::new("monitor")
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .default_value("s16le")
+                        .value_parser(["s16le", "f32le"])
+                        .help("Sample format of the PCM input"),
+                )
+                .arg(
+                    Arg::new("rate")
+                        .long("rate")
+                        .default_value("44100")
+                        .value_parser(clap::value_parser!(u32))
+                        .help("Sample rate of the PCM input"),
+                )
+                .arg(
+                    Arg::new("channels")
+                        .long("channels")
+                        .default_value("1")
+                        .value_parser(clap::value_parser!(u16))
+                        .help("Number of interleaved channels in the input"),
+                )
+                .arg(
+                    Arg::new("window")
+                        .long("window")
+                        .default_value("10")
+                        .value_parser(clap::value_parser!(u32))
+                        .help("Length of each recognition window, in seconds"),
+                )
+                .arg(
+                    Arg::new("step")
+                        .long("step")
+                        .default_value("5")
+                        .value_parser(clap::value_parser!(u32))
+                        .help("Distance between window starts, in seconds"),
+                )
+                .arg(
+                    Arg::new("log")
+                        .long("log")
+                        .default_value("plays.ndjson")
+                        .help("NDJSON file the plays are appended to"),
+                )
+                .arg(
+                    Arg::new("source")
+                        .required(true)
+                        .help("File or FIFO to read PCM from, or - for stdin"),
+                );
+            let matches = monitor_cmd.get_matches_from(&args[1..]);
+            let options = shazam::MonitorOptions {
+                format: wav::RawPcmFormat {
+                    encoding: wav::PcmEncoding::parse(matches.get_one::<String>("format").unwrap()).unwrap(),
+                    sample_rate: *matches.get_one::<u32>("rate").unwrap(),
+                    channels: *matches.get_one::<u16>("channels").unwrap(),
+                },
+                window_ms: matches.get_one::<u32>("window").unwrap().saturating_mul(1000),
+                step_ms: matches.get_one::<u32>("step").unwrap().saturating_mul(1000),
+                ..shazam::MonitorOptions::default()
+            };
+            let log_path = matches.get_one::<String>("log").unwrap();
+            let source = matches.get_one::<String>("source").unwrap();
+
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            rt.block_on(command_handlers::monitor(source, &options, log_path));
+        }
+        "clip" => {
+            let clip_cmd = Command::new("clip")
+                .arg(
+                    Arg::new("count")
+                        .long("count")
+                        .value_parser(clap::value_parser!(usize))
+                        .help("Number of clips to cut from random songs at random offsets [default: 10 without --segment]"),
+                )
+                .arg(
+                    Arg::new("segment")
+                        .long("segment")
+                        .action(clap::ArgAction::Append)
+                        .value_parser(parse_clip_segment)
+                        .help("Cut a clip from FILE starting at SECONDS, as FILE:SECONDS (repeatable)"),
+                )
+                .arg(
+                    Arg::new("length")
+                        .long("length")
+                        .default_value("10")
+                        .value_parser(parse_clip_length)
+                        .help("Length of each clip, in seconds"),
+                )
+                .arg(
+                    Arg::new("noise")
+                        .long("noise")
+                        .value_parser(["white", "pink"])
+                        .help("Add noise of this colour"),
+                )
+                .arg(
+                    Arg::new("snr")
+                        .long("snr")
+                        .default_value("10")
+                        .value_parser(clap::value_parser!(f64))
+                        .help("Signal-to-noise ratio of the added noise, in dB"),
+                )
+                .arg(
+                    Arg::new("gain")
+                        .long("gain")
+                        .allow_negative_numbers(true)
+                        .value_parser(clap::value_parser!(f64))
+                        .help("Gain to apply, in dB"),
+                )
+                .arg(
+                    Arg::new("clip-level")
+                        .long("clip-level")
+                        .value_parser(clap::value_parser!(f64))
+                        .help("Hard-clip samples above this level, in (0, 1]"),
+                )
+                .arg(
+                    Arg::new("bitrate")
+                        .long("bitrate")
+                        .value_parser(clap::value_parser!(u32))
+                        .help("Re-encode the clips as MP3 at this bitrate, in kbit/s (needs ffmpeg)"),
+                )
+                .arg(
+                    Arg::new("speed")
+                        .long("speed")
+                        .value_parser(clap::value_parser!(f64))
+                        .help("Playback speed factor, changing tempo and pitch"),
+                )
+                .arg(
+                    Arg::new("reverb")
+                        .long("reverb")
+                        .value_parser(clap::value_parser!(f64))
+                        .help("Add room reverb with this RT60, in seconds"),
+                )
+                .arg(
+                    Arg::new("seed")
+                        .long("seed")
+                        .value_parser(clap::value_parser!(u64))
+                        .help("Random seed, for a reproducible set of clips"),
+                )
+                .arg(
+                    Arg::new("songs-dir")
+                        .long("songs-dir")
+                        .default_value(SONGS_DIR)
+                        .help("Directory random songs are picked from"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .default_value("clips")
+                        .help("Directory the clips and manifest.json are written to"),
+                );
+            let matches = clip_cmd.get_matches_from(&args[1..]);
+            let segments: Vec<(String, f64)> = matches
+                .get_many::<(String, f64)>("segment")
+                .map(|segments| segments.cloned().collect())
+                .unwrap_or_default();
+            let default_count = if segments.is_empty() { 10 } else { 0 };
+            let degrade = wav::DegradeOptions {
+                speed: matches.get_one::<f64>("speed").copied(),
+                reverb_rt60: matches.get_one::<f64>("reverb").copied(),
+                gain_db: matches.get_one::<f64>("gain").copied(),
+                noise: matches.get_one::<String>("noise").map(|color| {
+                    (wav::NoiseColor::parse(color).unwrap(), *matches.get_one::<f64>("snr").unwrap())
+                }),
+                clip_level: matches.get_one::<f64>("clip-level").copied(),
+                bitrate_kbps: matches.get_one::<u32>("bitrate").copied(),
+            };
+            let options = wav::ClipOptions {
+                count: matches.get_one::<usize>("count").copied().unwrap_or(default_count),
+                length_ms: *matches.get_one::<u32>("length").unwrap(),
+                seed: matches.get_one::<u64>("seed").copied(),
+                degrade,
+            };
+            let songs_dir = matches.get_one::<String>("songs-dir").unwrap();
+            let output_dir = matches.get_one::<String>("output").unwrap();
+
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            rt.block_on(command_handlers::clip(songs_dir, &segments, &options, output_dir));
         }
         "download" => {
             if args.len() < 3 {
@@ -94,6 +347,10 @@ fn main() {
             let rt = tokio::runtime::Runtime::new().unwrap();
             rt.block_on(command_handlers::erase(SONGS_DIR));
         }
+        "reindex" => {
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            rt.block_on(command_handlers::reindex(SONGS_DIR));
+        }
         "save" => {
             let save_cmd = Command::new("save")
                 .arg(
@@ -128,12 +385,33 @@ fn main() {
             }
         }
         _ => {
-            println!("Expected 'find', 'download', 'erase', 'save', 'serve', or 'api-server' subcommands");
+            println!("Expected 'find', 'segment', 'monitor', 'clip', 'download', 'erase', 'reindex', 'save', 'serve', or 'api-server' subcommands");
             process::exit(1);
         }
     }
 }
 
+/// Parses a `--segment` value of the form FILE:SECONDS. The last colon splits it, so
+/// the file name may contain colons.
+fn parse_clip_segment(value: &str) -> Result<(String, f64), String> {
+    let (file, seconds) = value.rsplit_once(':').ok_or("expected FILE:SECONDS")?;
+    let seconds: f64 = seconds.parse().map_err(|_| format!("invalid offset {:?}", seconds))?;
+    if file.is_empty() || !seconds.is_finite() || seconds < 0.0 {
+        return Err("expected FILE:SECONDS with a non-negative offset".to_string());
+    }
+    Ok((file.to_string(), seconds))
+}
+
+/// Parses a clip length in seconds into milliseconds.
+fn parse_clip_length(value: &str) -> Result<u32, String> {
+    let seconds: f64 = value.parse().map_err(|_| format!("invalid length {:?}", value))?;
+    let ms = (seconds * 1000.0).round();
+    if !seconds.is_finite() || ms < 1.0 || ms > u32::MAX as f64 {
+        return Err("expected a positive length in seconds".to_string());
+    }
+    Ok(ms as u32)
+}
+
 // fn main() {
 //     // Create "tmp" folder
 //     if let Err(e) = utils::create_folder("tmp") {
@@ -1,11 +1,33 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Couple {
     pub anchor_time_ms: u32,
     pub song_id: u32,
 }
 
+/// Describes which fingerprinting algorithm and parameters produced the stored addresses.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexMetadata {
+    pub algorithm_version: u32,
+    pub config_hash: String,
+}
+
+/// One uninterrupted airing of a song picked up by the stream monitor.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Play {
+    pub song_id: u32,
+    pub song_title: String,
+    pub song_artist: String,
+    /// When the song was first and last heard, as RFC 3339 timestamps.
+    pub first_seen: String,
+    pub last_seen: String,
+    /// Number of recognition windows that detected the song.
+    pub detections: u32,
+    /// Mean confidence of those detections.
+    pub confidence: f64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RecordData {
     pub audio: String,
@@ -0,0 +1,146 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::shazam::config::FingerprintConfig;
+use crate::shazam::fingerprint::Peak;
+
+/// Version tag stored in the top bits of every address. Bump when the layout below changes.
+pub const ADDRESS_VERSION: u32 = 1;
+
+// Bit budget of a 32-bit address, from the most significant bits down:
+// | version (2) | anchor bin (9) | target bin (9) | delta frames (12) |
+const VERSION_BITS: u32 = 2;
+const BIN_BITS: u32 = 9;
+const DELTA_BITS: u32 = 12;
+
+const DELTA_SHIFT: u32 = 0;
+const TARGET_SHIFT: u32 = DELTA_SHIFT + DELTA_BITS;
+const ANCHOR_SHIFT: u32 = TARGET_SHIFT + BIN_BITS;
+const VERSION_SHIFT: u32 = ANCHOR_SHIFT + BIN_BITS;
+
+/// Largest quantized bin that fits in an address.
+pub const MAX_ADDRESS_BIN: u32 = (1 << BIN_BITS) - 1;
+/// Largest anchor-to-target distance, in STFT frames, that fits in an address.
+pub const MAX_ADDRESS_DELTA: u32 = (1 << DELTA_BITS) - 1;
+
+const _: () = assert!(VERSION_SHIFT + VERSION_BITS == 32);
+const _: () = assert!(ADDRESS_VERSION < 1 << VERSION_BITS);
+
+/// The decoded fields of a fingerprint address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Address {
+    /// Quantized frequency bin of the anchor peak.
+    pub anchor_bin: u32,
+    /// Quantized frequency bin of the target peak.
+    pub target_bin: u32,
+    /// Distance between the anchor and target peaks, in STFT frames.
+    pub delta_frames: u32,
+}
+
+impl Address {
+    /// Builds the address of an anchor/target pair. Bins are scaled so that the full
+    /// spectrum of `freq_bin_size` fits the bin budget; returns `None` when the target is
+    /// before the anchor or too far after it to be represented.
+    pub fn from_peaks(anchor: &Peak, target: &Peak, config: &FingerprintConfig) -> Option<Self> {
+        let delta_frames = target.frame.checked_sub(anchor.frame)?;
+        if delta_frames > MAX_ADDRESS_DELTA {
+            return None;
+        }
+        Some(Address {
+            anchor_bin: quantize_bin(anchor.bin, config),
+            target_bin: quantize_bin(target.bin, config),
+            delta_frames,
+        })
+    }
+
+    /// Packs the fields, each masked to its bit budget, together with the version tag.
+    pub fn encode(&self) -> u32 {
+        (ADDRESS_VERSION << VERSION_SHIFT)
+            | ((self.anchor_bin & MAX_ADDRESS_BIN) << ANCHOR_SHIFT)
+            | ((self.target_bin & MAX_ADDRESS_BIN) << TARGET_SHIFT)
+            | ((self.delta_frames & MAX_ADDRESS_DELTA) << DELTA_SHIFT)
+    }
+
+    /// Unpacks an address, failing if it was encoded with a different layout version.
+    pub fn decode(address: u32) -> Result<Self, Box<dyn Error>> {
+        let version = address >> VERSION_SHIFT;
+        if version != ADDRESS_VERSION {
+            return Err(format!(
+                "address {:#010x} has layout version {}, expected {}",
+                address, version, ADDRESS_VERSION
+            )
+            .into());
+        }
+        Ok(Address {
+            anchor_bin: (address >> ANCHOR_SHIFT) & MAX_ADDRESS_BIN,
+            target_bin: (address >> TARGET_SHIFT) & MAX_ADDRESS_BIN,
+            delta_frames: (address >> DELTA_SHIFT) & MAX_ADDRESS_DELTA,
+        })
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "v{} anchor bin {} -> target bin {} after {} frames",
+            ADDRESS_VERSION, self.anchor_bin, self.target_bin, self.delta_frames
+        )
+    }
+}
+
+/// Maps an FFT bin index (0..=freq_bin_size/2) onto 0..=MAX_ADDRESS_BIN.
+fn quantize_bin(bin: u32, config: &FingerprintConfig) -> u32 {
+    let half_spectrum = (config.freq_bin_size / 2).max(1) as u64;
+    ((bin as u64 * (MAX_ADDRESS_BIN as u64 + 1) / half_spectrum) as u32).min(MAX_ADDRESS_BIN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peak(frame: u32, bin: u32) -> Peak {
+        Peak { frame, time: frame as f64 * 32.0 / 11025.0, bin, freq_hz: bin as f64 * 11025.0 / 1024.0 }
+    }
+
+    #[test]
+    fn test_encode_decode_round_trip() {
+        let config = FingerprintConfig::default();
+        for (anchor, target) in [(peak(0, 0), peak(0, 0)), (peak(10, 37), peak(250, 411)), (peak(5, 511), peak(5 + MAX_ADDRESS_DELTA, 1))] {
+            let address = Address::from_peaks(&anchor, &target, &config).unwrap();
+            assert_eq!(Address::decode(address.encode()).unwrap(), address);
+        }
+    }
+
+    #[test]
+    fn test_fields_do_not_overlap() {
+        let full = Address { anchor_bin: MAX_ADDRESS_BIN, target_bin: 0, delta_frames: 0 }.encode();
+        assert_eq!(full, (ADDRESS_VERSION << 30) | (0x1ff << 21));
+
+        // Out-of-range values are masked instead of spilling into the neighbouring fields.
+        let masked = Address { anchor_bin: 0, target_bin: MAX_ADDRESS_BIN + 2, delta_frames: MAX_ADDRESS_DELTA + 3 };
+        let decoded = Address::decode(masked.encode()).unwrap();
+        assert_eq!(decoded, Address { anchor_bin: 0, target_bin: 1, delta_frames: 2 });
+    }
+
+    #[test]
+    fn test_decode_rejects_other_versions() {
+        let address = Address { anchor_bin: 1, target_bin: 2, delta_frames: 3 }.encode();
+        assert!(Address::decode(address ^ (1 << 31)).is_err());
+    }
+
+    #[test]
+    fn test_from_peaks_quantizes_bins_and_bounds_delta() {
+        let config = FingerprintConfig::default();
+        // The Nyquist bin of a 1024-point FFT (512) saturates at the top of the 9-bit range.
+        let address = Address::from_peaks(&peak(0, 512), &peak(1, 100), &config).unwrap();
+        assert_eq!((address.anchor_bin, address.target_bin, address.delta_frames), (MAX_ADDRESS_BIN, 100, 1));
+
+        // With a 2048-point FFT, two bins share one quantized bin.
+        let wide = FingerprintConfig { freq_bin_size: 2048, ..FingerprintConfig::default() };
+        assert_eq!(Address::from_peaks(&peak(0, 1000), &peak(1, 1001), &wide).unwrap().anchor_bin, 500);
+
+        assert!(Address::from_peaks(&peak(10, 1), &peak(9, 1), &config).is_none());
+        assert!(Address::from_peaks(&peak(0, 1), &peak(MAX_ADDRESS_DELTA + 1, 1), &config).is_none());
+    }
+}
@@ -0,0 +1,311 @@
+use std::error::Error;
+use std::fs;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::IndexMetadata;
+use crate::shazam::fingerprint::ALGORITHM_VERSION;
+use crate::shazam::window::Window;
+use crate::utils;
+
+/// All tunable DSP and hashing parameters of the fingerprinting pipeline.
+///
+/// The same config must be used for `spectrogram`, `extract_peaks` and `fingerprint`
+/// at ingest and at query time, otherwise addresses will not line up.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FingerprintConfig {
+    /// Sample rate, in Hz, the low-passed signal is resampled to before the STFT.
+    pub analysis_sample_rate: i32,
+    /// Number of samples per STFT window (the FFT size).
+    pub freq_bin_size: usize,
+    /// Cutoff of the anti-aliasing low-pass filter, in Hz.
+    pub max_freq: f64,
+    /// Cutoff of the optional high-pass filter, in Hz; 0 disables it.
+    pub min_freq: f64,
+    /// Order of the Butterworth filters applied before resampling.
+    pub filter_order: usize,
+    /// Filter forwards and backwards to cancel the phase shift. Needs the whole signal up front,
+    /// so it is only suitable for offline ingest and file queries, not streaming input.
+    pub zero_phase_filter: bool,
+    /// Window function applied to each STFT frame.
+    pub window: Window,
+    /// Number of samples between the starts of consecutive STFT windows.
+    pub hop_size: usize,
+    /// Fraction of a frame shared with the next one, in [0, 1). When set it takes precedence
+    /// over `hop_size`; see `FingerprintConfig::hop`.
+    pub overlap: Option<f64>,
+    /// Frequency bands (as `[min, max)` bin indices); peaks are only searched within them.
+    pub bands: Vec<(usize, usize)>,
+    /// Half-width, in frames, of the neighborhood a peak must dominate.
+    pub peak_neighborhood_frames: usize,
+    /// Half-height, in frequency bins, of the neighborhood a peak must dominate.
+    pub peak_neighborhood_bins: usize,
+    /// Smoothing factor of the per-bin noise floor (closer to 1 adapts more slowly).
+    pub noise_floor_decay: f64,
+    /// How far above the noise floor a peak must rise.
+    pub noise_floor_factor: f64,
+    /// Peaks quieter than this many dB below a full-scale sine are discarded.
+    pub peak_floor_db: f64,
+    /// Maximum number of peaks kept per second of audio.
+    pub peaks_per_second: usize,
+    /// Number of following peaks each anchor is paired with.
+    pub target_zone_size: usize,
+}
+
+impl Default for FingerprintConfig {
+    fn default() -> Self {
+        FingerprintConfig {
+            analysis_sample_rate: 11025,
+            freq_bin_size: 1024,
+            max_freq: 5000.0,
+            min_freq: 0.0,
+            filter_order: 6,
+            zero_phase_filter: false,
+            window: Window::Hamming,
+            hop_size: 1024 / 32,
+            overlap: None,
+            bands: vec![(0, 10), (10, 20), (20, 40), (40, 80), (80, 160), (160, 512)],
+            peak_neighborhood_frames: 16,
+            peak_neighborhood_bins: 8,
+            noise_floor_decay: 0.95,
+            noise_floor_factor: 1.5,
+            peak_floor_db: -60.0,
+            peaks_per_second: 30,
+            target_zone_size: 5,
+        }
+    }
+}
+
+impl FingerprintConfig {
+    /// Returns the named preset: "default", "dense" (short, noisy clips) or "sparse" (large libraries).
+    pub fn preset(name: &str) -> Result<Self, Box<dyn Error>> {
+        match name {
+            "default" => Ok(FingerprintConfig::default()),
+            "dense" => Ok(FingerprintConfig {
+                hop_size: 16,
+                bands: vec![
+                    (0, 10), (10, 20), (20, 30), (30, 40), (40, 60),
+                    (60, 80), (80, 120), (120, 160), (160, 256), (256, 512),
+                ],
+                peak_neighborhood_frames: 16,
+                peak_neighborhood_bins: 4,
+                peaks_per_second: 60,
+                target_zone_size: 10,
+                ..FingerprintConfig::default()
+            }),
+            "sparse" => Ok(FingerprintConfig {
+                hop_size: 64,
+                bands: vec![(0, 20), (20, 40), (40, 80), (80, 160), (160, 512)],
+                peak_neighborhood_frames: 8,
+                peak_neighborhood_bins: 12,
+                peaks_per_second: 15,
+                target_zone_size: 3,
+                ..FingerprintConfig::default()
+            }),
+            other => Err(format!("unknown fingerprint preset: {}", other).into()),
+        }
+    }
+
+    /// Loads a config from a JSON file. Fields missing from the file keep their default values.
+    pub fn from_file(path: &str) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read fingerprint config {}: {}", path, e))?;
+        let config: FingerprintConfig = serde_json::from_str(&contents)
+            .map_err(|e| format!("failed to parse fingerprint config {}: {}", path, e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Builds the config from the environment.
+    ///
+    /// `FINGERPRINT_CONFIG` names a JSON file to load; otherwise `FINGERPRINT_PRESET`
+    /// selects a preset (defaults to "default"). Individual fields can then be overridden
+    /// with `FINGERPRINT_ANALYSIS_SAMPLE_RATE`, `FINGERPRINT_FREQ_BIN_SIZE`, `FINGERPRINT_MAX_FREQ`,
+    /// `FINGERPRINT_HOP_SIZE`, `FINGERPRINT_OVERLAP`, `FINGERPRINT_PEAKS_PER_SECOND` and
+    /// `FINGERPRINT_TARGET_ZONE_SIZE`.
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let config_file = utils::get_env("FINGERPRINT_CONFIG", None);
+        let mut config = if config_file.is_empty() {
+            FingerprintConfig::preset(&utils::get_env("FINGERPRINT_PRESET", Some("default")))?
+        } else {
+            FingerprintConfig::from_file(&config_file)?
+        };
+
+        override_from_env("FINGERPRINT_ANALYSIS_SAMPLE_RATE", &mut config.analysis_sample_rate)?;
+        override_from_env("FINGERPRINT_FREQ_BIN_SIZE", &mut config.freq_bin_size)?;
+        override_from_env("FINGERPRINT_MAX_FREQ", &mut config.max_freq)?;
+        override_from_env("FINGERPRINT_HOP_SIZE", &mut config.hop_size)?;
+        let overlap = utils::get_env("FINGERPRINT_OVERLAP", None);
+        if !overlap.is_empty() {
+            config.overlap = Some(overlap.parse().map_err(|e| format!("invalid value for FINGERPRINT_OVERLAP: {}", e))?);
+        }
+        override_from_env("FINGERPRINT_PEAKS_PER_SECOND", &mut config.peaks_per_second)?;
+        override_from_env("FINGERPRINT_TARGET_ZONE_SIZE", &mut config.target_zone_size)?;
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Number of samples between the starts of consecutive STFT frames, derived from `overlap`
+    /// when it is set and `hop_size` otherwise.
+    pub fn hop(&self) -> usize {
+        match self.overlap {
+            Some(overlap) => ((self.freq_bin_size as f64 * (1.0 - overlap)).round() as usize).max(1),
+            None => self.hop_size,
+        }
+    }
+
+    /// Returns a stable hex digest of the parameters (FNV-1a over their JSON encoding).
+    pub fn config_hash(&self) -> String {
+        let encoded = serde_json::to_string(self).unwrap_or_default();
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in encoded.bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        format!("{:016x}", hash)
+    }
+
+    /// Returns the metadata describing fingerprints produced with this config.
+    pub fn index_metadata(&self) -> IndexMetadata {
+        IndexMetadata {
+            algorithm_version: ALGORITHM_VERSION,
+            config_hash: self.config_hash(),
+        }
+    }
+
+    /// Fails with a descriptive error if `stored` was produced by a different algorithm or config.
+    pub fn check_index_metadata(&self, stored: &IndexMetadata) -> Result<(), Box<dyn Error>> {
+        let current = self.index_metadata();
+        if *stored != current {
+            return Err(format!(
+                "fingerprint index was built with algorithm version {} (config {}), but the current \
+                 pipeline is version {} (config {}); run `reindex` to re-fingerprint the library",
+                stored.algorithm_version, stored.config_hash, current.algorithm_version, current.config_hash
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Checks that the parameters are consistent with each other.
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.analysis_sample_rate <= 0 {
+            return Err(format!("analysis_sample_rate must be positive, got {}", self.analysis_sample_rate).into());
+        }
+        if self.freq_bin_size == 0 || !self.freq_bin_size.is_multiple_of(2) {
+            return Err(format!("freq_bin_size must be even and non-zero, got {}", self.freq_bin_size).into());
+        }
+        if self.max_freq <= 0.0 || self.max_freq >= self.analysis_sample_rate as f64 / 2.0 {
+            return Err(format!(
+                "max_freq must be positive and below the analysis Nyquist frequency, got {}",
+                self.max_freq
+            )
+            .into());
+        }
+        if self.min_freq < 0.0 || self.min_freq >= self.max_freq {
+            return Err(format!("min_freq must be in [0, max_freq), got {}", self.min_freq).into());
+        }
+        if !(1..=16).contains(&self.filter_order) {
+            return Err(format!("filter_order must be between 1 and 16, got {}", self.filter_order).into());
+        }
+        if let Some(overlap) = self.overlap
+            && !(0.0..1.0).contains(&overlap)
+        {
+            return Err(format!("overlap must be in [0, 1), got {}", overlap).into());
+        }
+        if self.hop() == 0 || self.hop() > self.freq_bin_size {
+            return Err(format!("hop must be between 1 and freq_bin_size, got {}", self.hop()).into());
+        }
+        if let Window::Kaiser { beta } = self.window
+            && beta < 0.0
+        {
+            return Err(format!("kaiser window beta must not be negative, got {}", beta).into());
+        }
+        if self.bands.is_empty() {
+            return Err("at least one frequency band is required".into());
+        }
+        let num_bins = self.freq_bin_size / 2 + 1;
+        for &(min, max) in &self.bands {
+            if min >= max || max > num_bins {
+                return Err(format!("invalid frequency band ({}, {}) for {} bins", min, max, num_bins).into());
+            }
+        }
+        if !(0.0..1.0).contains(&self.noise_floor_decay) {
+            return Err(format!("noise_floor_decay must be in [0, 1), got {}", self.noise_floor_decay).into());
+        }
+        if self.noise_floor_factor < 0.0 {
+            return Err(format!("noise_floor_factor must not be negative, got {}", self.noise_floor_factor).into());
+        }
+        if self.peaks_per_second == 0 {
+            return Err("peaks_per_second must be at least 1".into());
+        }
+        if self.target_zone_size == 0 {
+            return Err("target_zone_size must be at least 1".into());
+        }
+        Ok(())
+    }
+}
+
+/// Replaces `value` with the parsed contents of the environment variable `key`, if it is set.
+pub(crate) fn override_from_env<T: FromStr>(key: &str, value: &mut T) -> Result<(), Box<dyn Error>>
+where
+    T::Err: std::fmt::Display,
+{
+    let raw = utils::get_env(key, None);
+    if !raw.is_empty() {
+        *value = raw.parse().map_err(|e| format!("invalid value for {}: {}", key, e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets_are_valid() {
+        for name in ["default", "dense", "sparse"] {
+            let config = FingerprintConfig::preset(name).unwrap();
+            assert!(config.validate().is_ok(), "preset {} is invalid", name);
+        }
+        assert!(FingerprintConfig::preset("unknown").is_err());
+    }
+
+    #[test]
+    fn test_partial_json_keeps_defaults() {
+        let config: FingerprintConfig = serde_json::from_str(r#"{"hop_size": 64}"#).unwrap();
+        assert_eq!(config.hop_size, 64);
+        assert_eq!(config.freq_bin_size, FingerprintConfig::default().freq_bin_size);
+    }
+
+    #[test]
+    fn test_config_hash_tracks_parameters() {
+        let default = FingerprintConfig::default();
+        let sparse = FingerprintConfig::preset("sparse").unwrap();
+        assert_eq!(default.config_hash(), FingerprintConfig::default().config_hash());
+        assert_ne!(default.config_hash(), sparse.config_hash());
+
+        assert!(default.check_index_metadata(&default.index_metadata()).is_ok());
+        assert!(default.check_index_metadata(&sparse.index_metadata()).is_err());
+    }
+
+    #[test]
+    fn test_overlap_overrides_hop_size() {
+        let config = FingerprintConfig { overlap: Some(0.75), ..FingerprintConfig::default() };
+        assert_eq!(config.hop(), 256);
+        assert_eq!(FingerprintConfig::default().hop(), FingerprintConfig::default().hop_size);
+        assert!(FingerprintConfig { overlap: Some(1.0), ..config }.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_rejects_out_of_range_band() {
+        let config = FingerprintConfig {
+            bands: vec![(0, 10), (500, 600)],
+            ..FingerprintConfig::default()
+        };
+        assert!(config.validate().is_err());
+    }
+}
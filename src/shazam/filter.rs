@@ -1,48 +1,246 @@
-/// A first-order low-pass filter defined by H(p) = 1 / (1 + pRC)
-pub struct LowPassFilter {
-    alpha: f64, // Filter coefficient
-    y_prev: f64, // Previous output value
+use std::error::Error;
+use std::f64::consts::PI;
+
+/// A second-order IIR section in transposed direct form II, normalized so that a0 = 1.
+/// First-order sections are represented with `b2 = a2 = 0`.
+#[derive(Debug, Clone)]
+pub struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    z1: f64, // Filter state
+    z2: f64,
+}
+
+impl Biquad {
+    fn new(b0: f64, b1: f64, b2: f64, a0: f64, a1: f64, a2: f64) -> Self {
+        Biquad {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    /// Second-order low-pass section with quality factor `q` (bilinear transform).
+    fn low_pass(cutoff: f64, sample_rate: f64, q: f64) -> Self {
+        let w0 = 2.0 * PI * cutoff / sample_rate;
+        let (cos, alpha) = (w0.cos(), w0.sin() / (2.0 * q));
+        Biquad::new((1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha)
+    }
+
+    /// Second-order high-pass section with quality factor `q` (bilinear transform).
+    fn high_pass(cutoff: f64, sample_rate: f64, q: f64) -> Self {
+        let w0 = 2.0 * PI * cutoff / sample_rate;
+        let (cos, alpha) = (w0.cos(), w0.sin() / (2.0 * q));
+        Biquad::new((1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha)
+    }
+
+    /// First-order low-pass section (bilinear transform of 1 / (1 + s)).
+    fn first_order_low_pass(cutoff: f64, sample_rate: f64) -> Self {
+        let k = (PI * cutoff / sample_rate).tan();
+        Biquad::new(k, k, 0.0, k + 1.0, k - 1.0, 0.0)
+    }
+
+    /// First-order high-pass section (bilinear transform of s / (1 + s)).
+    fn first_order_high_pass(cutoff: f64, sample_rate: f64) -> Self {
+        let k = (PI * cutoff / sample_rate).tan();
+        Biquad::new(1.0, -1.0, 0.0, k + 1.0, k - 1.0, 0.0)
+    }
+
+    /// Filters one sample.
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+
+    /// Clears the filter state.
+    pub fn reset(&mut self) {
+        self.z1 = 0.0;
+        self.z2 = 0.0;
+    }
 }
 
-impl LowPassFilter {
-    /// Creates a new low-pass filter given a cutoff frequency and sample rate.
-    pub fn new(cutoff_frequency: f64, sample_rate: f64) -> Self {
-        let rc = 1.0 / (2.0 * std::f64::consts::PI * cutoff_frequency);
-        let dt = 1.0 / sample_rate;
-        let alpha = dt / (rc + dt);
-        LowPassFilter { alpha, y_prev: 0.0 }
+/// A Butterworth filter of arbitrary order, implemented as a cascade of biquad sections.
+/// The rolloff is `6 * order` dB per octave beyond the cutoff.
+#[derive(Debug, Clone)]
+pub struct ButterworthFilter {
+    sections: Vec<Biquad>,
+}
+
+impl ButterworthFilter {
+    /// Creates a low-pass filter of the given order with its -3 dB point at `cutoff` Hz.
+    pub fn low_pass(order: usize, cutoff: f64, sample_rate: f64) -> Result<Self, Box<dyn Error>> {
+        check_cutoff(order, cutoff, sample_rate)?;
+        Ok(ButterworthFilter {
+            sections: butterworth_sections(order, |q| match q {
+                Some(q) => Biquad::low_pass(cutoff, sample_rate, q),
+                None => Biquad::first_order_low_pass(cutoff, sample_rate),
+            }),
+        })
+    }
+
+    /// Creates a high-pass filter of the given order with its -3 dB point at `cutoff` Hz.
+    pub fn high_pass(order: usize, cutoff: f64, sample_rate: f64) -> Result<Self, Box<dyn Error>> {
+        check_cutoff(order, cutoff, sample_rate)?;
+        Ok(ButterworthFilter {
+            sections: butterworth_sections(order, |q| match q {
+                Some(q) => Biquad::high_pass(cutoff, sample_rate, q),
+                None => Biquad::first_order_high_pass(cutoff, sample_rate),
+            }),
+        })
     }
 
-    /// Processes the input signal through the low-pass filter.
-    /// Returns a new vector containing the filtered signal.
+    /// Creates a band-pass filter passing `[low_cutoff, high_cutoff]` Hz, built from a high-pass
+    /// and a low-pass of the given order.
+    pub fn band_pass(order: usize, low_cutoff: f64, high_cutoff: f64, sample_rate: f64) -> Result<Self, Box<dyn Error>> {
+        if low_cutoff >= high_cutoff {
+            return Err(format!("invalid pass band: {} Hz to {} Hz", low_cutoff, high_cutoff).into());
+        }
+        let mut filter = ButterworthFilter::high_pass(order, low_cutoff, sample_rate)?;
+        filter.sections.extend(ButterworthFilter::low_pass(order, high_cutoff, sample_rate)?.sections);
+        Ok(filter)
+    }
+
+    /// Filters the input signal, continuing from the state left by previous calls so that
+    /// a signal can be filtered chunk by chunk.
     pub fn filter(&mut self, input: &[f64]) -> Vec<f64> {
-        let mut filtered = Vec::with_capacity(input.len());
-        for (i, &x) in input.iter().enumerate() {
-            let y = if i == 0 {
-                x * self.alpha
+        input
+            .iter()
+            .map(|&x| self.sections.iter_mut().fold(x, |acc, section| section.process(acc)))
+            .collect()
+    }
+
+    /// Filters the whole signal forwards and then backwards, which cancels the phase shift
+    /// (and doubles the attenuation in dB). Only usable when the complete signal is available.
+    pub fn filter_zero_phase(&mut self, input: &[f64]) -> Vec<f64> {
+        self.reset();
+        let mut output = self.filter(input);
+        output.reverse();
+        self.reset();
+        let mut output = self.filter(&output);
+        output.reverse();
+        self.reset();
+        output
+    }
+
+    /// Clears the state of every section.
+    pub fn reset(&mut self) {
+        self.sections.iter_mut().for_each(Biquad::reset);
+    }
+}
+
+fn check_cutoff(order: usize, cutoff: f64, sample_rate: f64) -> Result<(), Box<dyn Error>> {
+    if order == 0 {
+        return Err("filter order must be at least 1".into());
+    }
+    if cutoff <= 0.0 || cutoff >= sample_rate / 2.0 {
+        return Err(format!(
+            "filter cutoff must be between 0 and the Nyquist frequency ({} Hz), got {} Hz",
+            sample_rate / 2.0,
+            cutoff
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Builds the sections of a Butterworth filter of the given order. `make` is called with the Q of
+/// each second-order section, and with `None` for the extra first-order section of odd orders.
+fn butterworth_sections(order: usize, make: impl Fn(Option<f64>) -> Biquad) -> Vec<Biquad> {
+    let mut sections: Vec<Biquad> = (0..order / 2)
+        .map(|k| {
+            // Angle of the k-th conjugate pole pair from the negative real axis.
+            let angle = if order.is_multiple_of(2) {
+                PI * (2 * k + 1) as f64 / (2 * order) as f64
             } else {
-                self.alpha * x + (1.0 - self.alpha) * self.y_prev
+                PI * (k + 1) as f64 / order as f64
             };
-            self.y_prev = y;
-            filtered.push(y);
-        }
-        filtered
+            make(Some(1.0 / (2.0 * angle.cos())))
+        })
+        .collect();
+    if order % 2 == 1 {
+        sections.push(make(None));
     }
+    sections
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    const SAMPLE_RATE: f64 = 44100.0;
+
+    /// Measures the steady-state gain of the filter for a sine at `freq` Hz.
+    fn gain(filter: &mut ButterworthFilter, freq: f64, zero_phase: bool) -> f64 {
+        let input: Vec<f64> = (0..SAMPLE_RATE as usize)
+            .map(|i| (2.0 * PI * freq * i as f64 / SAMPLE_RATE).sin())
+            .collect();
+        filter.reset();
+        let output = if zero_phase { filter.filter_zero_phase(&input) } else { filter.filter(&input) };
+        // Skip the start-up (and, for zero-phase, the end) transients.
+        let rms = |s: &[f64]| (s.iter().map(|x| x * x).sum::<f64>() / s.len() as f64).sqrt();
+        let range = input.len() / 4..input.len() * 3 / 4;
+        rms(&output[range.clone()]) / rms(&input[range])
+    }
+
+    fn db(gain: f64) -> f64 {
+        20.0 * gain.log10()
+    }
+
+    #[test]
+    fn test_low_pass_response() {
+        for order in [1, 2, 3, 4, 8] {
+            let mut filter = ButterworthFilter::low_pass(order, 1000.0, SAMPLE_RATE).unwrap();
+            assert!(db(gain(&mut filter, 100.0, false)).abs() < 0.1, "order {} attenuates the pass band", order);
+            assert!((db(gain(&mut filter, 1000.0, false)) + 3.01).abs() < 0.2, "order {} misses -3 dB at the cutoff", order);
+            // An ideal Butterworth filter is down 10*log10(1 + 4^(2n)) dB two octaves above the cutoff.
+            let expected = -10.0 * (1.0 + 4f64.powi(2 * order as i32)).log10();
+            assert!(db(gain(&mut filter, 4000.0, false)) < expected + 1.0, "order {} rolls off too slowly", order);
+        }
+    }
+
+    #[test]
+    fn test_high_pass_response() {
+        let mut filter = ButterworthFilter::high_pass(4, 1000.0, SAMPLE_RATE).unwrap();
+        assert!(db(gain(&mut filter, 250.0, false)) < -47.0);
+        assert!((db(gain(&mut filter, 1000.0, false)) + 3.01).abs() < 0.2);
+        assert!(db(gain(&mut filter, 8000.0, false)).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_band_pass_response() {
+        let mut filter = ButterworthFilter::band_pass(4, 300.0, 3000.0, SAMPLE_RATE).unwrap();
+        assert!(db(gain(&mut filter, 1000.0, false)).abs() < 0.1);
+        assert!(db(gain(&mut filter, 50.0, false)) < -45.0);
+        assert!(db(gain(&mut filter, 12000.0, false)) < -45.0);
+        assert!(ButterworthFilter::band_pass(4, 3000.0, 300.0, SAMPLE_RATE).is_err());
+    }
+
+    #[test]
+    fn test_zero_phase_doubles_attenuation_without_delay() {
+        let mut filter = ButterworthFilter::low_pass(4, 1000.0, SAMPLE_RATE).unwrap();
+        assert!((db(gain(&mut filter, 1000.0, true)) + 6.02).abs() < 0.3);
+
+        // A symmetric pulse stays centered on the same sample.
+        let mut input = vec![0.0; 4001];
+        input[2000] = 1.0;
+        let output = filter.filter_zero_phase(&input);
+        let peak = (0..output.len()).max_by(|&a, &b| output[a].total_cmp(&output[b])).unwrap();
+        assert_eq!(peak, 2000);
+    }
+
     #[test]
-    fn test_low_pass_filter() {
-        // Create a low-pass filter with cutoff frequency of 1000 Hz and sample rate 44100 Hz.
-        let mut filter = LowPassFilter::new(1000.0, 44100.0);
-        // A simple impulse response: first sample is 1.0, rest are 0.
-        let input = vec![1.0, 0.0, 0.0, 0.0, 0.0];
-        let output = filter.filter(&input);
-        // Verify that the filter produces a decaying output.
-        assert!(output[0] > output[1]);
-        assert!(output[1] > output[2]);
+    fn test_rejects_invalid_parameters() {
+        assert!(ButterworthFilter::low_pass(0, 1000.0, SAMPLE_RATE).is_err());
+        assert!(ButterworthFilter::low_pass(4, 30000.0, SAMPLE_RATE).is_err());
+        assert!(ButterworthFilter::high_pass(4, 0.0, SAMPLE_RATE).is_err());
     }
 }
@@ -1,76 +1,107 @@
-use std::collections::HashMap;
-use num_complex::Complex;
 use crate::models::Couple;
+use crate::shazam::address::Address;
+use crate::shazam::config::FingerprintConfig;
 
-const MAX_FREQ_BITS: u32 = 9;
-const MAX_DELTA_BITS: u32 = 14;
-const TARGET_ZONE_SIZE: usize = 5;
+/// Version of the peak-pairing and address scheme. Bump whenever `create_address`
+/// or the way peaks are derived changes, so existing indexes are detected as stale.
+pub const ALGORITHM_VERSION: u32 = 8;
 
 /// Generates fingerprints from a list of peaks and associates each fingerprint (address)
 /// with a couple (anchor time in ms and song ID).
-pub fn fingerprint(peaks: &[Peak], song_id: u32) -> HashMap<u32, Couple> {
-    let mut fingerprints = HashMap::new();
+/// The same address may appear several times, once for every anchor time it occurs at.
+pub fn fingerprint(peaks: &[Peak], song_id: u32, config: &FingerprintConfig) -> Vec<(u32, Couple)> {
+    let mut fingerprints = Vec::new();
 
     for (i, anchor) in peaks.iter().enumerate() {
-        for target in peaks.iter().skip(i + 1).take(TARGET_ZONE_SIZE) {
-            let address = create_address(anchor, target);
-            let anchor_time_ms = (anchor.time * 1000.0) as u32;
-            fingerprints.insert(address, Couple { anchor_time_ms, song_id });
+        for target in peaks.iter().skip(i + 1).take(config.target_zone_size) {
+            // Pairs too far apart for the address layout are skipped rather than wrapped around.
+            if let Some(address) = create_address(anchor, target, config) {
+                let anchor_time_ms = (anchor.time * 1000.0) as u32;
+                fingerprints.push((address, Couple { anchor_time_ms, song_id }));
+            }
         }
     }
 
     fingerprints
 }
 
-/// Generates a unique address for a pair of anchor and target peaks.
-/// The address is a 32-bit integer combining the integer parts of the anchor frequency,
-/// target frequency, and the delta time (in ms) between them.
-pub fn create_address(anchor: &Peak, target: &Peak) -> u32 {
-    let anchor_freq = anchor.freq.re as u32;
-    let target_freq = target.freq.re as u32;
-    let delta_ms = ((target.time - anchor.time) * 1000.0) as u32;
-    
-    // Combine the values into a single 32-bit address.
-    (anchor_freq << 23) | (target_freq << 14) | delta_ms
+/// Generates the address of a pair of anchor and target peaks: their quantized frequency
+/// bins and the distance between them in frames, packed as described in `Address`.
+pub fn create_address(anchor: &Peak, target: &Peak, config: &FingerprintConfig) -> Option<u32> {
+    Address::from_peaks(anchor, target, config).map(|address| address.encode())
 }
 
-/// Dummy Peak struct for illustration purposes.
-/// In your project, ensure this is defined in the proper module.
-#[derive(Debug)]
+/// A point of the constellation map: a local maximum of the spectrogram.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Peak {
+    /// Index of the peak's STFT frame.
+    pub frame: u32,
+    /// Start time of the peak's STFT frame, in seconds.
     pub time: f64,
-    pub freq: Complex<f64>,
+    /// Index of the FFT bin the peak lies in.
+    pub bin: u32,
+    /// Center frequency of that bin, in Hz.
+    pub freq_hz: f64,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use num_complex::Complex;
+
+    fn peak(frame: u32, bin: u32) -> Peak {
+        Peak { frame, time: frame as f64 * 32.0 / 11025.0, bin, freq_hz: bin as f64 * 11025.0 / 1024.0 }
+    }
 
     #[test]
     fn test_create_address() {
         // Create two dummy peaks.
-        let anchor = Peak { time: 1.0, freq: Complex::new(100.0, 0.0) };
-        let target = Peak { time: 1.05, freq: Complex::new(200.0, 0.0) };
-        let address = create_address(&anchor, &target);
+        let anchor = peak(344, 100);
+        let target = peak(361, 200);
+        let address = create_address(&anchor, &target, &FingerprintConfig::default()).unwrap();
         // Verify that the address is computed as expected.
-        let expected_delta = ((1.05 - 1.0) * 1000.0) as u32;
-        let expected = (100 << 23) | (200 << 14) | expected_delta;
-        assert_eq!(address, expected);
+        let expected = Address { anchor_bin: 100, target_bin: 200, delta_frames: 17 };
+        assert_eq!(Address::decode(address).unwrap(), expected);
     }
 
     #[test]
     fn test_fingerprint() {
         // Create a few dummy peaks.
         let peaks = vec![
-            Peak { time: 0.0, freq: Complex::new(50.0, 0.0) },
-            Peak { time: 0.1, freq: Complex::new(60.0, 0.0) },
-            Peak { time: 0.2, freq: Complex::new(70.0, 0.0) },
-            Peak { time: 0.3, freq: Complex::new(80.0, 0.0) },
+            peak(0, 50),
+            peak(34, 60),
+            peak(69, 70),
+            peak(103, 80),
         ];
         let song_id = 42;
-        let fingerprints = fingerprint(&peaks, song_id);
-        // We expect some fingerprints to be generated.
-        assert!(!fingerprints.is_empty());
+        let fingerprints = fingerprint(&peaks, song_id, &FingerprintConfig::default());
+        // Every peak is paired with all the peaks after it.
+        assert_eq!(fingerprints.len(), 6);
+    }
+
+    #[test]
+    fn test_fingerprint_skips_pairs_beyond_the_delta_budget() {
+        let peaks = vec![peak(0, 50), peak(10_000, 60)];
+        assert!(fingerprint(&peaks, 1, &FingerprintConfig::default()).is_empty());
+    }
+
+    #[test]
+    fn test_fingerprint_keeps_repeated_addresses() {
+        // The same two-note motif played twice produces the same address at two anchor times.
+        let peaks = vec![
+            peak(0, 50),
+            peak(86, 60),
+            peak(1723, 50),
+            peak(1809, 60),
+        ];
+        let config = FingerprintConfig { target_zone_size: 1, ..FingerprintConfig::default() };
+        let fingerprints = fingerprint(&peaks, 7, &config);
+
+        let motif = create_address(&peaks[0], &peaks[1], &config).unwrap();
+        let anchor_times: Vec<u32> = fingerprints
+            .iter()
+            .filter(|(address, _)| *address == motif)
+            .map(|(_, couple)| couple.anchor_time_ms)
+            .collect();
+        assert_eq!(anchor_times, vec![0, (peaks[2].time * 1000.0) as u32]);
     }
 }
@@ -1,3 +1,7 @@
+mod address;
+pub use address::*;
+mod config;
+pub use config::*;
 mod fft;
 pub use fft::*;
 mod filter;
@@ -6,10 +10,20 @@ mod fingerprint;
 pub use fingerprint::*;
 mod image;
 pub use image::*;
+mod monitor;
+pub use monitor::*;
+mod scoring;
+pub use scoring::*;
+mod segment;
+pub use segment::*;
 mod shazam;
 pub use shazam::*;
 mod shazam_init;
 pub use shazam_init::*;
 mod spectrogram;
 pub use spectrogram::*;
+mod streaming;
+pub use streaming::*;
+mod window;
+pub use window::*;
 
@@ -0,0 +1,421 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use slog::info;
+
+use crate::db;
+use crate::models::{Couple, Play};
+use crate::shazam::{match_with_client, FingerprintConfig, Fingerprinter, Match, MatchOptions};
+use crate::utils;
+use crate::wav;
+
+/// Number of bytes read from the stream at a time.
+const READ_CHUNK_BYTES: usize = 1 << 14;
+
+/// How far the stream may run past the end of a window before the window is evaluated even
+/// though no later fingerprint has arrived, which happens when the audio goes quiet.
+const FINALIZE_DELAY_MS: u64 = 5_000;
+
+/// Options controlling how a continuous PCM stream is decoded, cut into recognition windows
+/// and how repeated detections are merged into plays.
+#[derive(Debug, Clone)]
+pub struct MonitorOptions {
+    /// Sample encoding, rate and channel count of the incoming headerless PCM; the channels
+    /// are averaged down to mono.
+    pub format: wav::RawPcmFormat,
+    /// Length of each recognition window, in milliseconds.
+    pub window_ms: u32,
+    /// Distance between the starts of consecutive windows, in milliseconds.
+    pub step_ms: u32,
+    /// Longest stretch without a detection of the current song before its play is closed.
+    pub max_gap_ms: u32,
+    /// Detections needed before a play is logged, which filters out one-off false positives.
+    pub min_detections: u32,
+}
+
+impl Default for MonitorOptions {
+    fn default() -> Self {
+        MonitorOptions {
+            format: wav::RawPcmFormat::default(),
+            window_ms: 10_000,
+            step_ms: 5_000,
+            max_gap_ms: 30_000,
+            min_detections: 2,
+        }
+    }
+}
+
+/// A play assembled from window detections, with times in milliseconds since the stream started.
+#[derive(Debug, Clone)]
+pub struct DetectedPlay {
+    pub song: Match,
+    pub first_seen_ms: u64,
+    pub last_seen_ms: u64,
+    pub detections: u32,
+    /// Sum of the detections' confidences.
+    confidence_sum: f64,
+}
+
+impl DetectedPlay {
+    /// Converts the play to its logged form, placing it in wall-clock time relative to
+    /// `started_at`, the moment the stream started.
+    pub fn to_play(&self, started_at: DateTime<Utc>) -> Play {
+        let at = |ms: u64| (started_at + Duration::milliseconds(ms as i64)).to_rfc3339_opts(SecondsFormat::Millis, true);
+        Play {
+            song_id: self.song.song_id,
+            song_title: self.song.song_title.clone(),
+            song_artist: self.song.song_artist.clone(),
+            first_seen: at(self.first_seen_ms),
+            last_seen: at(self.last_seen_ms),
+            detections: self.detections,
+            confidence: self.confidence_sum / self.detections as f64,
+        }
+    }
+}
+
+/// Debounces window detections: consecutive detections of the same song no more than
+/// `max_gap_ms` apart extend one play, which is reported once it ends.
+pub struct PlayTracker {
+    max_gap_ms: u64,
+    min_detections: u32,
+    current: Option<DetectedPlay>,
+}
+
+impl PlayTracker {
+    pub fn new(options: &MonitorOptions) -> Self {
+        PlayTracker { max_gap_ms: options.max_gap_ms as u64, min_detections: options.min_detections, current: None }
+    }
+
+    /// Feeds the outcome of the window `[window_start_ms, window_end_ms)` and returns the play
+    /// that ended because of it, if it had enough detections to be logged.
+    pub fn observe(&mut self, window_start_ms: u64, window_end_ms: u64, song: Option<&Match>) -> Option<DetectedPlay> {
+        let Some(song) = song else {
+            let expired = self
+                .current
+                .as_ref()
+                .is_some_and(|play| window_end_ms.saturating_sub(play.last_seen_ms) > self.max_gap_ms);
+            return if expired { self.finish() } else { None };
+        };
+
+        // The part of the window covered by the hits that agree with the match's offset. The
+        // offset is negative when the song starts partway into the window.
+        let in_window = |song_ms: u32| (song_ms as i64 - song.offset_ms).max(0) as u64;
+        let hit_start = (window_start_ms + in_window(song.match_start_ms)).min(window_end_ms);
+        let hit_end = window_start_ms + in_window(song.match_end_ms);
+        let hit_end = hit_end.clamp(hit_start, window_end_ms);
+
+        if let Some(play) = self.current.as_mut()
+            && play.song.song_id == song.song_id
+            && hit_start <= play.last_seen_ms.saturating_add(self.max_gap_ms)
+        {
+            play.last_seen_ms = play.last_seen_ms.max(hit_end);
+            play.detections += 1;
+            play.confidence_sum += song.confidence;
+            return None;
+        }
+
+        let finished = self.finish();
+        self.current = Some(DetectedPlay {
+            song: song.clone(),
+            first_seen_ms: hit_start,
+            last_seen_ms: hit_end,
+            detections: 1,
+            confidence_sum: song.confidence,
+        });
+        finished
+    }
+
+    /// Ends the current play, returning it if it had enough detections to be logged.
+    pub fn finish(&mut self) -> Option<DetectedPlay> {
+        self.current.take().filter(|play| play.detections >= self.min_detections)
+    }
+}
+
+/// A recognition window `[start_ms, end_ms)` of the stream, with anchor times made relative
+/// to its start.
+struct QueryWindow {
+    start_ms: u64,
+    end_ms: u64,
+    fingerprints: Vec<(u32, Couple)>,
+}
+
+/// Collects the fingerprints of a stream and hands out the rolling recognition windows
+/// once they are complete.
+struct WindowQueue {
+    window_ms: u64,
+    step_ms: u64,
+    /// ID the query fingerprints are attributed to.
+    song_id: u32,
+    /// Start of the next window to hand out.
+    window_start_ms: u64,
+    /// Addresses and stream positions of the fingerprints anchored at or after
+    /// `window_start_ms`, in anchor order.
+    fingerprints: VecDeque<(u32, u64)>,
+}
+
+impl WindowQueue {
+    fn new(options: &MonitorOptions, song_id: u32) -> Self {
+        WindowQueue {
+            window_ms: options.window_ms as u64,
+            step_ms: options.step_ms as u64,
+            song_id,
+            window_start_ms: 0,
+            fingerprints: VecDeque::new(),
+        }
+    }
+
+    /// Queues fingerprints whose anchor times are measured from `origin_ms` into the stream.
+    fn extend(&mut self, fingerprints: Vec<(u32, Couple)>, origin_ms: u64) {
+        self.fingerprints
+            .extend(fingerprints.into_iter().map(|(address, couple)| (address, origin_ms + couple.anchor_time_ms as u64)));
+    }
+
+    /// Returns the next complete window. Fingerprints come out of the fingerprinter in anchor
+    /// order, so a window is complete once one anchored at or after its end has arrived, once
+    /// the stream is `FINALIZE_DELAY_MS` past its end (in case the audio went quiet), or, after
+    /// the stream has ended, as long as it still contains fingerprints.
+    fn next_window(&mut self, stream_ms: u64, ended: bool) -> Option<QueryWindow> {
+        let start_ms = self.window_start_ms;
+        let end_ms = start_ms.saturating_add(self.window_ms);
+        let latest_anchor_ms = self.fingerprints.back().map(|&(_, anchor_ms)| anchor_ms);
+        let complete = if ended {
+            latest_anchor_ms.is_some()
+        } else {
+            latest_anchor_ms.is_some_and(|latest| latest >= end_ms)
+                || stream_ms >= end_ms.saturating_add(FINALIZE_DELAY_MS)
+        };
+        if !complete {
+            return None;
+        }
+
+        let fingerprints = self
+            .fingerprints
+            .iter()
+            .take_while(|&&(_, anchor_ms)| anchor_ms < end_ms)
+            .map(|&(address, anchor_ms)| {
+                (address, Couple { anchor_time_ms: (anchor_ms - start_ms) as u32, song_id: self.song_id })
+            })
+            .collect();
+
+        self.window_start_ms = start_ms.saturating_add(self.step_ms);
+        while self.fingerprints.front().is_some_and(|&(_, anchor_ms)| anchor_ms < self.window_start_ms) {
+            self.fingerprints.pop_front();
+        }
+        Some(QueryWindow { start_ms, end_ms, fingerprints })
+    }
+}
+
+/// Reads raw PCM from `reader` until it ends, recognizes songs over rolling windows and
+/// appends every play to the database and, as one JSON object per line, to `log_path`.
+/// Play times are the moment monitoring started plus the position in the stream, which is
+/// the wall-clock time for a live source. Returns the number of plays logged.
+pub async fn monitor_stream(mut reader: impl Read, options: &MonitorOptions, log_path: &str) -> Result<usize, Box<dyn Error>> {
+    if options.window_ms == 0 || options.step_ms == 0 {
+        return Err("monitor window and step must be at least 1 ms".into());
+    }
+    let mut decoder = wav::PcmStreamDecoder::new(&options.format)?;
+    let sample_rate = options.format.sample_rate;
+    let config = FingerprintConfig::from_env()?;
+    let match_options = MatchOptions::from_env()?;
+
+    let mut db_client = db::new_db_client().await?;
+    db::check_index_compatible(db_client.as_ref(), &config)?;
+
+    let mut log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path)
+        .map_err(|e| format!("failed to open play log {}: {}", log_path, e))?;
+
+    let started_at = Utc::now();
+    let query_id = utils::generate_unique_id();
+    let mut fingerprinter = Fingerprinter::new(sample_rate as i32, query_id, &config)?;
+    let mut tracker = PlayTracker::new(options);
+    let mut windows = WindowQueue::new(options, query_id);
+    // Stream position the fingerprinter's anchor times are measured from.
+    let mut origin_ms = 0;
+    let mut buffer = vec![0u8; READ_CHUNK_BYTES];
+    let mut samples_read: u64 = 0;
+    let mut plays = 0;
+
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(format!("failed to read PCM stream: {}", e).into()),
+        };
+        let samples = decoder.push(&buffer[..read])?;
+        samples_read += samples.len() as u64;
+        windows.extend(fingerprinter.push(&samples)?, origin_ms);
+
+        let stream_ms = samples_read * 1000 / sample_rate as u64;
+        for play in recognize_windows(&mut windows, &mut tracker, db_client.as_ref(), &match_options, stream_ms, false)? {
+            log_play(&play.to_play(started_at), db_client.as_mut(), &mut log)?;
+            plays += 1;
+        }
+        // Measure anchors from the oldest window still to be matched, so their 32-bit times
+        // never run out however long the stream runs.
+        origin_ms = windows.window_start_ms;
+        fingerprinter.set_origin_ms(origin_ms);
+    }
+
+    windows.extend(fingerprinter.finish()?, origin_ms);
+    let mut finished = recognize_windows(&mut windows, &mut tracker, db_client.as_ref(), &match_options, u64::MAX, true)?;
+    finished.extend(tracker.finish());
+    for play in finished {
+        log_play(&play.to_play(started_at), db_client.as_mut(), &mut log)?;
+        plays += 1;
+    }
+
+    db_client.close()?;
+    Ok(plays)
+}
+
+/// Matches every window that is complete at `stream_ms` and returns the plays that ended.
+fn recognize_windows(
+    windows: &mut WindowQueue,
+    tracker: &mut PlayTracker,
+    db_client: &dyn db::DBClient,
+    match_options: &MatchOptions,
+    stream_ms: u64,
+    ended: bool,
+) -> Result<Vec<DetectedPlay>, Box<dyn Error>> {
+    let mut finished = Vec::new();
+    while let Some(window) = windows.next_window(stream_ms, ended) {
+        let song = if window.fingerprints.is_empty() {
+            None
+        } else {
+            match_with_client(db_client, &window.fingerprints, match_options)?.best().cloned()
+        };
+        finished.extend(tracker.observe(window.start_ms, window.end_ms, song.as_ref()));
+    }
+    Ok(finished)
+}
+
+/// Records a play in the database and appends it to the NDJSON play log.
+fn log_play(play: &Play, db_client: &mut dyn db::DBClient, log: &mut File) -> Result<(), Box<dyn Error>> {
+    let logger = utils::get_logger();
+    info!(logger, "detected {} by {} from {} to {}", play.song_title, play.song_artist, play.first_seen, play.last_seen);
+    db_client.record_play(play)?;
+    writeln!(log, "{}", serde_json::to_string(play)?)?;
+    log.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A match whose hits cover the whole of a 10 s window.
+    fn detection(song_id: u32) -> Match {
+        Match {
+            song_id,
+            song_title: format!("Song {}", song_id),
+            song_artist: "Artist".to_string(),
+            youtube_id: String::new(),
+            timestamp: 60_000,
+            offset_ms: 60_000,
+            match_start_ms: 60_000,
+            match_end_ms: 70_000,
+            score: 10.0,
+            aligned_hits: 20,
+            confidence: 0.4,
+        }
+    }
+
+    #[test]
+    fn test_tracker_debounces_repeated_detections() {
+        let options = MonitorOptions::default();
+        let mut tracker = PlayTracker::new(&options);
+        let (song1, song2) = (detection(1), detection(2));
+
+        assert!(tracker.observe(0, 10_000, Some(&song1)).is_none());
+        assert!(tracker.observe(5_000, 15_000, Some(&song1)).is_none());
+        // A missed window inside the gap tolerance does not end the play.
+        assert!(tracker.observe(10_000, 20_000, None).is_none());
+        assert!(tracker.observe(15_000, 25_000, Some(&song1)).is_none());
+
+        let play = tracker.observe(20_000, 30_000, Some(&song2)).unwrap();
+        assert_eq!((play.song.song_id, play.first_seen_ms, play.last_seen_ms, play.detections), (1, 0, 25_000, 3));
+
+        let started_at = DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z").unwrap().with_timezone(&Utc);
+        let logged = play.to_play(started_at);
+        assert_eq!(logged.first_seen, "2024-05-01T12:00:00.000Z");
+        assert_eq!(logged.last_seen, "2024-05-01T12:00:25.000Z");
+        assert!((logged.confidence - 0.4).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_tracker_places_song_starting_inside_a_window() {
+        let options = MonitorOptions::default();
+        let mut tracker = PlayTracker::new(&options);
+        // The song starts 4 s into the first window, so that window's offset is negative.
+        let starting = Match { timestamp: 0, offset_ms: -4_000, match_start_ms: 0, match_end_ms: 6_000, ..detection(1) };
+        let playing = Match { timestamp: 1_000, offset_ms: 1_000, match_start_ms: 1_000, match_end_ms: 11_000, ..detection(1) };
+        tracker.observe(0, 10_000, Some(&starting));
+        tracker.observe(5_000, 15_000, Some(&playing));
+        let play = tracker.finish().unwrap();
+        assert_eq!((play.first_seen_ms, play.last_seen_ms), (4_000, 15_000));
+    }
+
+    #[test]
+    fn test_tracker_drops_single_detections_and_closes_after_gap() {
+        let options = MonitorOptions::default();
+        let mut tracker = PlayTracker::new(&options);
+
+        // A lone false positive is never logged.
+        tracker.observe(0, 10_000, Some(&detection(7)));
+        assert!(tracker.observe(5_000, 15_000, Some(&detection(1))).is_none());
+
+        tracker.observe(10_000, 20_000, Some(&detection(1)));
+        assert!(tracker.observe(40_000, 50_000, None).is_none());
+        let play = tracker.observe(45_000, 55_000, None).unwrap();
+        assert_eq!((play.song.song_id, play.first_seen_ms, play.last_seen_ms), (1, 5_000, 20_000));
+        assert!(tracker.finish().is_none());
+    }
+
+    #[test]
+    fn test_window_queue_waits_for_complete_windows() {
+        let options = MonitorOptions { window_ms: 1_000, step_ms: 500, ..MonitorOptions::default() };
+        let mut windows = WindowQueue::new(&options, 9);
+        let couple = |anchor_time_ms| Couple { anchor_time_ms, song_id: 9 };
+        windows.extend(vec![(1, couple(100)), (2, couple(600)), (3, couple(900))], 0);
+        assert!(windows.next_window(1_200, false).is_none());
+
+        // Anchor times are taken relative to the given origin.
+        windows.extend(vec![(4, couple(600))], 500);
+        let window = windows.next_window(1_200, false).unwrap();
+        assert_eq!((window.start_ms, window.end_ms), (0, 1_000));
+        assert_eq!(window.fingerprints, vec![(1, couple(100)), (2, couple(600)), (3, couple(900))]);
+        assert!(windows.next_window(1_200, false).is_none());
+
+        // Quiet audio: the window is evaluated once the stream is far enough past its end.
+        assert!(windows.next_window(1_500 + FINALIZE_DELAY_MS, false).is_some());
+        // At the end of the stream, the remaining windows are flushed.
+        let window = windows.next_window(u64::MAX, true).unwrap();
+        assert_eq!((window.start_ms, window.fingerprints), (1_000, vec![(4, couple(100))]));
+        assert!(windows.next_window(u64::MAX, true).is_none());
+    }
+
+    #[test]
+    fn test_window_queue_runs_past_u32_milliseconds() {
+        let options = MonitorOptions { window_ms: 1_000, step_ms: 1_000, ..MonitorOptions::default() };
+        let mut windows = WindowQueue::new(&options, 9);
+        // Sixty days into the stream.
+        let day60_ms = 60 * 24 * 3_600_000;
+        windows.window_start_ms = day60_ms;
+        windows.extend(vec![(1, Couple { anchor_time_ms: 250, song_id: 9 })], day60_ms);
+        let window = windows.next_window(day60_ms + 1_000 + FINALIZE_DELAY_MS, false).unwrap();
+        assert_eq!((window.start_ms, window.end_ms), (day60_ms, day60_ms + 1_000));
+        assert_eq!(window.fingerprints, vec![(1, Couple { anchor_time_ms: 250, song_id: 9 })]);
+
+        let mut tracker = PlayTracker::new(&options);
+        tracker.observe(window.start_ms, window.end_ms, Some(&detection(1)));
+        tracker.observe(window.end_ms, window.end_ms + 1_000, Some(&detection(1)));
+        assert_eq!(tracker.finish().unwrap().first_seen_ms, day60_ms);
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use crate::shazam::config::override_from_env;
+use crate::utils;
+
+/// Selects how matched fingerprint pairs are turned into a score per song.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scorer {
+    /// Histogram of `db_time - query_time` offsets; the score is the size of the peak bin and its neighbours.
+    Histogram,
+    /// The original O(n²) scorer comparing every pair of matches, kept for comparison.
+    Pairwise,
+}
+
+/// Options controlling how query fingerprints are scored against the database.
+#[derive(Debug, Clone)]
+pub struct MatchOptions {
+    pub scorer: Scorer,
+    /// Width of an offset histogram bin, in milliseconds.
+    pub offset_bin_ms: u32,
+    /// Minimum confidence (aligned hits per query hash) for the best candidate to be accepted.
+    pub min_confidence: f64,
+    /// Minimum ratio between the score of the best candidate and that of the runner-up.
+    pub min_margin: f64,
+    /// Minimum number of time-aligned hits for the best candidate to be accepted.
+    pub min_aligned_hits: u32,
+}
+
+impl Default for MatchOptions {
+    fn default() -> Self {
+        MatchOptions {
+            scorer: Scorer::Histogram,
+            offset_bin_ms: 50,
+            min_confidence: 0.02,
+            min_margin: 1.5,
+            min_aligned_hits: 5,
+        }
+    }
+}
+
+impl MatchOptions {
+    /// Reads the options from `MATCH_SCORER` ("histogram" or "pairwise"), `MATCH_OFFSET_BIN_MS`,
+    /// `MATCH_MIN_CONFIDENCE`, `MATCH_MIN_MARGIN` and `MATCH_MIN_ALIGNED_HITS`.
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let scorer = match utils::get_env("MATCH_SCORER", Some("histogram")).as_str() {
+            "histogram" => Scorer::Histogram,
+            "pairwise" => Scorer::Pairwise,
+            other => return Err(format!("unknown match scorer: {}", other).into()),
+        };
+        let mut options = MatchOptions { scorer, ..MatchOptions::default() };
+
+        override_from_env("MATCH_OFFSET_BIN_MS", &mut options.offset_bin_ms)?;
+        override_from_env("MATCH_MIN_CONFIDENCE", &mut options.min_confidence)?;
+        override_from_env("MATCH_MIN_MARGIN", &mut options.min_margin)?;
+        override_from_env("MATCH_MIN_ALIGNED_HITS", &mut options.min_aligned_hits)?;
+        if options.offset_bin_ms == 0 {
+            return Err("MATCH_OFFSET_BIN_MS must be at least 1".into());
+        }
+
+        Ok(options)
+    }
+}
+
+/// The score of one song together with the time alignment that produced it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SongScore {
+    pub score: f64,
+    /// Number of hits in the dominant offset bin and its neighbours, whichever scorer produced `score`.
+    pub aligned_hits: u32,
+    /// Dominant `db_time - query_time` offset in milliseconds, i.e. where the query starts in the song.
+    pub offset_ms: i64,
+    /// Earliest and latest song time (ms) among the hits agreeing with the dominant offset.
+    pub span_start_ms: u32,
+    pub span_end_ms: u32,
+}
+
+/// Scores every song from its list of `[query_time, db_time]` pairs using the selected scorer.
+pub fn score_matches(matches: &HashMap<u32, Vec<[u32; 2]>>, options: &MatchOptions) -> HashMap<u32, SongScore> {
+    let mut scores = score_offset_histogram(matches, options.offset_bin_ms);
+    if options.scorer == Scorer::Pairwise {
+        for (song_id, points) in analyze_relative_timing(matches) {
+            if let Some(entry) = scores.get_mut(&song_id) {
+                entry.score = points;
+            }
+        }
+    }
+    scores
+}
+
+/// Bins the `db_time - query_time` offsets of each song and scores it by its most populated bin,
+/// counted together with its two neighbours so that hits straddling a bin edge are not split.
+/// Runs in linear time in the number of matches. The winning offset is the mean offset in those bins.
+pub fn score_offset_histogram(matches: &HashMap<u32, Vec<[u32; 2]>>, bin_ms: u32) -> HashMap<u32, SongScore> {
+    let bin_ms = bin_ms.max(1) as i64;
+    let mut scores = HashMap::new();
+
+    for (&song_id, times) in matches.iter() {
+        // bin -> (count, sum of offsets, earliest db time, latest db time)
+        let mut histogram: HashMap<i64, (u32, i64, u32, u32)> = HashMap::new();
+        for &[query_time, db_time] in times {
+            let offset = db_time as i64 - query_time as i64;
+            let entry = histogram
+                .entry(offset.div_euclid(bin_ms))
+                .or_insert((0, 0, db_time, db_time));
+            entry.0 += 1;
+            entry.1 += offset;
+            entry.2 = entry.2.min(db_time);
+            entry.3 = entry.3.max(db_time);
+        }
+
+        // Each bin together with its neighbours.
+        let windowed = histogram.keys().map(|&bin| {
+            let merged = (bin - 1..=bin + 1)
+                .filter_map(|b| histogram.get(&b))
+                .fold((0, 0, u32::MAX, 0), |acc, &(count, sum, start, end)| {
+                    (acc.0 + count, acc.1 + sum, acc.2.min(start), acc.3.max(end))
+                });
+            (bin, merged)
+        });
+
+        // Break ties towards the earliest offset so results are deterministic.
+        if let Some((_, (count, sum, span_start_ms, span_end_ms))) =
+            windowed.max_by(|a, b| a.1.0.cmp(&b.1.0).then(b.0.cmp(&a.0)))
+        {
+            scores.insert(song_id, SongScore {
+                score: count as f64,
+                aligned_hits: count,
+                offset_ms: (sum as f64 / count as f64).round() as i64,
+                span_start_ms,
+                span_end_ms,
+            });
+        }
+    }
+
+    scores
+}
+
+/// Analyzes the relative timing between matched fingerprint pairs and returns a score for each song.
+/// The score is computed as the number of pairs whose relative timing differences are within a tolerance.
+pub fn analyze_relative_timing(matches: &HashMap<u32, Vec<[u32; 2]>>) -> HashMap<u32, f64> {
+    let mut scores = HashMap::new();
+    for (&song_id, times) in matches.iter() {
+        let mut count = 0;
+        for i in 0..times.len() {
+            for j in i + 1..times.len() {
+                let sample_diff = (times[i][0] as f64 - times[j][0] as f64).abs();
+                let db_diff = (times[i][1] as f64 - times[j][1] as f64).abs();
+                if (sample_diff - db_diff).abs() < 100.0 { // Allow some tolerance
+                    count += 1;
+                }
+            }
+        }
+        scores.insert(song_id, count as f64);
+    }
+    scores
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_finds_dominant_offset() {
+        let mut matches = HashMap::new();
+        // Five hits aligned at +10 s, plus two stray hits.
+        let mut times: Vec<[u32; 2]> = (0..5).map(|i| [i * 1000, 10_000 + i * 1000 + (i % 2)]).collect();
+        times.push([0, 50_000]);
+        times.push([3000, 2000]);
+        matches.insert(1, times);
+
+        let scores = score_offset_histogram(&matches, 50);
+        let song = scores[&1];
+        assert_eq!(song.score, 5.0);
+        assert_eq!(song.offset_ms, 10_000);
+        assert_eq!((song.span_start_ms, song.span_end_ms), (10_000, 14_000));
+    }
+
+    #[test]
+    fn test_histogram_handles_negative_offsets() {
+        let mut matches = HashMap::new();
+        matches.insert(2, vec![[5000, 1000], [6000, 2000], [9000, 100]]);
+        let scores = score_offset_histogram(&matches, 50);
+        assert_eq!(scores[&2].score, 2.0);
+        assert_eq!(scores[&2].offset_ms, -4000);
+    }
+
+    #[test]
+    fn test_histogram_counts_hits_across_a_bin_edge() {
+        let mut matches = HashMap::new();
+        // Six hits around +10 s, split by the edge at 10 000 ms, and four stray hits at +30 s.
+        let mut times: Vec<[u32; 2]> = (0..6).map(|i| [i * 1000, 9_990 + (i % 2) * 20 + i * 1000]).collect();
+        times.extend((0..4).map(|i| [i * 1000, 30_000 + i * 1000]));
+        matches.insert(4, times);
+
+        let scores = score_offset_histogram(&matches, 50);
+        assert_eq!(scores[&4].aligned_hits, 6);
+        assert_eq!(scores[&4].offset_ms, 10_000);
+    }
+
+    #[test]
+    fn test_pairwise_scorer_keeps_histogram_offset() {
+        let mut matches = HashMap::new();
+        matches.insert(3, vec![[0, 1000], [1000, 2000], [2000, 3000]]);
+        let options = MatchOptions { scorer: Scorer::Pairwise, ..MatchOptions::default() };
+        let scores = score_matches(&matches, &options);
+        assert_eq!(scores[&3].score, 3.0);
+        assert_eq!(scores[&3].offset_ms, 1000);
+    }
+}
@@ -0,0 +1,316 @@
+use std::error::Error;
+
+use serde::Serialize;
+
+use crate::db;
+use crate::models::Couple;
+use crate::shazam::{fingerprint_wav_file, match_with_client, FingerprintConfig, Match, MatchOptions, Recognition};
+use crate::utils;
+
+/// Options controlling how a long recording is cut into query windows and how window
+/// matches are merged into segments.
+#[derive(Debug, Clone)]
+pub struct SegmentOptions {
+    /// Length of each query window, in milliseconds.
+    pub window_ms: u32,
+    /// Distance between the starts of consecutive windows, in milliseconds.
+    pub step_ms: u32,
+    /// How far the song's start time (in the recording) may drift between two windows
+    /// for them to still belong to the same segment.
+    pub offset_tolerance_ms: u32,
+    /// Longest stretch without a consistent match that a segment may bridge.
+    pub max_gap_ms: u32,
+}
+
+impl Default for SegmentOptions {
+    fn default() -> Self {
+        SegmentOptions {
+            window_ms: 10_000,
+            step_ms: 5_000,
+            offset_tolerance_ms: 2_000,
+            max_gap_ms: 10_000,
+        }
+    }
+}
+
+/// The best match of one query window, whose start is `window_start_ms` into the recording.
+#[derive(Debug, Clone)]
+pub struct WindowMatch {
+    pub window_start_ms: u32,
+    pub song: Match,
+}
+
+/// A stretch of the recording identified as one song.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Segment {
+    /// Start and end of the segment in the recording, in milliseconds.
+    pub start_ms: u32,
+    pub end_ms: u32,
+    pub song_id: u32,
+    pub song_title: String,
+    pub song_artist: String,
+    /// Position in the song at the start of the segment, in milliseconds.
+    pub offset_ms: u32,
+    /// Mean confidence of the windows that make up the segment.
+    pub confidence: f64,
+}
+
+/// Fingerprints a long WAV recording, matches it window by window and returns the timeline
+/// of identified songs.
+pub async fn segment_file(file_path: &str, options: &SegmentOptions) -> Result<Vec<Segment>, Box<dyn Error>> {
+    if options.window_ms == 0 || options.step_ms == 0 {
+        return Err("segment window and step must be at least 1 ms".into());
+    }
+    let config = FingerprintConfig::from_env()?;
+    let match_options = MatchOptions::from_env()?;
+
+    // Fingerprints come out of the streaming fingerprinter in anchor time order.
+    let fingerprints = fingerprint_wav_file(file_path, utils::generate_unique_id(), &config)
+        .map_err(|e| format!("failed to fingerprint {}: {}", file_path, e))?;
+    let last_anchor_ms = fingerprints.last().map_or(0, |(_, couple)| couple.anchor_time_ms);
+
+    let mut db_client = db::new_db_client().await?;
+    db::check_index_compatible(db_client.as_ref(), &config)?;
+
+    let mut windows = Vec::new();
+    let mut window_start_ms = 0;
+    while window_start_ms <= last_anchor_ms {
+        let window = window_fingerprints(&fingerprints, window_start_ms, options.window_ms);
+        if let Recognition::Match { song, .. } = match_with_client(db_client.as_ref(), &window, &match_options)? {
+            windows.push(WindowMatch { window_start_ms, song });
+        }
+        window_start_ms = window_start_ms.saturating_add(options.step_ms);
+    }
+    db_client.close()?;
+
+    Ok(build_segments(&windows, options))
+}
+
+/// Returns the fingerprints anchored within `[start_ms, start_ms + length_ms)`, with their
+/// anchor times made relative to the window start.
+fn window_fingerprints(fingerprints: &[(u32, Couple)], start_ms: u32, length_ms: u32) -> Vec<(u32, Couple)> {
+    let end_ms = start_ms.saturating_add(length_ms);
+    let first = fingerprints.partition_point(|(_, couple)| couple.anchor_time_ms < start_ms);
+    let last = fingerprints.partition_point(|(_, couple)| couple.anchor_time_ms < end_ms);
+    fingerprints[first..last]
+        .iter()
+        .map(|(address, couple)| {
+            (*address, Couple { anchor_time_ms: couple.anchor_time_ms - start_ms, song_id: couple.song_id })
+        })
+        .collect()
+}
+
+/// Merges window matches, sorted by window start, into segments. Consecutive windows join
+/// the same segment when they match the same song, agree on where the song started in the
+/// recording, and are no more than `max_gap_ms` apart.
+pub fn build_segments(windows: &[WindowMatch], options: &SegmentOptions) -> Vec<Segment> {
+    // Segment under construction: (song start in the recording, start, end, confidence sum,
+    // window count, first window's match).
+    let mut current: Option<(i64, i64, i64, f64, u32, &Match)> = None;
+    let mut segments = Vec::new();
+
+    let finish = |(song_start, start, end, confidence_sum, count, song): (i64, i64, i64, f64, u32, &Match)| {
+        let start = start.max(0);
+        Segment {
+            start_ms: start as u32,
+            end_ms: end.max(start) as u32,
+            song_id: song.song_id,
+            song_title: song.song_title.clone(),
+            song_artist: song.song_artist.clone(),
+            offset_ms: (start - song_start).max(0) as u32,
+            confidence: confidence_sum / count as f64,
+        }
+    };
+
+    for window in windows {
+        let song = &window.song;
+        // Where position 0 of the song falls in the recording, and the matched hits around it.
+        let song_start = window.window_start_ms as i64 - song.offset_ms;
+        let hit_start = song_start + song.match_start_ms as i64;
+        let hit_end = song_start + song.match_end_ms as i64;
+
+        if let Some(segment) = current.as_mut()
+            && segment.5.song_id == song.song_id
+            && (song_start - segment.0).abs() <= options.offset_tolerance_ms as i64
+            && hit_start <= segment.2 + options.max_gap_ms as i64
+        {
+            segment.2 = segment.2.max(hit_end);
+            segment.3 += song.confidence;
+            segment.4 += 1;
+            continue;
+        }
+
+        if let Some(segment) = current.take() {
+            segments.push(finish(segment));
+        }
+        current = Some((song_start, hit_start, hit_end, song.confidence, 1, song));
+    }
+    if let Some(segment) = current {
+        segments.push(finish(segment));
+    }
+    segments
+}
+
+/// Renders the timeline as a pretty-printed JSON array.
+pub fn timeline_to_json(segments: &[Segment]) -> Result<String, Box<dyn Error>> {
+    Ok(serde_json::to_string_pretty(segments)?)
+}
+
+/// Renders the timeline as CSV with a header row; times are in milliseconds.
+pub fn timeline_to_csv(segments: &[Segment]) -> String {
+    let mut csv = String::from("start_ms,end_ms,song_id,title,artist,offset_ms,confidence\n");
+    for segment in segments {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{:.4}\n",
+            segment.start_ms,
+            segment.end_ms,
+            segment.song_id,
+            csv_field(&segment.song_title),
+            csv_field(&segment.song_artist),
+            segment.offset_ms,
+            segment.confidence
+        ));
+    }
+    csv
+}
+
+/// Quotes a CSV field when it contains a separator, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Renders the timeline as a CUE sheet for `audio_file`, one track per segment.
+pub fn timeline_to_cue(segments: &[Segment], audio_file: &str) -> String {
+    let mut cue = format!("FILE \"{}\" WAVE\n", cue_string(audio_file));
+    for (i, segment) in segments.iter().enumerate() {
+        cue.push_str(&format!("  TRACK {:02} AUDIO\n", i + 1));
+        cue.push_str(&format!("    TITLE \"{}\"\n", cue_string(&segment.song_title)));
+        cue.push_str(&format!("    PERFORMER \"{}\"\n", cue_string(&segment.song_artist)));
+        cue.push_str(&format!("    INDEX 01 {}\n", cue_timestamp(segment.start_ms)));
+    }
+    cue
+}
+
+/// CUE strings cannot contain double quotes.
+fn cue_string(value: &str) -> String {
+    value.replace('"', "'")
+}
+
+/// Formats a time as the CUE `mm:ss:ff` timestamp, where a frame is 1/75 s.
+fn cue_timestamp(ms: u32) -> String {
+    let frames = ms as u64 * 75 / 1000;
+    format!("{:02}:{:02}:{:02}", frames / (75 * 60), frames / 75 % 60, frames % 75)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A window match whose song position at the window start is `offset_ms`, with hits over
+    /// the first `span_ms` of the song heard in the window.
+    fn window(window_start_ms: u32, song_id: u32, offset_ms: i64, span_ms: u32) -> WindowMatch {
+        let match_start_ms = offset_ms.max(0) as u32;
+        WindowMatch {
+            window_start_ms,
+            song: Match {
+                song_id,
+                song_title: format!("Song {}", song_id),
+                song_artist: "Artist".to_string(),
+                youtube_id: String::new(),
+                timestamp: offset_ms.max(0) as u32,
+                offset_ms,
+                match_start_ms,
+                match_end_ms: match_start_ms + span_ms,
+                score: 10.0,
+                aligned_hits: 20,
+                confidence: 0.5,
+            },
+        }
+    }
+
+    #[test]
+    fn test_build_segments_merges_consistent_windows() {
+        let options = SegmentOptions::default();
+        // Song 1 plays from 0 s (starting 30 s into the song), song 2 from 20 s.
+        let windows = vec![
+            window(0, 1, 30_000, 9_000),
+            window(5_000, 1, 35_000, 9_000),
+            window(10_000, 1, 40_000, 8_000),
+            window(20_000, 2, 0, 9_000),
+            window(25_000, 2, 5_000, 9_000),
+        ];
+        let segments = build_segments(&windows, &options);
+
+        assert_eq!(segments.len(), 2);
+        assert_eq!((segments[0].song_id, segments[0].start_ms, segments[0].end_ms), (1, 0, 18_000));
+        assert_eq!(segments[0].offset_ms, 30_000);
+        assert_eq!((segments[1].song_id, segments[1].start_ms, segments[1].end_ms), (2, 20_000, 34_000));
+        assert_eq!(segments[1].offset_ms, 0);
+    }
+
+    #[test]
+    fn test_build_segments_splits_on_offset_jump_and_long_gap() {
+        let options = SegmentOptions::default();
+        let windows = vec![
+            window(0, 1, 0, 9_000),
+            // The same song restarted from the beginning.
+            window(5_000, 1, 0, 9_000),
+            // Played again much later.
+            window(60_000, 1, 55_000, 9_000),
+        ];
+        let segments = build_segments(&windows, &options);
+        assert_eq!(segments.len(), 3);
+        assert_eq!(segments[1].start_ms, 5_000);
+        assert_eq!(segments[2].offset_ms, 55_000);
+    }
+
+    #[test]
+    fn test_build_segments_song_starting_inside_a_window() {
+        let options = SegmentOptions::default();
+        // The song starts 3 s into the first window, so that window's offset is negative.
+        let windows = vec![
+            window(0, 1, -3_000, 6_000),
+            window(5_000, 1, 2_000, 9_000),
+            window(10_000, 1, 7_000, 9_000),
+        ];
+        let segments = build_segments(&windows, &options);
+        assert_eq!(segments.len(), 1);
+        assert_eq!((segments[0].start_ms, segments[0].end_ms, segments[0].offset_ms), (3_000, 19_000, 0));
+    }
+
+    #[test]
+    fn test_window_fingerprints_rebases_anchor_times() {
+        let couple = |anchor_time_ms| Couple { anchor_time_ms, song_id: 9 };
+        let fingerprints = vec![(1, couple(0)), (2, couple(4_000)), (3, couple(5_000)), (4, couple(12_000))];
+        let window = window_fingerprints(&fingerprints, 4_000, 5_000);
+        assert_eq!(window, vec![(2, couple(0)), (3, couple(1_000))]);
+    }
+
+    #[test]
+    fn test_timeline_formats() {
+        let segments = vec![Segment {
+            start_ms: 61_500,
+            end_ms: 200_000,
+            song_id: 3,
+            song_title: "Hello, \"World\"".to_string(),
+            song_artist: "Band".to_string(),
+            offset_ms: 1_000,
+            confidence: 0.25,
+        }];
+
+        let csv = timeline_to_csv(&segments);
+        assert_eq!(csv.lines().nth(1), Some("61500,200000,3,\"Hello, \"\"World\"\"\",Band,1000,0.2500"));
+
+        let cue = timeline_to_cue(&segments, "mix.wav");
+        assert!(cue.starts_with("FILE \"mix.wav\" WAVE\n  TRACK 01 AUDIO\n"));
+        assert!(cue.contains("    TITLE \"Hello, 'World'\"\n"));
+        assert!(cue.contains("    INDEX 01 01:01:37\n"));
+
+        let json: serde_json::Value = serde_json::from_str(&timeline_to_json(&segments).unwrap()).unwrap();
+        assert_eq!(json[0]["offset_ms"], 1_000);
+    }
+}
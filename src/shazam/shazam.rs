@@ -2,7 +2,6 @@ use std::collections::HashMap;
 use std::error::Error;
 use std::time::{Duration, Instant};
 use serde::Serialize;
-use crate::wav;
 
 use crate::db;
 use crate::models::Couple;
@@ -10,89 +9,162 @@ use crate::db::Song;
 use slog::info;
 
 // Assumes Song has fields: title, artist, youtube_id, etc.
-use crate::shazam::{extract_peaks, fingerprint, spectrogram, Peak};
+use crate::shazam::{extract_peaks, fingerprint, fingerprint_audio_file, score_matches, spectrogram, FingerprintConfig, MatchOptions};
 use crate::utils;
 
 // Represents a matching song from the database.
 
 
-#[derive(Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Match {
     pub song_id: u32,
     pub song_title: String,
     pub song_artist: String,
     pub youtube_id: String,
+    /// Position in the song (ms) where the query clip starts, from the dominant time offset.
     pub timestamp: u32,
+    /// The same offset unclamped: negative when the clip starts before the song does.
+    pub offset_ms: i64,
+    /// Span of the song (ms) covered by the hits that agree with that offset.
+    pub match_start_ms: u32,
+    pub match_end_ms: u32,
     pub score: f64,
+    /// Number of hits at the dominant offset. Can exceed the number of query hashes when an
+    /// address repeats in both the query and the song.
+    pub aligned_hits: u32,
+    /// Share of the query's hashes that hit this song at the dominant offset, in [0, 1].
+    pub confidence: f64,
 }
 
-pub async fn find_matches_for_api(file_path: &str) -> Result<Vec<Match>, Box<dyn Error>> {
-    let wav_info = wav::read_wav_info(file_path)?;
-    let samples = wav::wav_bytes_to_samples(&wav_info.data)?;
-    
-    let (matches, _) = find_matches(&samples, wav_info.duration, wav_info.sample_rate).await?;
-    Ok(matches)
+/// The outcome of a search: either an accepted song or an explicit "no match".
+/// Both carry the ranked candidates so callers can still show what came close.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Recognition {
+    Match { song: Match, candidates: Vec<Match> },
+    NoMatch { reason: String, candidates: Vec<Match> },
+}
+
+impl Recognition {
+    /// Returns the accepted song, if any.
+    pub fn best(&self) -> Option<&Match> {
+        match self {
+            Recognition::Match { song, .. } => Some(song),
+            Recognition::NoMatch { .. } => None,
+        }
+    }
+
+    /// Returns the ranked candidates, best first.
+    pub fn candidates(&self) -> &[Match] {
+        match self {
+            Recognition::Match { candidates, .. } | Recognition::NoMatch { candidates, .. } => candidates,
+        }
+    }
+
+    /// Keeps only the first `n` candidates.
+    pub fn truncate_candidates(&mut self, n: usize) {
+        match self {
+            Recognition::Match { candidates, .. } | Recognition::NoMatch { candidates, .. } => candidates.truncate(n),
+        }
+    }
+}
+
+pub async fn find_matches_for_api(file_path: &str) -> Result<Recognition, Box<dyn Error>> {
+    let (recognition, _) = find_matches_in_file(file_path).await?;
+    Ok(recognition)
+}
+
+/// Fingerprints an audio file (streaming WAV files without loading them into memory) and
+/// finds matching songs from the database. Returns the same as `find_matches`.
+pub async fn find_matches_in_file(file_path: &str) -> Result<(Recognition, Duration), Box<dyn Error>> {
+    let start_time = Instant::now();
+    let config = FingerprintConfig::from_env()?;
+    let fingerprints = fingerprint_audio_file(file_path, utils::generate_unique_id(), &config)
+        .map_err(|e| format!("failed to fingerprint {}: {}", file_path, e))?;
+
+    let recognition = match_fingerprints(&fingerprints, &config).await?;
+    Ok((recognition, start_time.elapsed()))
 }
 
 /// Processes the audio samples and finds matching songs from the database.
-/// Returns a list of matches sorted in descending order by score along with the duration
-/// of the search.
+/// Returns the recognition decision, with candidates sorted in descending order by score,
+/// along with the duration of the search.
 pub async fn find_matches(
     audio_samples: &[f64],
-    audio_duration: f64,
     sample_rate: i32,
-) -> Result<(Vec<Match>, Duration), Box<dyn Error>> {
+) -> Result<(Recognition, Duration), Box<dyn Error>> {
     let start_time = Instant::now();
-    let logger = utils::get_logger();
+    let config = FingerprintConfig::from_env()?;
 
     // Get the spectrogram of the audio samples.
-    let spectro = spectrogram(audio_samples, sample_rate)
+    let spectro = spectrogram(audio_samples, sample_rate, &config)
         .map_err(|e| format!("failed to get spectrogram of samples: {}", e))?;
     // Extract peaks from the spectrogram.
-    let peaks = extract_peaks(&spectro, audio_duration);
+    let peaks = extract_peaks(&spectro, &config);
     // Generate fingerprints using a unique song ID.
-    let fingerprints = fingerprint(&peaks, utils::generate_unique_id());
+    let fingerprints = fingerprint(&peaks, utils::generate_unique_id(), &config);
 
-    // Collect all fingerprint addresses.
-    let addresses: Vec<u32> = fingerprints.keys().cloned().collect();
+    let recognition = match_fingerprints(&fingerprints, &config).await?;
+    Ok((recognition, start_time.elapsed()))
+}
+
+/// Looks up the query fingerprints in the database, scores every song sharing them and
+/// decides on a recognition. `config` must be the config the fingerprints were made with.
+pub async fn match_fingerprints(
+    fingerprints: &[(u32, Couple)],
+    config: &FingerprintConfig,
+) -> Result<Recognition, Box<dyn Error>> {
+    let options = MatchOptions::from_env()?;
 
     let mut db_client = db::new_db_client().await?;
+    // Make sure the stored fingerprints were produced by the same pipeline.
+    db::check_index_compatible(db_client.as_ref(), config)?;
+    let recognition = match_with_client(db_client.as_ref(), fingerprints, &options);
+    // Close the DB client once we're done.
+    db_client.close()?;
+    recognition
+}
+
+/// Scores the query fingerprints against the songs in `db_client` and decides on a
+/// recognition. The caller is responsible for checking the index metadata.
+pub fn match_with_client(
+    db_client: &dyn db::DBClient,
+    fingerprints: &[(u32, Couple)],
+    options: &MatchOptions,
+) -> Result<Recognition, Box<dyn Error>> {
+    // Group the query's anchor times by address; an address may occur at several times.
+    let mut query_times: HashMap<u32, Vec<u32>> = HashMap::new();
+    for (address, couple) in fingerprints.iter() {
+        query_times.entry(*address).or_default().push(couple.anchor_time_ms);
+    }
+    let addresses: Vec<u32> = query_times.keys().cloned().collect();
+
     // Query the database to get couples (fingerprint matches) for the addresses.
     let couples_map = db_client.get_couples(&addresses)?;
-    // Close the DB client once we're done.
-    db_client.close();
 
     // Build maps for relative timing analysis.
     let mut matches_map: HashMap<u32, Vec<[u32; 2]>> = HashMap::new(); // song_id -> list of [sample_time, db_time]
-    let mut timestamps: HashMap<u32, Vec<u32>> = HashMap::new();
 
     // Iterate over each fingerprint address found in the database.
     for (&address, couples) in couples_map.iter() {
+        let sample_times = query_times.get(&address).map(Vec::as_slice).unwrap_or_default();
         // For each couple (from the database) corresponding to this fingerprint:
         for couple in couples {
-            // Here, fingerprints[address] must have an anchor time in ms.
-            // We assume the Couple struct in models has a field `anchor_time` of type u32.
-            let anchor_time_ms = fingerprints.get(&address)
-                .map(|couple| couple.anchor_time_ms)
-                .unwrap_or(0);
-            // Add the pair [sample_time, db_time] into the matches_map for this song.
-            matches_map.entry(couple.song_id)
-                .or_insert_with(Vec::new)
-                .push([anchor_time_ms, couple.anchor_time_ms]);
-            timestamps.entry(couple.song_id)
-                .or_insert_with(Vec::new)
-                .push(couple.anchor_time_ms);
+            // Pair it with every occurrence of the address in the query.
+            let song_matches = matches_map.entry(couple.song_id).or_default();
+            for &sample_time in sample_times {
+                song_matches.push([sample_time, couple.anchor_time_ms]);
+            }
         }
     }
 
-    // Analyze relative timing to produce a score for each song.
-    let scores = analyze_relative_timing(&matches_map);
+    // Score each song by how consistently its hits line up in time.
+    let scores = score_matches(&matches_map, options);
 
     let mut match_list = Vec::new();
 
     // For each song with a score, fetch its metadata from the database.
-    let mut db_client = db::new_db_client().await?;
-    for (&song_id, &points) in scores.iter() {
+    for (&song_id, song_score) in scores.iter() {
         let (song, song_exists) = db_client.get_song_by_id(song_id)?;
         if !song_exists {
             let logger = utils::get_logger();
@@ -100,47 +172,153 @@ pub async fn find_matches(
 
             continue;
         }
-        // Sort the timestamps for the song in ascending order.
-        if let Some(ts) = timestamps.get_mut(&song_id) {
-            ts.sort_unstable();
-        }
-        let timestamp = timestamps.get(&song_id)
-            .and_then(|ts| ts.first().cloned())
-            .unwrap_or(0);
         let m = Match {
             song_id,
             song_title: song.title,
             song_artist: song.artist,
             youtube_id: song.youtube_id,
-            timestamp,
-            score: points,
+            // A clip that starts before the song does is reported as starting at 0.
+            timestamp: song_score.offset_ms.max(0) as u32,
+            offset_ms: song_score.offset_ms,
+            match_start_ms: song_score.span_start_ms,
+            match_end_ms: song_score.span_end_ms,
+            score: song_score.score,
+            aligned_hits: song_score.aligned_hits,
+            confidence: confidence(song_score.aligned_hits, fingerprints.len()),
         };
         match_list.push(m);
     }
-    db_client.close();
 
-    // Sort match_list in descending order by score.
-    match_list.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+    Ok(recognize(match_list, fingerprints.len(), options))
+}
 
-    Ok((match_list, start_time.elapsed()))
+/// Normalizes a song's aligned hits by the number of hashes in the query, so that the value
+/// does not grow with the length of the clip.
+fn confidence(aligned_hits: u32, query_hashes: usize) -> f64 {
+    if query_hashes == 0 {
+        return 0.0;
+    }
+    (aligned_hits as f64 / query_hashes as f64).min(1.0)
 }
 
-/// Analyzes the relative timing between matched fingerprint pairs and returns a score for each song.
-/// The score is computed as the number of pairs whose relative timing differences are within a tolerance.
-fn analyze_relative_timing(matches: &HashMap<u32, Vec<[u32; 2]>>) -> HashMap<u32, f64> {
-    let mut scores = HashMap::new();
-    for (&song_id, times) in matches.iter() {
-        let mut count = 0;
-        for i in 0..times.len() {
-            for j in i + 1..times.len() {
-                let sample_diff = (times[i][0] as f64 - times[j][0] as f64).abs();
-                let db_diff = (times[i][1] as f64 - times[j][1] as f64).abs();
-                if (sample_diff - db_diff).abs() < 100.0 { // Allow some tolerance
-                    count += 1;
-                }
-            }
+/// Ranks the candidates by the scorer's score and accepts the best one only if it is confident
+/// enough and its score is clearly ahead of the runner-up's. With the histogram scorer the score
+/// is the aligned hit count, so unlike the confidence it does not saturate on long matches.
+fn recognize(mut candidates: Vec<Match>, query_hashes: usize, options: &MatchOptions) -> Recognition {
+    let no_match = |reason: String, candidates: Vec<Match>| Recognition::NoMatch { reason, candidates };
+
+    candidates.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then(b.aligned_hits.cmp(&a.aligned_hits))
+            .then(b.confidence.total_cmp(&a.confidence))
+    });
+
+    if query_hashes == 0 {
+        return no_match("no fingerprints could be extracted from the query".to_string(), candidates);
+    }
+    let best = match candidates.first() {
+        Some(best) => best.clone(),
+        None => return no_match("no song shares fingerprints with the query".to_string(), candidates),
+    };
+
+    if best.aligned_hits < options.min_aligned_hits {
+        return no_match(
+            format!("best candidate has {} aligned hits, need {}", best.aligned_hits, options.min_aligned_hits),
+            candidates,
+        );
+    }
+    if best.confidence < options.min_confidence {
+        return no_match(
+            format!("best candidate confidence {:.3} is below {:.3}", best.confidence, options.min_confidence),
+            candidates,
+        );
+    }
+    if let Some(runner_up) = candidates.get(1)
+        && runner_up.score > 0.0
+        && best.score < runner_up.score * options.min_margin
+    {
+        return no_match(
+            format!(
+                "best candidate score {} is not clearly ahead of the runner-up ({})",
+                best.score, runner_up.score
+            ),
+            candidates,
+        );
+    }
+
+    Recognition::Match { song: best, candidates }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shazam::Scorer;
+
+    fn candidate(song_id: u32, confidence: f64) -> Match {
+        Match {
+            song_id,
+            song_title: format!("song {}", song_id),
+            song_artist: "artist".to_string(),
+            youtube_id: String::new(),
+            timestamp: 0,
+            offset_ms: 0,
+            match_start_ms: 0,
+            match_end_ms: 0,
+            score: confidence * 1000.0,
+            // As if the query had 1000 hashes.
+            aligned_hits: (confidence * 1000.0).round() as u32,
+            confidence,
         }
-        scores.insert(song_id, count as f64);
     }
-    scores
+
+    #[test]
+    fn test_recognize_accepts_clear_winner() {
+        let options = MatchOptions::default();
+        let recognition = recognize(vec![candidate(1, 0.2), candidate(2, 0.01)], 1000, &options);
+        assert_eq!(recognition.best().map(|m| m.song_id), Some(1));
+        assert_eq!(recognition.candidates().len(), 2);
+    }
+
+    #[test]
+    fn test_recognize_rejects_weak_or_ambiguous_results() {
+        let options = MatchOptions::default();
+        // Nothing matched at all.
+        assert!(recognize(vec![], 1000, &options).best().is_none());
+        // Below the confidence threshold.
+        assert!(recognize(vec![candidate(1, 0.01)], 1000, &options).best().is_none());
+        // Too close to the runner-up.
+        assert!(recognize(vec![candidate(1, 0.2), candidate(2, 0.18)], 1000, &options).best().is_none());
+        // Too few aligned hits for a tiny query, even with a high ratio.
+        assert!(recognize(vec![Match { aligned_hits: 2, ..candidate(1, 0.5) }], 4, &options).best().is_none());
+    }
+
+    #[test]
+    fn test_recognize_ranks_and_decides_on_score() {
+        let options = MatchOptions::default();
+        // Confidence saturates at 1, but the aligned hits behind the score still decide.
+        let saturated = Match { score: 30.0, aligned_hits: 30, ..candidate(3, 1.0) };
+        let more = Match { score: 45.0, aligned_hits: 45, ..candidate(4, 1.0) };
+        let recognition = recognize(vec![saturated.clone(), more], 20, &options);
+        assert_eq!(recognition.best().map(|m| m.song_id), Some(4));
+        assert_eq!(recognition.candidates()[1].song_id, 3);
+
+        // Both saturated and close together is still ambiguous.
+        let close = Match { score: 40.0, aligned_hits: 40, ..candidate(5, 1.0) };
+        assert!(recognize(vec![saturated, close], 20, &options).best().is_none());
+
+        // The pairwise scorer's score picks the winner over the raw hit count.
+        let options = MatchOptions { scorer: Scorer::Pairwise, ..MatchOptions::default() };
+        let consistent = Match { score: 500.0, aligned_hits: 40, ..candidate(6, 0.2) };
+        let scattered = Match { score: 100.0, aligned_hits: 50, ..candidate(7, 0.25) };
+        let recognition = recognize(vec![scattered, consistent], 200, &options);
+        assert_eq!(recognition.best().map(|m| m.song_id), Some(6));
+    }
+
+    #[test]
+    fn test_confidence_is_normalized() {
+        assert_eq!(confidence(0, 0), 0.0);
+        assert_eq!(confidence(50, 100), 0.5);
+        assert_eq!(confidence(200, 100), 1.0);
+    }
 }
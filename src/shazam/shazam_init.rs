@@ -6,7 +6,7 @@ use crate::db;
 use crate::models;
 use crate::db::Song;
 use crate::models::Couple;
-use crate::shazam::{extract_peaks, fingerprint, spectrogram};
+use crate::shazam::{extract_peaks, fingerprint, spectrogram, FingerprintConfig};
 use crate::utils;
 use crate::shazam::fingerprint::Peak;
 use slog::info;
@@ -25,20 +25,25 @@ pub struct Match1 {
 /// Returns a list of matches sorted in descending order of coherency.
 pub async fn search(
     audio_samples: &[f64],
-    audio_duration: f64,
     sample_rate: i32,
 ) -> Result<Vec<Match1>, Box<dyn Error>> {
+    let config = FingerprintConfig::from_env()?;
+
     // Compute spectrogram.
-    let spectrogram = spectrogram(audio_samples, sample_rate)
+    let spectrogram = spectrogram(audio_samples, sample_rate, &config)
         .map_err(|e| format!("failed to get spectrogram of samples: {}", e))?;
     
     // Extract peaks from the spectrogram.
-    let peaks = extract_peaks(&spectrogram, audio_duration);
+    let peaks = extract_peaks(&spectrogram, &config);
     // Generate fingerprints using a unique song ID.
-    let fingerprints = fingerprint(&peaks, utils::generate_unique_id());
+    let fingerprints = fingerprint(&peaks, utils::generate_unique_id(), &config);
     
-    // Collect fingerprint addresses.
-    let addresses: Vec<u32> = fingerprints.keys().cloned().collect();
+    // Group the query's anchor times by address.
+    let mut query_times: HashMap<u32, Vec<u32>> = HashMap::new();
+    for (address, couple) in fingerprints.iter() {
+        query_times.entry(*address).or_default().push(couple.anchor_time_ms);
+    }
+    let addresses: Vec<u32> = query_times.keys().cloned().collect();
 
     let mut db_client = db::new_db_client().await?;
     // Get couples from the database.
@@ -49,15 +54,14 @@ pub async fn search(
     let mut matches_map: HashMap<u32, Vec<[u32; 2]>> = HashMap::new(); // song_id -> list of [sample_time, db_time]
     let mut timestamps: HashMap<u32, Vec<u32>> = HashMap::new();
 
-    for (_address, couples) in couples_map.iter() {
+    for (address, couples) in couples_map.iter() {
+        let sample_times = query_times.get(address).map(Vec::as_slice).unwrap_or_default();
         for couple in couples {
-            // Use the anchor time from our fingerprint (for the given address).
-            // If not found, default to 0.
-            let sample_time = fingerprints.get(&_address).map(|c| c.anchor_time_ms).unwrap_or(0);
-            matches_map
-                .entry(couple.song_id)
-                .or_insert_with(Vec::new)
-                .push([sample_time, couple.anchor_time_ms]);
+            // Pair the database time with every anchor time of this address in the query.
+            let song_matches = matches_map.entry(couple.song_id).or_default();
+            for &sample_time in sample_times {
+                song_matches.push([sample_time, couple.anchor_time_ms]);
+            }
             timestamps
                 .entry(couple.song_id)
                 .or_insert_with(Vec::new)
@@ -155,14 +159,14 @@ fn target_zones(couples_map: &HashMap<u32, Vec<models::Couple>>) -> HashMap<u32,
 
 /// Computes time coherency between the fingerprint record and target zones.
 /// For each song, compares each target anchor time with every record anchor time and counts matches.
-fn time_coherency(record: &HashMap<u32, models::Couple>, songs: &HashMap<u32, Vec<u32>>) -> HashMap<u32, i32> {
+fn time_coherency(record: &[(u32, models::Couple)], songs: &HashMap<u32, Vec<u32>>) -> HashMap<u32, i32> {
     let mut matches = HashMap::new();
 
     for (&song_id, song_anchor_times) in songs.iter() {
         // Use quantization to handle floating point keys
         let mut deltas = HashMap::new();
         for &song_anchor_time in song_anchor_times.iter() {
-            for couple in record.values() {
+            for (_, couple) in record.iter() {
                 let record_anchor_time = couple.anchor_time_ms as f64;
                 let delta = record_anchor_time - song_anchor_time as f64;
                 // Convert to integer by quantizing (round to nearest integer)
@@ -1,160 +1,408 @@
 use num_complex::Complex;
+use std::collections::VecDeque;
 use std::error::Error;
 use std::f64::consts::PI;
 
-use crate::shazam::filter::LowPassFilter; // Assumes a LowPassFilter struct with a `filter(&[f64]) -> Vec<f64>` method.
-use crate::shazam::fft::fft;      // Assumes an FFT function: `fn fft(input: &[f64]) -> Vec<Complex<f64>>`
+use crate::shazam::filter::ButterworthFilter;
+use crate::shazam::fft::RealFft;
 use crate::shazam::fingerprint::Peak;
-// Constants
-const DSP_RATIO: i32 = 4;
-const FREQ_BIN_SIZE: usize = 1024;
-const MAX_FREQ: f64 = 5000.0; // 5kHz
-const HOP_SIZE: usize = FREQ_BIN_SIZE / 32;
+use crate::shazam::config::FingerprintConfig;
 
 /// Computes the spectrogram (STFT) of the input audio samples.
-/// Returns a two-dimensional vector where each row is the FFT of a windowed segment.
-pub fn spectrogram(samples: &[f64], sample_rate: i32) -> Result<Vec<Vec<Complex<f64>>>, Box<dyn Error>> {
-    // Apply a low-pass filter
-    let mut lpf = LowPassFilter::new(MAX_FREQ, sample_rate as f64);
-    let filtered_samples = lpf.filter(samples);
-
-    // Downsample the filtered samples.
-    let target_sample_rate = sample_rate / DSP_RATIO;
-    let downsampled_samples = downsample(&filtered_samples, sample_rate, target_sample_rate)
-        .map_err(|e| format!("couldn't downsample audio samples: {}", e))?;
-
-    // Compute number of windows for the spectrogram.
-    let window_length = FREQ_BIN_SIZE;
-    let hop = HOP_SIZE;
-    let num_of_windows = downsampled_samples.len() / (window_length - hop);
+/// Returns a two-dimensional vector where each row holds the non-redundant half
+/// (`freq_bin_size / 2 + 1` bins) of the FFT of a windowed segment.
+pub fn spectrogram(
+    samples: &[f64],
+    sample_rate: i32,
+    config: &FingerprintConfig,
+) -> Result<Vec<Vec<Complex<f64>>>, Box<dyn Error>> {
+    // Band-limit the signal before resampling.
+    let filtered_samples = match band_limit_filter(sample_rate, config)? {
+        Some(mut filter) if config.zero_phase_filter => filter.filter_zero_phase(samples),
+        Some(mut filter) => filter.filter(samples),
+        None => samples.to_vec(),
+    };
+
+    // Resample the filtered samples to the analysis rate.
+    let downsampled_samples = resample(&filtered_samples, sample_rate, config.analysis_sample_rate)
+        .map_err(|e| format!("couldn't resample audio samples: {}", e))?;
+
+    let window_length = config.freq_bin_size;
+    let hop = config.hop();
+    let num_of_windows = frame_count(downsampled_samples.len(), config);
     let mut spectrogram = Vec::with_capacity(num_of_windows);
+    let window = config.window.coefficients(window_length);
 
-    // Create a Hamming window.
-    let window: Vec<f64> = (0..window_length)
-        .map(|i| 0.54 - 0.46 * ((2.0 * PI * i as f64) / ((window_length - 1) as f64)).cos())
-        .collect();
+    // Plan the FFT once and reuse it, along with the frame buffer, for every window.
+    let mut planned_fft = RealFft::new(window_length)?;
+    let mut bin = vec![0.0; window_length];
 
     // Perform STFT.
     for i in 0..num_of_windows {
         let start = i * hop;
-        let mut end = start + window_length;
-        if end > downsampled_samples.len() {
-            end = downsampled_samples.len();
-        }
-        let mut bin = vec![0.0; window_length];
-        // Copy available samples into bin.
-        bin[..(end - start)].copy_from_slice(&downsampled_samples[start..end]);
+        let frame = &downsampled_samples[start..start + window_length];
 
-        // Apply the Hamming window.
-        for j in 0..window_length {
-            bin[j] *= window[j];
+        // Copy the frame into bin and apply the window.
+        for ((sample, &x), &w) in bin.iter_mut().zip(frame).zip(&window) {
+            *sample = x * w;
         }
 
         // Compute the FFT for this bin.
-        let fft_result = fft(&bin);
+        let mut fft_result = vec![Complex::new(0.0, 0.0); planned_fft.output_len()];
+        planned_fft.process(&bin, &mut fft_result)?;
         spectrogram.push(fft_result);
     }
 
     Ok(spectrogram)
 }
 
-/// Downsamples the input audio from the original sample rate to the target sample rate.
-pub fn downsample(input: &[f64], original_sample_rate: i32, target_sample_rate: i32) -> Result<Vec<f64>, Box<dyn Error>> {
-    if target_sample_rate <= 0 || original_sample_rate <= 0 {
-        return Err("sample rates must be positive".into());
+/// Number of STFT frames for a signal of `num_samples` samples at the analysis rate.
+/// Only frames that fit completely are computed, so a signal shorter than one window has none.
+pub fn frame_count(num_samples: usize, config: &FingerprintConfig) -> usize {
+    if num_samples < config.freq_bin_size {
+        0
+    } else {
+        1 + (num_samples - config.freq_bin_size) / config.hop()
+    }
+}
+
+/// Time, in seconds, at which the frame with the given index starts.
+pub fn frame_time(frame_index: usize, config: &FingerprintConfig) -> f64 {
+    (frame_index * config.hop()) as f64 / config.analysis_sample_rate as f64
+}
+
+/// Center frequency, in Hz, of the FFT bin with the given index.
+pub fn bin_frequency(bin: usize, config: &FingerprintConfig) -> f64 {
+    bin as f64 * config.analysis_sample_rate as f64 / config.freq_bin_size as f64
+}
+
+/// Builds the Butterworth filter limiting the input to `[min_freq, max_freq]`. The low-pass is
+/// skipped when `max_freq` is at or above the input's Nyquist frequency, since the signal cannot
+/// contain anything above it; returns `None` when no filtering is needed at all.
+pub fn band_limit_filter(sample_rate: i32, config: &FingerprintConfig) -> Result<Option<ButterworthFilter>, Box<dyn Error>> {
+    let sample_rate = sample_rate as f64;
+    let low_pass = config.max_freq < sample_rate / 2.0;
+    let high_pass = config.min_freq > 0.0;
+    let filter = match (high_pass, low_pass) {
+        (true, true) => ButterworthFilter::band_pass(config.filter_order, config.min_freq, config.max_freq, sample_rate)?,
+        (false, true) => ButterworthFilter::low_pass(config.filter_order, config.max_freq, sample_rate)?,
+        (true, false) => ButterworthFilter::high_pass(config.filter_order, config.min_freq, sample_rate)?,
+        (false, false) => return Ok(None),
+    };
+    Ok(Some(filter))
+}
+
+/// Number of zero crossings of the sinc kernel on each side of the interpolation point.
+const SINC_ZERO_CROSSINGS: usize = 16;
+/// Number of precomputed kernel values per zero crossing; values in between are interpolated.
+const SINC_TABLE_RESOLUTION: usize = 512;
+
+/// Resamples the input audio from the original sample rate to the target sample rate using
+/// Blackman-windowed sinc interpolation, so any rate (including non-integer ratios such as
+/// 48 kHz -> 11.025 kHz) maps onto the same time axis. When downsampling, the kernel cutoff is
+/// lowered to the target Nyquist frequency so nothing above it aliases into the output.
+pub fn resample(input: &[f64], original_sample_rate: i32, target_sample_rate: i32) -> Result<Vec<f64>, Box<dyn Error>> {
+    let mut resampler = Resampler::new(original_sample_rate, target_sample_rate)?;
+    let mut output = resampler.process(input);
+    output.extend(resampler.finish());
+    Ok(output)
+}
+
+/// Streaming form of `resample`: input is pushed in chunks and output is produced as soon as
+/// the kernel has seen enough samples, keeping only the kernel's span of input in memory.
+pub struct Resampler {
+    step: f64,
+    cutoff: f64,
+    half_width: f64,
+    passthrough: bool,
+    /// Windowed sinc sampled at `SINC_TABLE_RESOLUTION` points per zero crossing.
+    kernel: Vec<f64>,
+    history: VecDeque<f64>, // Input samples from index `offset` on
+    offset: usize,
+    received: usize,
+    next_output: usize,
+}
+
+impl Resampler {
+    pub fn new(original_sample_rate: i32, target_sample_rate: i32) -> Result<Self, Box<dyn Error>> {
+        if target_sample_rate <= 0 || original_sample_rate <= 0 {
+            return Err("sample rates must be positive".into());
+        }
+        let step = original_sample_rate as f64 / target_sample_rate as f64;
+        // Cutoff relative to the input Nyquist frequency.
+        let cutoff = (1.0 / step).min(1.0);
+        let zero_crossings = SINC_ZERO_CROSSINGS as f64;
+        // Blackman-windowed sinc as a function of the distance in zero crossings.
+        let kernel = (0..=SINC_ZERO_CROSSINGS * SINC_TABLE_RESOLUTION + 1)
+            .map(|i| {
+                let u = i as f64 / SINC_TABLE_RESOLUTION as f64;
+                if u >= zero_crossings {
+                    return 0.0;
+                }
+                let sinc = if u == 0.0 { 1.0 } else { (PI * u).sin() / (PI * u) };
+                let w = 0.5 + u / (2.0 * zero_crossings);
+                sinc * (0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos())
+            })
+            .collect();
+        Ok(Resampler {
+            step,
+            cutoff,
+            half_width: zero_crossings / cutoff,
+            passthrough: original_sample_rate == target_sample_rate,
+            kernel,
+            history: VecDeque::new(),
+            offset: 0,
+            received: 0,
+            next_output: 0,
+        })
+    }
+
+    /// Pushes a chunk of input and returns the output samples that became available.
+    pub fn process(&mut self, input: &[f64]) -> Vec<f64> {
+        if self.passthrough {
+            return input.to_vec();
+        }
+        self.history.extend(input);
+        self.received += input.len();
+
+        let mut output = Vec::new();
+        // An output sample is ready once every input sample under its kernel has arrived.
+        while ((self.next_output as f64 * self.step + self.half_width).floor() as usize) < self.received {
+            output.push(self.interpolate(self.next_output));
+            self.next_output += 1;
+        }
+
+        // Forget the input that no later output sample can reach.
+        let first_needed = ((self.next_output as f64 * self.step - self.half_width).ceil().max(0.0)) as usize;
+        while self.offset < first_needed && !self.history.is_empty() {
+            self.history.pop_front();
+            self.offset += 1;
+        }
+        output
     }
-    if target_sample_rate > original_sample_rate {
-        return Err("target sample rate must be less than or equal to original sample rate".into());
+
+    /// Returns the remaining output, treating the input as silent past its end. The total output
+    /// length is the input length scaled by the rate ratio.
+    pub fn finish(&mut self) -> Vec<f64> {
+        if self.passthrough {
+            return vec![];
+        }
+        let output_len = (self.received as f64 / self.step).round() as usize;
+        let output = (self.next_output..output_len).map(|k| self.interpolate(k)).collect();
+        self.next_output = self.next_output.max(output_len);
+        output
     }
 
-    let ratio = original_sample_rate / target_sample_rate;
-    if ratio <= 0 {
-        return Err("invalid ratio calculated from sample rates".into());
+    fn interpolate(&self, k: usize) -> f64 {
+        let t = k as f64 * self.step;
+        let first = ((t - self.half_width).ceil().max(0.0)) as usize;
+        let last = ((t + self.half_width).floor() as usize).min(self.received.saturating_sub(1));
+
+        let mut acc = 0.0;
+        for j in first.max(self.offset)..=last {
+            let position = (t - j as f64).abs() * self.cutoff * SINC_TABLE_RESOLUTION as f64;
+            let index = (position as usize).min(self.kernel.len() - 2);
+            let fraction = position - index as f64;
+            let weight = self.kernel[index] + fraction * (self.kernel[index + 1] - self.kernel[index]);
+            acc += self.history[j - self.offset] * weight;
+        }
+        acc * self.cutoff
     }
+}
 
-    let mut resampled = Vec::new();
-    let len = input.len();
-    let ratio = ratio as usize;
-    let mut i = 0;
-    while i < len {
-        let end = if i + ratio > len { len } else { i + ratio };
-        let sum: f64 = input[i..end].iter().sum();
-        let avg = sum / (end - i) as f64;
-        resampled.push(avg);
-        i += ratio;
+/// Analyzes a spectrogram and extracts a constellation of significant peaks over time.
+/// This runs `PeakPicker` over every frame; see it for the selection rules.
+pub fn extract_peaks(spectrogram: &[Vec<Complex<f64>>], config: &FingerprintConfig) -> Vec<Peak> {
+    let mut picker = PeakPicker::new(config);
+    let mut peaks = Vec::new();
+    for frame in spectrogram {
+        peaks.extend(picker.push_frame(frame));
     }
+    peaks.extend(picker.finish());
+    peaks
+}
 
-    Ok(resampled)
+/// Picks constellation peaks from STFT frames pushed one at a time.
+///
+/// A point is a peak when it is the largest magnitude in its time–frequency neighborhood
+/// (`peak_neighborhood_frames` × `peak_neighborhood_bins` in each direction), rises above an
+/// adaptive per-bin noise floor by `noise_floor_factor`, and is no more than `peak_floor_db`
+/// below a full-scale sine. The strongest `peaks_per_second` peaks of each second are kept.
+///
+/// Only the frames of one neighborhood and the candidates of the current second are held,
+/// and peaks are returned, sorted by frame and bin, once their second is complete.
+pub struct PeakPicker {
+    config: FingerprintConfig,
+    min_bin: usize,
+    max_bin: usize,
+    absolute_floor: f64,
+    /// Band-limited magnitudes of the frames from `decided` to the newest one.
+    frames: VecDeque<Vec<f64>>,
+    /// Per bin, a monotonic queue of (frame index, frequency-neighborhood maximum) used for the
+    /// time-direction maximum.
+    time_max: Vec<VecDeque<(usize, f64)>>,
+    noise_floor: Option<Vec<f64>>,
+    pushed: usize,
+    decided: usize,
+    /// Second currently being collected and its candidates: (magnitude, frame index, bin index).
+    second: usize,
+    candidates: Vec<(f64, usize, usize)>,
 }
 
-/// A Peak in the spectrogram with its time (in seconds) and frequency (as a complex number).
-// #[derive(Debug, Clone)]
-// pub struct Peak {
-//     pub time: f64,
-//     pub freq: Complex<f64>,
-// }
+impl PeakPicker {
+    pub fn new(config: &FingerprintConfig) -> Self {
+        // Only the bins covered by the configured bands are searched.
+        let num_bins = config.freq_bin_size / 2 + 1;
+        let min_bin = config.bands.iter().map(|&(min, _)| min).min().unwrap_or(0).min(num_bins);
+        let max_bin = config.bands.iter().map(|&(_, max)| max).max().unwrap_or(num_bins).min(num_bins).max(min_bin);
+
+        // A full-scale sine peaks at half the sum of the window coefficients.
+        let full_scale = config.window.coefficients(config.freq_bin_size).iter().sum::<f64>() / 2.0;
 
-/// Analyzes a spectrogram and extracts significant peaks in the frequency domain over time.
-pub fn extract_peaks(spectrogram: &[Vec<Complex<f64>>], audio_duration: f64) -> Vec<Peak> {
-    if spectrogram.is_empty() {
-        return vec![];
+        PeakPicker {
+            config: config.clone(),
+            min_bin,
+            max_bin,
+            absolute_floor: full_scale * 10f64.powf(config.peak_floor_db / 20.0),
+            frames: VecDeque::new(),
+            time_max: vec![VecDeque::new(); max_bin - min_bin],
+            noise_floor: None,
+            pushed: 0,
+            decided: 0,
+            second: 0,
+            candidates: Vec::new(),
+        }
     }
 
-    // Local struct for tracking maximum magnitude within a band.
-    struct Maxies {
-        max_mag: f64,
-        max_freq: Complex<f64>,
-        freq_idx: usize,
+    /// Pushes the next STFT frame and returns the peaks that are now final.
+    pub fn push_frame(&mut self, frame: &[Complex<f64>]) -> Vec<Peak> {
+        let magnitudes: Vec<f64> = frame[self.min_bin..self.max_bin.min(frame.len())].iter().map(|v| v.norm()).collect();
+
+        // Maximum over the neighborhood, computed separably: first along frequency, then along time.
+        let index = self.pushed;
+        for (queue, max) in self.time_max.iter_mut().zip(sliding_max(&magnitudes, self.config.peak_neighborhood_bins)) {
+            while let Some(&(_, back)) = queue.back() {
+                if back <= max {
+                    queue.pop_back();
+                } else {
+                    break;
+                }
+            }
+            queue.push_back((index, max));
+        }
+        self.frames.push_back(magnitudes);
+        self.pushed += 1;
+
+        let mut peaks = Vec::new();
+        // A frame is decided once the frames after it within the neighborhood have arrived.
+        while self.decided + self.config.peak_neighborhood_frames < self.pushed {
+            peaks.extend(self.decide_next());
+        }
+        peaks
     }
 
-    // Define frequency bands (indices).
-    let bands = vec![(0, 10), (10, 20), (20, 40), (40, 80), (80, 160), (160, 512)];
+    /// Decides the remaining frames and returns the last peaks.
+    pub fn finish(&mut self) -> Vec<Peak> {
+        let mut peaks = Vec::new();
+        while self.decided < self.pushed {
+            peaks.extend(self.decide_next());
+        }
+        peaks.extend(self.flush_second());
+        peaks
+    }
 
-    let mut peaks = Vec::new();
-    let bin_duration = audio_duration / spectrogram.len() as f64;
-
-    // Iterate over each time window (bin) in the spectrogram.
-    for (bin_idx, bin) in spectrogram.iter().enumerate() {
-        let mut bin_band_maxies = Vec::new();
-        // For each defined band, find the frequency bin with maximum magnitude.
-        for &(min, max) in bands.iter() {
-            let mut max_val = 0.0;
-            let mut max_entry = Maxies { max_mag: 0.0, max_freq: Complex::new(0.0, 0.0), freq_idx: min };
-            for (idx, freq) in bin[min..max].iter().enumerate() {
-                let magnitude = freq.norm();
-                if magnitude > max_val {
-                    max_val = magnitude;
-                    max_entry = Maxies {
-                        max_mag: magnitude,
-                        max_freq: *freq,
-                        freq_idx: min + idx,
-                    };
+    fn decide_next(&mut self) -> Vec<Peak> {
+        let t = self.decided;
+        let radius = self.config.peak_neighborhood_frames;
+        let magnitudes = self.frames.pop_front().unwrap_or_default();
+        self.decided += 1;
+
+        let mut peaks = Vec::new();
+        let second = frame_time(t, &self.config) as usize;
+        if second != self.second {
+            peaks = self.flush_second();
+            self.second = second;
+        }
+
+        let decay = self.config.noise_floor_decay;
+        let noise_floor = self.noise_floor.get_or_insert_with(|| magnitudes.clone());
+        for (f, &magnitude) in magnitudes.iter().enumerate() {
+            // Track the background level of each bin with an exponential moving average.
+            noise_floor[f] = decay * noise_floor[f] + (1.0 - decay) * magnitude;
+
+            let queue = &mut self.time_max[f];
+            while let Some(&(index, _)) = queue.front() {
+                if index + radius < t {
+                    queue.pop_front();
+                } else {
+                    break;
                 }
             }
-            bin_band_maxies.push(max_entry);
+            let neighborhood_max = queue.front().map_or(magnitude, |&(_, max)| max);
+
+            if magnitude < neighborhood_max
+                || magnitude <= self.absolute_floor
+                || magnitude <= noise_floor[f] * self.config.noise_floor_factor
+            {
+                continue;
+            }
+            self.candidates.push((magnitude, t, self.min_bin + f));
         }
+        peaks
+    }
+
+    /// Keeps the strongest candidates of the current second and returns them in time order.
+    fn flush_second(&mut self) -> Vec<Peak> {
+        let mut candidates = std::mem::take(&mut self.candidates);
+        candidates.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+        candidates.truncate(self.config.peaks_per_second);
+        let mut selected: Vec<(usize, usize)> = candidates.into_iter().map(|(_, t, f)| (t, f)).collect();
+        selected.sort_unstable();
 
-        // Extract arrays of maximum magnitudes, frequencies, and their indices.
-        let max_mags: Vec<f64> = bin_band_maxies.iter().map(|m| m.max_mag).collect();
-        let max_freqs: Vec<Complex<f64>> = bin_band_maxies.iter().map(|m| m.max_freq).collect();
-        let freq_indices: Vec<f64> = bin_band_maxies.iter().map(|m| m.freq_idx as f64).collect();
+        selected
+            .into_iter()
+            .map(|(t, f)| Peak {
+                frame: t as u32,
+                time: frame_time(t, &self.config),
+                bin: f as u32,
+                freq_hz: bin_frequency(f, &self.config),
+            })
+            .collect()
+    }
+}
 
-        // Calculate average magnitude across bands.
-        let sum: f64 = max_mags.iter().sum();
-        let avg = sum / max_mags.len() as f64;
+/// Returns, for every index, the maximum of `values` within `radius` positions on either side.
+/// Runs in linear time using a monotonic queue of candidate indices.
+fn sliding_max(values: &[f64], radius: usize) -> Vec<f64> {
+    let mut result = Vec::with_capacity(values.len());
+    let mut queue: VecDeque<usize> = VecDeque::new();
+    let mut next = 0;
 
-        // For each band, if the maximum magnitude exceeds the average, record a peak.
-        for i in 0..max_mags.len() {
-            if max_mags[i] > avg {
-                // Calculate a time offset within the bin.
-                let peak_time_in_bin = freq_indices[i] * bin_duration / bin.len() as f64;
-                let peak_time = bin_idx as f64 * bin_duration + peak_time_in_bin;
-                peaks.push(Peak { time: peak_time, freq: max_freqs[i] });
+    for i in 0..values.len() {
+        // Extend the window to include i + radius.
+        while next < values.len() && next <= i + radius {
+            while let Some(&back) = queue.back() {
+                if values[back] <= values[next] {
+                    queue.pop_back();
+                } else {
+                    break;
+                }
+            }
+            queue.push_back(next);
+            next += 1;
+        }
+        // Drop indices that fell out of the window on the left.
+        while let Some(&front) = queue.front() {
+            if front + radius < i {
+                queue.pop_front();
+            } else {
+                break;
             }
         }
+        result.push(values[queue[0]]);
     }
 
-    peaks
+    result
 }
 
 #[cfg(test)]
@@ -163,20 +411,116 @@ mod tests {
     use num_complex::Complex;
 
     #[test]
-    fn test_downsample() {
+    fn test_resample_integer_ratio() {
         // Test with a simple sequence.
         let input: Vec<f64> = (0..100).map(|x| x as f64).collect();
         let original_rate = 100;
         let target_rate = 25;
-        let resampled = downsample(&input, original_rate, target_rate).unwrap();
+        let resampled = resample(&input, original_rate, target_rate).unwrap();
         // Expect length approx 100/4 = 25.
         assert!((resampled.len() as i32 - 25).abs() <= 1);
     }
 
+    /// Generates `seconds` of a sine at `freq` Hz sampled at `rate`.
+    fn sine(freq: f64, rate: i32, seconds: f64) -> Vec<f64> {
+        (0..(rate as f64 * seconds) as usize)
+            .map(|i| (2.0 * PI * freq * i as f64 / rate as f64).sin())
+            .collect()
+    }
+
+    #[test]
+    fn test_resample_non_integer_ratios_keep_timing() {
+        let target = 11025;
+        for rate in [8000, 16000, 22050, 44100, 48000, 96000] {
+            let input = sine(440.0, rate, 1.0);
+            let resampled = resample(&input, rate, target).unwrap();
+            assert!((resampled.len() as i32 - target).abs() <= 1, "{} Hz gave {} samples", rate, resampled.len());
+
+            // Away from the edges, the output must follow the same sine on the target time axis.
+            let expected = sine(440.0, target, 1.0);
+            for i in 1000..resampled.len() - 1000 {
+                assert!((resampled[i] - expected[i]).abs() < 0.05, "{} Hz differs at sample {}", rate, i);
+            }
+        }
+    }
+
+    #[test]
+    fn test_frame_count_and_times() {
+        let config = FingerprintConfig { freq_bin_size: 1024, hop_size: 256, ..FingerprintConfig::default() };
+        assert_eq!(frame_count(1000, &config), 0);
+        assert_eq!(frame_count(1024, &config), 1);
+        assert_eq!(frame_count(1024 + 255, &config), 1);
+        assert_eq!(frame_count(1024 + 256, &config), 2);
+        assert_eq!(frame_time(0, &config), 0.0);
+        assert!((frame_time(441, &config) - 441.0 * 256.0 / 11025.0).abs() < 1e-12);
+
+        // The STFT produces exactly that many frames.
+        let samples = sine(440.0, 11025, 2.0);
+        let spec = spectrogram(&samples, 11025, &config).unwrap();
+        assert_eq!(spec.len(), frame_count(samples.len(), &config));
+    }
+
     #[test]
     fn test_extract_peaks_empty() {
         let spec: Vec<Vec<Complex<f64>>> = vec![];
-        let peaks = extract_peaks(&spec, 1.0);
+        let peaks = extract_peaks(&spec, &FingerprintConfig::default());
         assert!(peaks.is_empty());
     }
+
+    #[test]
+    fn test_sliding_max() {
+        let values = [1.0, 3.0, 2.0, 0.0, 0.0, 5.0, 1.0];
+        assert_eq!(sliding_max(&values, 1), vec![3.0, 3.0, 3.0, 2.0, 5.0, 5.0, 5.0]);
+        assert_eq!(sliding_max(&values, 0), values.to_vec());
+    }
+
+    /// Builds a spectrogram of `frames` frames of low noise with a loud tone in `tone_bin`
+    /// during the frames in `tone_frames`.
+    fn tone_spectrogram(frames: usize, tone_bin: usize, tone_frames: std::ops::Range<usize>) -> Vec<Vec<Complex<f64>>> {
+        (0..frames)
+            .map(|t| {
+                (0..513)
+                    .map(|f| {
+                        let noise = 0.01 * (((t * 31 + f * 17) % 13) as f64 / 13.0);
+                        let tone = if f == tone_bin && tone_frames.contains(&t) { 10.0 } else { 0.0 };
+                        Complex::new(noise + tone, 0.0)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_extract_peaks_finds_tone_onset() {
+        let config = FingerprintConfig::default();
+        let spec = tone_spectrogram(200, 100, 80..120);
+        let peaks = extract_peaks(&spec, &config);
+
+        // Only the loud tone must be picked, not the noise around it.
+        assert!(!peaks.is_empty());
+        assert!(peaks.iter().all(|p| p.bin == 100));
+        assert!((peaks[0].freq_hz - 100.0 * 11025.0 / 1024.0).abs() < 1e-9);
+        // The onset is reported at the exact start time of its frame.
+        assert!(peaks.iter().all(|p| (80..120).any(|t| p.time == frame_time(t, &config))));
+    }
+
+    #[test]
+    fn test_extract_peaks_ignores_silence() {
+        let spec = vec![vec![Complex::new(0.0, 0.0); 513]; 100];
+        assert!(extract_peaks(&spec, &FingerprintConfig::default()).is_empty());
+    }
+
+    #[test]
+    fn test_extract_peaks_respects_density() {
+        let config = FingerprintConfig { peaks_per_second: 3, ..FingerprintConfig::default() };
+        // Uniform random-looking noise yields many local maxima; the cap must hold per second.
+        let spec: Vec<Vec<Complex<f64>>> = (0..400)
+            .map(|t| (0..513).map(|f| Complex::new((((t * 7919 + f * 104729) % 1000) as f64) / 1000.0, 0.0)).collect())
+            .collect();
+        let peaks = extract_peaks(&spec, &config);
+        for second in 0..4 {
+            let count = peaks.iter().filter(|p| p.time as usize == second).count();
+            assert!(count <= 3, "second {} has {} peaks", second, count);
+        }
+    }
 }
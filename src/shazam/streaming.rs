@@ -0,0 +1,271 @@
+use std::collections::VecDeque;
+use std::error::Error;
+
+use num_complex::Complex;
+
+use crate::models::Couple;
+use crate::shazam::config::FingerprintConfig;
+use crate::shazam::fft::RealFft;
+use crate::shazam::filter::ButterworthFilter;
+use crate::shazam::fingerprint::{create_address, fingerprint, Peak};
+use crate::shazam::spectrogram::{band_limit_filter, extract_peaks, spectrogram, PeakPicker, Resampler};
+use crate::wav;
+
+/// Number of frames read from a WAV file per chunk when fingerprinting it.
+const WAV_CHUNK_FRAMES: usize = 1 << 16;
+
+/// Fingerprints audio pushed a chunk at a time, producing the same fingerprints as running
+/// `spectrogram`, `extract_peaks` and `fingerprint` over the whole signal.
+///
+/// Memory stays bounded by the configuration rather than the length of the audio: only the
+/// filter state, the resampler kernel span, one STFT window, the peak neighborhood and the
+/// peaks still waiting for their targets are kept.
+pub struct Fingerprinter {
+    config: FingerprintConfig,
+    song_id: u32,
+    filter: Option<ButterworthFilter>,
+    resampler: Resampler,
+    fft: RealFft,
+    window: Vec<f64>,
+    frame: Vec<f64>,
+    spectrum: Vec<Complex<f64>>,
+    /// Analysis-rate samples not yet consumed by a full STFT frame.
+    pending: VecDeque<f64>,
+    picker: PeakPicker,
+    /// Peaks whose target zone is not complete yet.
+    anchors: VecDeque<Peak>,
+    /// Position in the stream, in milliseconds, that anchor times are measured from.
+    origin_ms: u64,
+}
+
+impl Fingerprinter {
+    /// Creates a fingerprinter for audio at `sample_rate` whose fingerprints are attributed
+    /// to `song_id`. Zero-phase filtering needs the whole signal, so it is rejected here.
+    pub fn new(sample_rate: i32, song_id: u32, config: &FingerprintConfig) -> Result<Self, Box<dyn Error>> {
+        if config.zero_phase_filter {
+            return Err("zero-phase filtering cannot be used when fingerprinting a stream".into());
+        }
+        let fft = RealFft::new(config.freq_bin_size)?;
+        let spectrum = vec![Complex::new(0.0, 0.0); fft.output_len()];
+        Ok(Fingerprinter {
+            config: config.clone(),
+            song_id,
+            filter: band_limit_filter(sample_rate, config)?,
+            resampler: Resampler::new(sample_rate, config.analysis_sample_rate)?,
+            fft,
+            window: config.window.coefficients(config.freq_bin_size),
+            frame: vec![0.0; config.freq_bin_size],
+            spectrum,
+            pending: VecDeque::new(),
+            picker: PeakPicker::new(config),
+            anchors: VecDeque::new(),
+            origin_ms: 0,
+        })
+    }
+
+    /// Measures the anchor times of later fingerprints from `origin_ms` into the stream rather
+    /// than from its start, and drops fingerprints anchored before it. Moving the origin along
+    /// keeps the 32-bit anchor times of an unbounded stream from running out.
+    pub fn set_origin_ms(&mut self, origin_ms: u64) {
+        self.origin_ms = origin_ms;
+    }
+
+    /// Pushes the next chunk of mono samples and returns the fingerprints that became final.
+    pub fn push(&mut self, samples: &[f64]) -> Result<Vec<(u32, Couple)>, Box<dyn Error>> {
+        let filtered = match self.filter.as_mut() {
+            Some(filter) => filter.filter(samples),
+            None => samples.to_vec(),
+        };
+        let resampled = self.resampler.process(&filtered);
+        self.pending.extend(resampled);
+
+        let mut peaks = Vec::new();
+        let window_length = self.config.freq_bin_size;
+        let hop = self.config.hop().min(window_length);
+        while self.pending.len() >= window_length {
+            for ((sample, &x), &w) in self.frame.iter_mut().zip(&self.pending).zip(&self.window) {
+                *sample = x * w;
+            }
+            self.fft.process(&self.frame, &mut self.spectrum)?;
+            peaks.extend(self.picker.push_frame(&self.spectrum));
+            self.pending.drain(..hop);
+        }
+
+        Ok(self.pair(peaks, false))
+    }
+
+    /// Flushes the remaining audio and returns the last fingerprints.
+    pub fn finish(mut self) -> Result<Vec<(u32, Couple)>, Box<dyn Error>> {
+        let tail = self.resampler.finish();
+        self.pending.extend(tail);
+        let mut fingerprints = self.push(&[])?;
+        let peaks = self.picker.finish();
+        fingerprints.extend(self.pair(peaks, true));
+        Ok(fingerprints)
+    }
+
+    /// Queues new peaks and pairs every anchor whose target zone is complete (all remaining
+    /// anchors when `last` is set) with the peaks that follow it.
+    fn pair(&mut self, peaks: Vec<Peak>, last: bool) -> Vec<(u32, Couple)> {
+        self.anchors.extend(peaks);
+        let zone = self.config.target_zone_size;
+        let mut fingerprints = Vec::new();
+
+        while last || self.anchors.len() > zone {
+            let Some(anchor) = self.anchors.pop_front() else { break };
+            let Some(anchor_time_ms) = ((anchor.time * 1000.0) as u64).checked_sub(self.origin_ms) else { continue };
+            let anchor_time_ms = anchor_time_ms.min(u32::MAX as u64) as u32;
+            for target in self.anchors.iter().take(zone) {
+                if let Some(address) = create_address(&anchor, target, &self.config) {
+                    fingerprints.push((address, Couple { anchor_time_ms, song_id: self.song_id }));
+                }
+            }
+        }
+        fingerprints
+    }
+}
+
+/// Fingerprints a PCM or float WAV file, downmixed to mono, streaming it through a `Fingerprinter` so that memory
+/// use does not grow with the file's length. With zero-phase filtering enabled the whole file
+/// is loaded and processed at once instead.
+pub fn fingerprint_wav_file(
+    file_path: &str,
+    song_id: u32,
+    config: &FingerprintConfig,
+) -> Result<Vec<(u32, Couple)>, Box<dyn Error>> {
+    if config.zero_phase_filter {
+        let wav_info = wav::read_wav_info(file_path)?;
+        let samples = wav_info.mono_samples()?;
+        let spectro = spectrogram(&samples, wav_info.sample_rate, config)?;
+        let peaks = extract_peaks(&spectro, config);
+        return Ok(fingerprint(&peaks, song_id, config));
+    }
+
+    let mut stream = wav::WavStream::open(file_path)?;
+    let mut fingerprinter = Fingerprinter::new(stream.sample_rate, song_id, config)?;
+    let mut fingerprints = Vec::new();
+    while let Some(chunk) = stream.read_chunk(WAV_CHUNK_FRAMES)? {
+        fingerprints.extend(fingerprinter.push(&chunk)?);
+    }
+    fingerprints.extend(fingerprinter.finish()?);
+    Ok(fingerprints)
+}
+
+/// Fingerprints an audio file in any format a decoder backend supports. WAV files are
+/// streamed with `fingerprint_wav_file`; other files are decoded in memory to mono at their
+/// own sample rate and then go through the same filter and resampler as WAV input, so a song
+/// fingerprints the same whatever container it comes in.
+pub fn fingerprint_audio_file(
+    file_path: &str,
+    song_id: u32,
+    config: &FingerprintConfig,
+) -> Result<Vec<(u32, Couple)>, Box<dyn Error>> {
+    if wav::WavStream::open(file_path).is_ok() {
+        return fingerprint_wav_file(file_path, song_id, config);
+    }
+    fingerprint_decoded_file(wav::decoder_for(file_path)?.as_ref(), file_path, song_id, config)
+}
+
+/// Decodes a whole file with `decoder` at its native sample rate and fingerprints it.
+fn fingerprint_decoded_file(
+    decoder: &dyn wav::AudioDecoder,
+    file_path: &str,
+    song_id: u32,
+    config: &FingerprintConfig,
+) -> Result<Vec<(u32, Couple)>, Box<dyn Error>> {
+    let sample_rate = decoder.probe(file_path)?.sample_rate;
+    let samples = decoder.decode(file_path, sample_rate)?;
+    if config.zero_phase_filter {
+        let spectro = spectrogram(&samples, sample_rate, config)?;
+        return Ok(fingerprint(&extract_peaks(&spectro, config), song_id, config));
+    }
+
+    let mut fingerprinter = Fingerprinter::new(sample_rate, song_id, config)?;
+    let mut fingerprints = fingerprinter.push(&samples)?;
+    fingerprints.extend(fingerprinter.finish()?);
+    Ok(fingerprints)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    /// A few seconds of a melody of short tones over a quiet noise bed.
+    fn melody(sample_rate: i32, seconds: f64) -> Vec<f64> {
+        let notes = [440.0, 660.0, 550.0, 880.0, 330.0, 990.0, 495.0];
+        (0..(sample_rate as f64 * seconds) as usize)
+            .map(|i| {
+                let t = i as f64 / sample_rate as f64;
+                let note = notes[(t * 4.0) as usize % notes.len()];
+                let noise = 0.001 * (((i * 7919) % 1000) as f64 / 1000.0 - 0.5);
+                0.5 * (2.0 * PI * note * t).sin() + noise
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_streaming_matches_batch() {
+        let config = FingerprintConfig::default();
+        let sample_rate = 44100;
+        let samples = melody(sample_rate, 4.0);
+
+        let spectro = spectrogram(&samples, sample_rate, &config).unwrap();
+        let batch = fingerprint(&extract_peaks(&spectro, &config), 3, &config);
+        assert!(!batch.is_empty());
+
+        // Uneven chunk sizes, including chunks smaller than a window and than a hop.
+        for chunk_size in [17, 1000, 30000] {
+            let mut fingerprinter = Fingerprinter::new(sample_rate, 3, &config).unwrap();
+            let mut streamed = Vec::new();
+            for chunk in samples.chunks(chunk_size) {
+                streamed.extend(fingerprinter.push(chunk).unwrap());
+            }
+            streamed.extend(fingerprinter.finish().unwrap());
+            assert_eq!(streamed, batch, "chunk size {} differs from batch", chunk_size);
+        }
+    }
+
+    #[test]
+    fn test_streaming_emits_incrementally_with_bounded_state() {
+        let config = FingerprintConfig::default();
+        let mut fingerprinter = Fingerprinter::new(44100, 1, &config).unwrap();
+        let samples = melody(44100, 6.0);
+
+        let mut emitted_before_finish = 0;
+        for chunk in samples.chunks(4410) {
+            emitted_before_finish += fingerprinter.push(chunk).unwrap().len();
+            assert!(fingerprinter.pending.len() < config.freq_bin_size);
+            assert!(fingerprinter.anchors.len() <= config.target_zone_size + config.peaks_per_second);
+        }
+        assert!(emitted_before_finish > 0);
+    }
+
+    #[test]
+    fn test_decoded_files_fingerprint_like_wav_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("melody.wav");
+        let path = path.to_str().unwrap();
+        let sample_rate = 22050;
+        let pcm: Vec<u8> = melody(sample_rate, 3.0)
+            .iter()
+            .flat_map(|&sample| ((sample * 32767.0) as i16).to_le_bytes())
+            .collect();
+        wav::write_wav_file(path, &pcm, sample_rate, 1, 16).unwrap();
+
+        // Going through a decoder backend instead of the WAV reader must not change the result.
+        for zero_phase_filter in [false, true] {
+            let config = FingerprintConfig { zero_phase_filter, ..FingerprintConfig::default() };
+            let streamed = fingerprint_wav_file(path, 5, &config).unwrap();
+            let decoded = fingerprint_decoded_file(&wav::NativeDecoder, path, 5, &config).unwrap();
+            assert!(!streamed.is_empty());
+            assert_eq!(decoded, streamed, "zero-phase {}", zero_phase_filter);
+        }
+    }
+
+    #[test]
+    fn test_streaming_rejects_zero_phase() {
+        let config = FingerprintConfig { zero_phase_filter: true, ..FingerprintConfig::default() };
+        assert!(Fingerprinter::new(44100, 1, &config).is_err());
+    }
+}
@@ -244,8 +244,8 @@ pub async fn handle_new_recording(socket: &dyn SocketIOSocket, record_data: &str
         }
     };
 
-    let (matches, _duration) =
-        match shazam::find_matches(&samples, rec_data.duration, rec_data.sample_rate).await {
+    let (mut recognition, _duration) =
+        match shazam::find_matches(&samples, rec_data.sample_rate).await {
             Ok(result) => result,
             Err(e) => {
                 // logger.error_context("", e);
@@ -254,12 +254,9 @@ pub async fn handle_new_recording(socket: &dyn SocketIOSocket, record_data: &str
             }
         };
 
-    // Only return up to 10 matches.
-    let json_data = match serde_json::to_string(if matches.len() > 10 {
-        &matches[..10]
-    } else {
-        &matches[..]
-    }) {
+    // Only return up to 10 candidates, along with the match / no-match decision.
+    recognition.truncate_candidates(10);
+    let json_data = match serde_json::to_string(&recognition) {
         Ok(data) => data,
         Err(e) => {
             // logger.error_context("", e);
@@ -70,9 +70,9 @@ pub fn floats_to_bytes(data: &[f64], bits_per_sample: i32) -> Result<Vec<u8>, Bo
     Ok(byte_data)
 }
 
-/// Processes recording data by decoding, writing a temporary WAV file, reformatting it, reading samples,
-/// and optionally moving the file to a recordings folder.
-/// Temporary files are cleaned up afterward.
+/// Processes recording data by decoding it, writing it as a WAV file in a scratch directory,
+/// converting that to mono and reading its samples, optionally keeping the converted file in
+/// the recordings folder. The scratch files are removed afterward.
 pub fn process_recording(rec_data: &models::RecordData, save_recording: bool) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
     // Decode the Base64-encoded audio.
     let decoded_audio_data = base64::prelude::BASE64_STANDARD.decode(&rec_data.audio).expect("Failed to decode audio data.");
@@ -87,7 +87,8 @@ pub fn process_recording(rec_data: &models::RecordData, save_recording: bool) ->
         now.month(),
         now.year(),
     );
-    let file_path = format!("tmp/{}", file_name);
+    let scratch = wav::ScratchDir::new()?;
+    let file_path = scratch.file(&file_name);
 
     // Write the initial WAV file.
     wav::write_wav_file(
@@ -98,12 +99,9 @@ pub fn process_recording(rec_data: &models::RecordData, save_recording: bool) ->
         rec_data.sample_size,
     )?;
 
-    // Reformat the WAV file (forcing single channel).
-    let reformatted_wav_file = wav::reformat_wav(&file_path, 1)?;
-
-    // Read WAV info and extract samples.
-    let wav_info = wav::read_wav_info(&reformatted_wav_file)?;
-    let samples = wav::wav_bytes_to_samples(&wav_info.data)?;
+    // Convert the WAV file (forcing single channel) and extract its samples.
+    let converted = wav::convert_to_wav(&file_path)?;
+    let samples = converted.samples()?;
 
     if save_recording {
         let logger = crate::utils::get_logger();
@@ -112,17 +110,12 @@ pub fn process_recording(rec_data: &models::RecordData, save_recording: bool) ->
             // logger.error_context("", &e);
             error!(logger, "Failed to create folder: {}", e);
         }
-        // Move the reformatted file into the recordings folder.
-        let new_file_path = reformatted_wav_file.replacen("tmp/", "recordings/", 1);
-        if let Err(e) = fs::rename(&reformatted_wav_file, &new_file_path) {
+        // Move the converted file into the recordings folder.
+        if let Err(e) = converted.persist(Path::new("recordings").join(&file_name)) {
             // logger.error_context("Failed to move file.", &e);
             error!(logger, "Failed to move file.{}", e);
         }
     }
 
-    // Clean up temporary files.
-    let _ = delete_file(&file_path);
-    let _ = delete_file(&reformatted_wav_file);
-
     Ok(samples)
 }
@@ -3,6 +3,8 @@ use std::env;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 pub const SONGS_DIR: &str = "songs";
+/// Root of the scratch directories used for intermediate files (see `wav::ScratchDir`).
+pub const TMP_DIR: &str = "tmp";
 
 /// Generates a unique ID based on a random u32 value.
 pub fn generate_unique_id() -> u32 {
@@ -16,6 +18,12 @@ pub fn generate_song_key(song_title: &str, song_artist: &str) -> String {
     format!("{}---{}", song_title, song_artist)
 }
 
+/// Formats a duration in milliseconds as `m:ss`, e.g. 133000 -> "2:13".
+pub fn format_timestamp(ms: u32) -> String {
+    let total_seconds = ms / 1000;
+    format!("{}:{:02}", total_seconds / 60, total_seconds % 60)
+}
+
 /// Returns the value of the environment variable `key`.
 /// If the variable is not set, returns the provided fallback value or an empty string if no fallback is provided.
 pub fn get_env(key: &str, fallback: Option<&str>) -> String {
@@ -0,0 +1,414 @@
+use std::error::Error;
+use std::f64::consts::PI;
+use std::process::Command;
+
+use num_complex::Complex;
+use rand::Rng;
+use rustfft::FftPlanner;
+use serde::Serialize;
+
+use crate::shazam;
+use crate::utils;
+use crate::wav::{write_wav_file, AudioDecoder, FfmpegDecoder, ScratchDir};
+
+/// Sample rate clips are extracted, degraded and written at.
+pub const CLIP_SAMPLE_RATE: i32 = 44100;
+
+/// Colour of the noise added to a clip.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoiseColor {
+    /// Flat spectrum.
+    White,
+    /// Power falling 3 dB per octave, closer to real background noise.
+    Pink,
+}
+
+impl NoiseColor {
+    /// Parses a colour name as given on the command line ("white" or "pink").
+    pub fn parse(name: &str) -> Result<NoiseColor, Box<dyn Error>> {
+        match name {
+            "white" => Ok(NoiseColor::White),
+            "pink" => Ok(NoiseColor::Pink),
+            other => Err(format!("unknown noise colour {:?}: expected white or pink", other).into()),
+        }
+    }
+}
+
+/// The degradations applied to each clip. They are applied in the order of the fields:
+/// a speed change, room reverb, gain, additive noise, clipping and finally a lossy re-encode.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DegradeOptions {
+    /// Playback speed factor; changes tempo and pitch together, like a tape running fast.
+    pub speed: Option<f64>,
+    /// Reverberation time (RT60) of a synthetic room, in seconds.
+    pub reverb_rt60: Option<f64>,
+    /// Gain in dB.
+    pub gain_db: Option<f64>,
+    /// Noise colour and the signal-to-noise ratio to add it at, in dB.
+    pub noise: Option<(NoiseColor, f64)>,
+    /// Level in (0, 1] above which samples are hard-clipped.
+    pub clip_level: Option<f64>,
+    /// MP3 bitrate in kbit/s to round-trip the clip through (needs ffmpeg).
+    pub bitrate_kbps: Option<u32>,
+}
+
+impl DegradeOptions {
+    /// Checks that every degradation is in range, so a bad option is reported before any clip
+    /// is cut rather than partway through the set.
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(speed) = self.speed
+            && !(0.25..=4.0).contains(&speed)
+        {
+            return Err(format!("speed must be between 0.25 and 4, got {}", speed).into());
+        }
+        if let Some(rt60) = self.reverb_rt60
+            && !(rt60 > 0.0 && rt60 <= 10.0)
+        {
+            return Err(format!("reverb time must be in (0, 10] seconds, got {}", rt60).into());
+        }
+        if let Some(gain_db) = self.gain_db
+            && !gain_db.is_finite()
+        {
+            return Err(format!("gain must be a finite number of dB, got {}", gain_db).into());
+        }
+        if let Some((_, snr_db)) = self.noise
+            && !snr_db.is_finite()
+        {
+            return Err(format!("SNR must be a finite number of dB, got {}", snr_db).into());
+        }
+        if let Some(level) = self.clip_level
+            && !(level > 0.0 && level <= 1.0)
+        {
+            return Err(format!("clip level must be in (0, 1], got {}", level).into());
+        }
+        if self.bitrate_kbps == Some(0) {
+            return Err("bitrate must be positive".into());
+        }
+        Ok(())
+    }
+
+    /// Describes the degradations, in the order they are applied, for the manifest.
+    pub fn describe(&self) -> Vec<String> {
+        let mut steps = Vec::new();
+        if let Some(speed) = self.speed {
+            steps.push(format!("speed x{}", speed));
+        }
+        if let Some(rt60) = self.reverb_rt60 {
+            steps.push(format!("reverb rt60 {}s", rt60));
+        }
+        if let Some(gain_db) = self.gain_db {
+            steps.push(format!("gain {:+} dB", gain_db));
+        }
+        if let Some((color, snr_db)) = self.noise {
+            let color = match color {
+                NoiseColor::White => "white",
+                NoiseColor::Pink => "pink",
+            };
+            steps.push(format!("{} noise at {} dB SNR", color, snr_db));
+        }
+        if let Some(level) = self.clip_level {
+            steps.push(format!("clipped at {}", level));
+        }
+        if let Some(kbps) = self.bitrate_kbps {
+            steps.push(format!("mp3 at {} kbps", kbps));
+        }
+        steps
+    }
+}
+
+/// What to cut out of the library.
+#[derive(Debug, Clone)]
+pub struct ClipOptions {
+    /// Number of clips taken from random songs at random offsets, besides any given segments.
+    pub count: usize,
+    pub length_ms: u32,
+    /// Seed for the song and offset choices and the noise, for reproducible test sets.
+    pub seed: Option<u64>,
+    pub degrade: DegradeOptions,
+}
+
+impl ClipOptions {
+    /// Checks the clip length and the degradations.
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.length_ms == 0 {
+            return Err("clip length must be positive".into());
+        }
+        self.degrade.validate()
+    }
+}
+
+/// One clip in the ground-truth manifest.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClipManifestEntry {
+    /// File name of the clip, relative to the manifest.
+    pub file: String,
+    /// Song file the clip was cut from.
+    pub source: String,
+    /// The song's database ID, if it could be found.
+    pub song_id: Option<u32>,
+    pub song_title: Option<String>,
+    pub song_artist: Option<String>,
+    /// Where the clip starts in the song, in milliseconds.
+    pub offset_ms: u32,
+    /// Length of the song audio in the clip, before any speed change, in milliseconds.
+    pub duration_ms: u32,
+    pub degradations: Vec<String>,
+}
+
+/// Returns `[start_ms, start_ms + length_ms)` of `samples`, cut short at the end of the audio.
+pub fn extract_segment(samples: &[f64], sample_rate: i32, start_ms: u32, length_ms: u32) -> Result<Vec<f64>, Box<dyn Error>> {
+    let to_index = |ms: u32| (ms as u64 * sample_rate as u64 / 1000) as usize;
+    let start = to_index(start_ms);
+    if start >= samples.len() {
+        return Err(format!(
+            "segment starts at {} ms, past the end of the {} ms of audio",
+            start_ms,
+            samples.len() as u64 * 1000 / sample_rate.max(1) as u64
+        )
+        .into());
+    }
+    let end = (start + to_index(length_ms)).min(samples.len());
+    Ok(samples[start..end].to_vec())
+}
+
+/// Applies the degradations in `options` to a clip. The options are expected to have passed
+/// [`DegradeOptions::validate`].
+pub fn degrade(samples: &[f64], sample_rate: i32, options: &DegradeOptions, rng: &mut impl Rng) -> Result<Vec<f64>, Box<dyn Error>> {
+    let mut samples = samples.to_vec();
+    if let Some(speed) = options.speed {
+        samples = change_speed(&samples, sample_rate, speed)?;
+    }
+    if let Some(rt60) = options.reverb_rt60 {
+        samples = add_reverb(&samples, sample_rate, rt60, rng);
+    }
+    if let Some(gain_db) = options.gain_db {
+        let gain = 10f64.powf(gain_db / 20.0);
+        samples.iter_mut().for_each(|sample| *sample *= gain);
+    }
+    if let Some((color, snr_db)) = options.noise {
+        add_noise(&mut samples, color, snr_db, rng);
+    }
+    if let Some(level) = options.clip_level {
+        samples.iter_mut().for_each(|sample| *sample = sample.clamp(-level, level));
+    }
+    if let Some(kbps) = options.bitrate_kbps {
+        samples = reencode_mp3(&samples, sample_rate, kbps)?;
+    }
+    Ok(samples)
+}
+
+/// Plays the clip `speed` times faster by resampling it as if it had been recorded at
+/// `speed * sample_rate`.
+fn change_speed(samples: &[f64], sample_rate: i32, speed: f64) -> Result<Vec<f64>, Box<dyn Error>> {
+    shazam::resample(samples, (sample_rate as f64 * speed).round() as i32, sample_rate)
+}
+
+/// Convolves the clip with the impulse response of a synthetic room: the direct sound
+/// followed by exponentially decaying noise that falls by 60 dB over `rt60` seconds. The
+/// result keeps the clip's length and loudness.
+fn add_reverb(samples: &[f64], sample_rate: i32, rt60: f64, rng: &mut impl Rng) -> Vec<f64> {
+    let ir_len = ((rt60 * sample_rate as f64) as usize).max(1);
+    let mut impulse = gaussian_noise(ir_len, rng);
+    for (i, tap) in impulse.iter_mut().enumerate() {
+        // 60 dB of decay is a factor of 1000 in amplitude.
+        *tap *= 0.1 * (-(1000f64.ln()) * i as f64 / (rt60 * sample_rate as f64)).exp();
+    }
+    impulse[0] = 1.0;
+
+    let mut wet = convolve(samples, &impulse);
+    wet.truncate(samples.len());
+    let (dry_rms, wet_rms) = (rms(samples), rms(&wet));
+    if wet_rms > 0.0 {
+        wet.iter_mut().for_each(|sample| *sample *= dry_rms / wet_rms);
+    }
+    wet
+}
+
+/// Linear convolution through the FFT.
+fn convolve(signal: &[f64], kernel: &[f64]) -> Vec<f64> {
+    if signal.is_empty() || kernel.is_empty() {
+        return Vec::new();
+    }
+    let len = (signal.len() + kernel.len() - 1).next_power_of_two();
+    let mut planner = FftPlanner::new();
+    let (forward, inverse) = (planner.plan_fft_forward(len), planner.plan_fft_inverse(len));
+    let padded = |values: &[f64]| -> Vec<Complex<f64>> {
+        let mut buffer: Vec<Complex<f64>> = values.iter().map(|&v| Complex::new(v, 0.0)).collect();
+        buffer.resize(len, Complex::new(0.0, 0.0));
+        buffer
+    };
+    let (mut a, mut b) = (padded(signal), padded(kernel));
+    forward.process(&mut a);
+    forward.process(&mut b);
+    a.iter_mut().zip(&b).for_each(|(x, y)| *x *= y);
+    inverse.process(&mut a);
+    a.iter().take(signal.len() + kernel.len() - 1).map(|z| z.re / len as f64).collect()
+}
+
+/// Adds noise of the given colour at `snr_db` below the clip's power.
+fn add_noise(samples: &mut [f64], color: NoiseColor, snr_db: f64, rng: &mut impl Rng) {
+    let signal_rms = rms(samples);
+    if signal_rms == 0.0 {
+        return;
+    }
+    let mut noise = gaussian_noise(samples.len(), rng);
+    if color == NoiseColor::Pink {
+        noise = pink_filter(&noise);
+    }
+    let noise_rms = rms(&noise);
+    if noise_rms == 0.0 {
+        return;
+    }
+    let scale = signal_rms / 10f64.powf(snr_db / 20.0) / noise_rms;
+    samples.iter_mut().zip(&noise).for_each(|(sample, n)| *sample += n * scale);
+}
+
+/// Standard normal noise, from the Box-Muller transform.
+fn gaussian_noise(len: usize, rng: &mut impl Rng) -> Vec<f64> {
+    (0..len)
+        .map(|_| {
+            let u1: f64 = rng.random_range(f64::EPSILON..1.0);
+            let u2: f64 = rng.random();
+            (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+        })
+        .collect()
+}
+
+/// Shapes white noise into pink noise with Paul Kellet's economy filter.
+fn pink_filter(white: &[f64]) -> Vec<f64> {
+    let (mut b0, mut b1, mut b2) = (0.0, 0.0, 0.0);
+    white
+        .iter()
+        .map(|&w| {
+            b0 = 0.99765 * b0 + w * 0.0990460;
+            b1 = 0.96300 * b1 + w * 0.2965164;
+            b2 = 0.57000 * b2 + w * 1.0526913;
+            b0 + b1 + b2 + w * 0.1848
+        })
+        .collect()
+}
+
+fn rms(samples: &[f64]) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    (samples.iter().map(|s| s * s).sum::<f64>() / samples.len() as f64).sqrt()
+}
+
+/// Round-trips the clip through an MP3 encode at `kbps` with ffmpeg, keeping its length.
+fn reencode_mp3(samples: &[f64], sample_rate: i32, kbps: u32) -> Result<Vec<f64>, Box<dyn Error>> {
+    if !FfmpegDecoder::is_available() {
+        return Err("re-encoding at a lower bitrate needs ffmpeg, which is not installed".into());
+    }
+    let scratch = ScratchDir::new()?;
+    let wav_path = scratch.file("clip.wav");
+    let mp3_path = scratch.file("clip.mp3");
+    write_clip(&wav_path, samples, sample_rate)?;
+
+    let output = Command::new("ffmpeg")
+        .args(["-v", "error", "-y", "-i", &wav_path, "-codec:a", "libmp3lame", "-b:a"])
+        .arg(format!("{}k", kbps))
+        .arg(&mp3_path)
+        .output()?;
+    if !output.status.success() {
+        return Err(format!("ffmpeg failed to encode MP3: {}", String::from_utf8_lossy(&output.stderr)).into());
+    }
+    let mut decoded = FfmpegDecoder.decode(&mp3_path, sample_rate)?;
+    decoded.resize(samples.len(), 0.0);
+    Ok(decoded)
+}
+
+/// Writes mono samples as a 16-bit WAV file, clamping them to [-1, 1].
+pub fn write_clip(file_path: &str, samples: &[f64], sample_rate: i32) -> Result<(), Box<dyn Error>> {
+    let clamped: Vec<f64> = samples.iter().map(|sample| sample.clamp(-1.0, 1.0)).collect();
+    let data = utils::floats_to_bytes(&clamped, 16)?;
+    write_wav_file(file_path, &data, sample_rate, 1, 16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn sine(len: usize, frequency: f64) -> Vec<f64> {
+        (0..len).map(|i| 0.5 * (2.0 * PI * frequency * i as f64 / CLIP_SAMPLE_RATE as f64).sin()).collect()
+    }
+
+    #[test]
+    fn test_extract_segment_bounds() {
+        let samples: Vec<f64> = (0..44100).map(|i| i as f64).collect();
+        let segment = extract_segment(&samples, 44100, 500, 250).unwrap();
+        assert_eq!((segment.len(), segment[0]), (11025, 22050.0));
+        // Cut short at the end of the audio.
+        assert_eq!(extract_segment(&samples, 44100, 900, 1000).unwrap().len(), 4410);
+        assert!(extract_segment(&samples, 44100, 1000, 100).is_err());
+    }
+
+    #[test]
+    fn test_noise_is_added_at_the_requested_snr() {
+        let clean = sine(44100, 440.0);
+        let mut rng = StdRng::seed_from_u64(7);
+        for color in [NoiseColor::White, NoiseColor::Pink] {
+            let mut noisy = clean.clone();
+            add_noise(&mut noisy, color, 10.0, &mut rng);
+            let noise: Vec<f64> = noisy.iter().zip(&clean).map(|(n, c)| n - c).collect();
+            let snr_db = 20.0 * (rms(&clean) / rms(&noise)).log10();
+            assert!((snr_db - 10.0).abs() < 1e-6, "{:?} noise at {} dB", color, snr_db);
+        }
+    }
+
+    #[test]
+    fn test_degrade_chain() {
+        let clean = sine(44100, 440.0);
+        let options = DegradeOptions {
+            speed: Some(1.25),
+            reverb_rt60: Some(0.3),
+            gain_db: Some(6.0),
+            clip_level: Some(0.5),
+            ..DegradeOptions::default()
+        };
+        let degraded = degrade(&clean, CLIP_SAMPLE_RATE, &options, &mut StdRng::seed_from_u64(1)).unwrap();
+        // A quarter faster means a fifth shorter.
+        assert!((degraded.len() as i64 - 35280).abs() <= 2, "{} samples", degraded.len());
+        assert!(degraded.iter().all(|sample| sample.abs() <= 0.5));
+        assert!(degraded.iter().any(|sample| sample.abs() == 0.5));
+        assert_eq!(
+            options.describe(),
+            vec!["speed x1.25", "reverb rt60 0.3s", "gain +6 dB", "clipped at 0.5"]
+        );
+
+    }
+
+    #[test]
+    fn test_options_are_validated() {
+        let degrade = DegradeOptions { speed: Some(1.25), clip_level: Some(0.5), ..DegradeOptions::default() };
+        assert!(degrade.validate().is_ok());
+        for invalid in [
+            DegradeOptions { clip_level: Some(1.5), ..DegradeOptions::default() },
+            DegradeOptions { speed: Some(8.0), ..DegradeOptions::default() },
+            DegradeOptions { reverb_rt60: Some(0.0), ..DegradeOptions::default() },
+            DegradeOptions { gain_db: Some(f64::NAN), ..DegradeOptions::default() },
+            DegradeOptions { noise: Some((NoiseColor::Pink, f64::INFINITY)), ..DegradeOptions::default() },
+            DegradeOptions { bitrate_kbps: Some(0), ..DegradeOptions::default() },
+        ] {
+            assert!(invalid.validate().is_err(), "{:?}", invalid);
+        }
+
+        let clip = ClipOptions { count: 1, length_ms: 0, seed: None, degrade };
+        assert!(clip.validate().is_err());
+        assert!(ClipOptions { length_ms: 5000, ..clip }.validate().is_ok());
+    }
+
+    #[test]
+    fn test_convolve_matches_direct_form() {
+        let signal = [1.0, 2.0, 3.0, 4.0];
+        let kernel = [0.5, -1.0];
+        let expected = [0.5, 0.0, -0.5, -1.0, -4.0];
+        let convolved = convolve(&signal, &kernel);
+        assert_eq!(convolved.len(), expected.len());
+        for (got, want) in convolved.iter().zip(expected) {
+            assert!((got - want).abs() < 1e-9);
+        }
+    }
+}
@@ -1,110 +1,84 @@
 use std::error::Error;
-use std::fmt;
-use std::fs;
-use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::path::Path;
 
-/// Converts an input audio file to WAV format with the specified number of channels.
-/// It uses FFmpeg to perform the conversion and writes the result to a temporary file
-/// before renaming it to the final output.
-pub fn convert_to_wav(input_file_path: &str, mut channels: i32) -> Result<String, Box<dyn Error>> {
+use crate::utils;
+use crate::wav::{decoder_for, write_wav_file, ConvertedWav, ScratchDir};
+
+/// Sample rate of the WAV files produced by the conversions.
+const CONVERTED_SAMPLE_RATE: i32 = 44100;
+
+/// Converts an input audio file to a mono 16-bit 44.1 kHz WAV file, in a fresh scratch
+/// directory under `utils::TMP_DIR`. Nothing is written next to
+/// the input; the result is deleted when the returned handle is dropped unless it is
+/// persisted.
+pub fn convert_to_wav(input_file_path: &str) -> Result<ConvertedWav, Box<dyn Error>> {
+    convert_to_wav_in(ScratchDir::new()?, input_file_path)
+}
+
+/// Like `convert_to_wav`, but writes into the given scratch directory.
+pub fn convert_to_wav_in(scratch: ScratchDir, input_file_path: &str) -> Result<ConvertedWav, Box<dyn Error>> {
     // Check if the input file exists.
     if !Path::new(input_file_path).exists() {
         return Err(format!("input file does not exist: {}", input_file_path).into());
     }
-    
-    println!("Converting file: {}", input_file_path);
-    
-    // Force channels to be either 1 or 2, defaulting to 1 if outside the allowed range.
-    if channels < 1 || channels > 2 {
-        channels = 1;
-    }
-
-    let input_path = Path::new(input_file_path);
-    let file_ext = input_path.extension().and_then(|s| s.to_str()).unwrap_or("");
-    let mut output_file = input_file_path.trim_end_matches(&format!(".{}", file_ext)).to_string();
-    output_file.push_str(".wav");
-    
-    println!("Output will be: {}", output_file);
-    
-    // Create a temporary file path in the same directory.
-    let tmp_file = {
-        let mut tmp = input_path.with_file_name(format!("tmp_{}", input_path.file_name().unwrap().to_string_lossy()));
-        tmp.set_extension("wav");  // Make sure temp file has .wav extension
-        tmp
-    };
 
-    // Run FFmpeg with more verbose output
-    let ffmpeg_output = Command::new("ffmpeg")
-        .arg("-y")
-        .arg("-i")
-        .arg(input_file_path)
-        .arg("-c")
-        .arg("pcm_s16le")
-        .arg("-ar")
-        .arg("44100")
-        .arg("-ac")
-        .arg(channels.to_string())
-        .arg(tmp_file.to_str().unwrap())
-        .output()?;
+    println!("Converting file: {}", input_file_path);
 
-    if !ffmpeg_output.status.success() {
-        // Remove tmp file if it exists.
-        let _ = fs::remove_file(&tmp_file);
-        println!("FFmpeg STDERR: {}", String::from_utf8_lossy(&ffmpeg_output.stderr));
-        return Err(format!(
-            "failed to convert to WAV: {}. output: {}, error: {}",
-            ffmpeg_output.status,
-            String::from_utf8_lossy(&ffmpeg_output.stdout),
-            String::from_utf8_lossy(&ffmpeg_output.stderr)
-        ).into());
-    }
+    let file_stem = Path::new(input_file_path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "audio".to_string());
+    let output_file = scratch.file(&format!("{}.wav", file_stem));
 
-    // Verify the file was created and has content
-    if !tmp_file.exists() || fs::metadata(&tmp_file)?.len() == 0 {
-        return Err(format!("FFmpeg did not produce a valid output file").into());
-    }
+    // The scratch directory, and whatever was written to it, is removed if this fails.
+    transcode_to_wav(input_file_path, &output_file)
+        .map_err(|e| format!("failed to convert to WAV: {}", e))?;
 
-    // Rename the temporary file to the output file.
-    fs::rename(&tmp_file, &output_file)
-        .map_err(|e| format!("failed to rename temporary file to output file: {}", e))?;
+    Ok(scratch.into_converted(output_file))
+}
 
-    Ok(output_file)
+/// Decodes `input_file_path` to mono at `CONVERTED_SAMPLE_RATE` and writes it as 16-bit PCM.
+fn transcode_to_wav(input_file_path: &str, output_file_path: &str) -> Result<(), Box<dyn Error>> {
+    let decoder = decoder_for(input_file_path)?;
+    let samples: Vec<f64> = decoder
+        .decode(input_file_path, CONVERTED_SAMPLE_RATE)?
+        .iter()
+        .map(|sample| sample.clamp(-1.0, 1.0))
+        .collect();
+    let data = utils::floats_to_bytes(&samples, 16)?;
+    write_wav_file(output_file_path, &data, CONVERTED_SAMPLE_RATE, 1, 16)
 }
 
-/// Reformats a WAV file with the specified number of channels. The reformatted file will have
-/// "rfm.wav" appended to its original base name.
-pub fn reformat_wav(input_file_path: &str, mut channels: i32) -> Result<String, Box<dyn Error>> {
-    if channels < 1 || channels > 2 {
-        channels = 1;
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
 
-    let input_path = Path::new(input_file_path);
-    let file_ext = input_path.extension().and_then(|s| s.to_str()).unwrap_or("");
-    let mut output_file = input_file_path.trim_end_matches(&format!(".{}", file_ext)).to_string();
-    output_file.push_str("rfm.wav");
+    #[test]
+    fn test_conversion_stays_in_scratch_and_is_cleaned_up() {
+        let input_dir = tempfile::tempdir().unwrap();
+        let input = input_dir.path().join("song.wav");
+        let input = input.to_str().unwrap();
+        let pcm: Vec<u8> = (0..22050i16).flat_map(|i| ((i % 100) * 100).to_le_bytes()).collect();
+        write_wav_file(input, &pcm, 22050, 1, 16).unwrap();
 
-    let ffmpeg_status = Command::new("ffmpeg")
-        .arg("-y")
-        .arg("-i")
-        .arg(input_file_path)
-        .arg("-c")
-        .arg("pcm_s16le")
-        .arg("-ar")
-        .arg("44100")
-        .arg("-ac")
-        .arg(channels.to_string())
-        .arg(&output_file)
-        .output()?;
+        let root = tempfile::tempdir().unwrap();
+        let first = convert_to_wav_in(ScratchDir::new_in(root.path()).unwrap(), input).unwrap();
+        let second = convert_to_wav_in(ScratchDir::new_in(root.path()).unwrap(), input).unwrap();
+        // Concurrent conversions of the same input never share a file.
+        assert_ne!(first.path(), second.path());
+        assert!(Path::new(first.path()).starts_with(root.path()));
+        assert_eq!(first.samples().unwrap().len(), 44100);
+        // Nothing was written beside the input.
+        assert_eq!(fs::read_dir(input_dir.path()).unwrap().count(), 1);
 
-    if !ffmpeg_status.status.success() {
-        return Err(format!(
-            "failed to convert to WAV: {}. output: {}",
-            ffmpeg_status.status,
-            String::from_utf8_lossy(&ffmpeg_status.stdout)
-        )
-        .into());
-    }
+        let scratch = Path::new(first.path()).parent().unwrap().to_path_buf();
+        drop(first);
+        assert!(!scratch.exists());
 
-    Ok(output_file)
+        let kept = root.path().join("kept.wav");
+        second.persist(&kept).unwrap();
+        assert!(kept.exists());
+        assert_eq!(fs::read_dir(root.path()).unwrap().count(), 1);
+    }
 }
@@ -0,0 +1,377 @@
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{Decoder, DecoderOptions};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use crate::shazam;
+use crate::utils;
+use crate::wav::{get_metadata, wav_bytes_to_samples};
+
+/// Basic properties of an audio file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioInfo {
+    pub channels: i32,
+    pub sample_rate: i32,
+    /// Duration in seconds.
+    pub duration: f64,
+}
+
+/// Decodes audio files into mono samples in memory.
+pub trait AudioDecoder {
+    /// Name of the backend, for logs and error messages.
+    fn name(&self) -> &'static str;
+    /// Reads the channel count, sample rate and duration of a file.
+    fn probe(&self, file_path: &str) -> Result<AudioInfo, Box<dyn Error>>;
+    /// Decodes a whole file, averaging its channels down to mono and resampling to
+    /// `sample_rate`, into samples in [-1, 1].
+    fn decode(&self, file_path: &str, sample_rate: i32) -> Result<Vec<f64>, Box<dyn Error>>;
+}
+
+/// Pure-Rust backend for WAV, FLAC, Ogg Vorbis and MP3 files. The format is detected from the
+/// file's contents rather than its extension.
+pub struct NativeDecoder;
+
+impl NativeDecoder {
+    /// Returns whether the file is in a format this backend can read.
+    pub fn can_decode(file_path: &str) -> bool {
+        audrey::open(file_path).is_ok() || Mp3Reader::open(file_path).is_ok()
+    }
+}
+
+impl AudioDecoder for NativeDecoder {
+    fn name(&self) -> &'static str {
+        "native"
+    }
+
+    fn probe(&self, file_path: &str) -> Result<AudioInfo, Box<dyn Error>> {
+        let mut reader = match audrey::open(file_path) {
+            Ok(reader) => reader,
+            Err(_) if let Ok(mp3) = Mp3Reader::open(file_path) => return mp3.probe(),
+            Err(e) => return Err(format!("failed to open {}: {}", file_path, e).into()),
+        };
+        let description = reader.description();
+        let channels = description.channel_count().max(1);
+        let sample_rate = description.sample_rate();
+        let frames = match reader {
+            audrey::read::Reader::Wav(ref wav) => wav.duration() as u64,
+            audrey::read::Reader::Flac(ref flac) if flac.streaminfo().samples.is_some() => {
+                flac.streaminfo().samples.unwrap_or_default()
+            }
+            // Other formats do not record their length up front.
+            _ => reader.samples::<f32>().count() as u64 / channels as u64,
+        };
+        Ok(AudioInfo {
+            channels: channels as i32,
+            sample_rate: sample_rate as i32,
+            duration: frames as f64 / sample_rate.max(1) as f64,
+        })
+    }
+
+    fn decode(&self, file_path: &str, sample_rate: i32) -> Result<Vec<f64>, Box<dyn Error>> {
+        let mut reader = match audrey::open(file_path) {
+            Ok(reader) => reader,
+            Err(_) if let Ok(mp3) = Mp3Reader::open(file_path) => {
+                let (mono, mp3_rate) = mp3.decode_mono()?;
+                return shazam::resample(&mono, mp3_rate, sample_rate);
+            }
+            Err(e) => return Err(format!("failed to open {}: {}", file_path, e).into()),
+        };
+        let description = reader.description();
+        let channels = description.channel_count().max(1) as usize;
+
+        let mut mono = Vec::new();
+        let mut frame_sum = 0.0;
+        for (i, sample) in reader.samples::<f32>().enumerate() {
+            let sample = sample.map_err(|e| format!("failed to decode {}: {}", file_path, e))?;
+            frame_sum += sample as f64;
+            if (i + 1) % channels == 0 {
+                mono.push(frame_sum / channels as f64);
+                frame_sum = 0.0;
+            }
+        }
+        shazam::resample(&mono, description.sample_rate() as i32, sample_rate)
+    }
+}
+
+/// An MP3 file opened with symphonia, the part of the native backend audrey does not cover.
+struct Mp3Reader {
+    file_path: String,
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+}
+
+impl Mp3Reader {
+    /// Opens `file_path` if it holds an MP3 stream, skipping any leading ID3v2 tag.
+    fn open(file_path: &str) -> Result<Mp3Reader, Box<dyn Error>> {
+        let file = File::open(file_path)?;
+        let stream = MediaSourceStream::new(Box::new(file), Default::default());
+        let mut hint = Hint::new();
+        hint.with_extension("mp3");
+        let probed = symphonia::default::get_probe()
+            .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+            .map_err(|e| format!("failed to open {} as MP3: {}", file_path, e))?;
+        let format = probed.format;
+        let track = format.default_track().ok_or_else(|| format!("no audio track in {}", file_path))?;
+        let decoder = symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+        let track_id = track.id;
+        Ok(Mp3Reader { file_path: file_path.to_string(), format, decoder, track_id })
+    }
+
+    fn probe(self) -> Result<AudioInfo, Box<dyn Error>> {
+        let params = self.decoder.codec_params().clone();
+        let channels = params.channels.map_or(1, |channels| channels.count()) as i32;
+        let sample_rate = params.sample_rate.unwrap_or_default() as i32;
+        // Streams without a Xing/VBRI header do not record their length, so decode to find it.
+        let frames = match params.n_frames {
+            Some(frames) => frames,
+            None => self.decode_mono()?.0.len() as u64,
+        };
+        Ok(AudioInfo { channels, sample_rate, duration: frames as f64 / sample_rate.max(1) as f64 })
+    }
+
+    /// Decodes the whole stream, averaging the channels down to mono, and returns the samples
+    /// with their sample rate.
+    fn decode_mono(mut self) -> Result<(Vec<f64>, i32), Box<dyn Error>> {
+        let mut mono = Vec::new();
+        let mut sample_rate = self.decoder.codec_params().sample_rate.unwrap_or_default();
+        let mut buffer: Option<SampleBuffer<f32>> = None;
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(format!("failed to read {}: {}", self.file_path, e).into()),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // A corrupt frame is skipped, as players do.
+                Err(SymphoniaError::DecodeError(_)) => continue,
+                Err(e) => return Err(format!("failed to decode {}: {}", self.file_path, e).into()),
+            };
+            let spec = *decoded.spec();
+            sample_rate = spec.rate;
+            let buffer = match buffer.as_mut() {
+                Some(buffer) if buffer.capacity() >= decoded.capacity() * spec.channels.count() => buffer,
+                _ => buffer.insert(SampleBuffer::new(decoded.capacity() as u64, spec)),
+            };
+            buffer.copy_interleaved_ref(decoded);
+            let channels = spec.channels.count().max(1);
+            mono.extend(
+                buffer
+                    .samples()
+                    .chunks_exact(channels)
+                    .map(|frame| frame.iter().map(|&s| s as f64).sum::<f64>() / channels as f64),
+            );
+        }
+        Ok((mono, sample_rate as i32))
+    }
+}
+
+/// Backend that shells out to ffmpeg and ffprobe, for formats the native backend cannot read.
+pub struct FfmpegDecoder;
+
+impl FfmpegDecoder {
+    /// Returns whether an ffmpeg executable can be run.
+    pub fn is_available() -> bool {
+        Command::new("ffmpeg")
+            .arg("-version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    }
+}
+
+impl AudioDecoder for FfmpegDecoder {
+    fn name(&self) -> &'static str {
+        "ffmpeg"
+    }
+
+    fn probe(&self, file_path: &str) -> Result<AudioInfo, Box<dyn Error>> {
+        let metadata = get_metadata(file_path)?;
+        let stream = metadata
+            .streams
+            .iter()
+            .find(|stream| stream.codec_type == "audio")
+            .ok_or_else(|| format!("no audio stream in {}", file_path))?;
+        let sample_rate = stream.sample_rate.as_deref().unwrap_or_default();
+        Ok(AudioInfo {
+            channels: stream.channels.unwrap_or(1),
+            sample_rate: sample_rate.parse().map_err(|e| format!("invalid sample rate {:?}: {}", sample_rate, e))?,
+            duration: metadata
+                .format
+                .duration
+                .parse()
+                .map_err(|e| format!("invalid duration {:?}: {}", metadata.format.duration, e))?,
+        })
+    }
+
+    fn decode(&self, file_path: &str, sample_rate: i32) -> Result<Vec<f64>, Box<dyn Error>> {
+        // Have ffmpeg write raw mono 16-bit PCM to stdout instead of going through a file.
+        let output = Command::new("ffmpeg")
+            .args(["-v", "error", "-i", file_path, "-f", "s16le", "-acodec", "pcm_s16le", "-ac", "1", "-ar"])
+            .arg(sample_rate.to_string())
+            .arg("-")
+            .output()?;
+        if !output.status.success() {
+            return Err(format!(
+                "ffmpeg failed to decode {}: {}. error: {}",
+                file_path,
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            )
+            .into());
+        }
+        let mut pcm = output.stdout;
+        pcm.truncate(pcm.len() & !1);
+        wav_bytes_to_samples(&pcm)
+    }
+}
+
+/// Which backend decodes a file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Backend {
+    Native,
+    Ffmpeg,
+}
+
+/// Picks the backend for a file from the `AUDIO_DECODER` preference ("auto", "native" or
+/// "ffmpeg") and what is possible: "auto" prefers the native backend and falls back to ffmpeg.
+fn choose_backend(preference: &str, native_supported: bool, ffmpeg_available: bool) -> Result<Backend, Box<dyn Error>> {
+    match preference {
+        "native" => Ok(Backend::Native),
+        "ffmpeg" => Ok(Backend::Ffmpeg),
+        "auto" if native_supported => Ok(Backend::Native),
+        "auto" if ffmpeg_available => Ok(Backend::Ffmpeg),
+        "auto" => Err("the native decoder supports WAV, FLAC, Ogg Vorbis and MP3 only, and ffmpeg is not installed".into()),
+        other => Err(format!("unknown AUDIO_DECODER {:?}: expected auto, native or ffmpeg", other).into()),
+    }
+}
+
+/// Returns the decoder to use for a file, as selected by the `AUDIO_DECODER` environment
+/// variable (default "auto").
+pub fn decoder_for(file_path: &str) -> Result<Box<dyn AudioDecoder>, Box<dyn Error>> {
+    if !Path::new(file_path).exists() {
+        return Err(format!("input file does not exist: {}", file_path).into());
+    }
+    let preference = utils::get_env("AUDIO_DECODER", Some("auto"));
+    // Only probe for ffmpeg when it may be needed.
+    let native_supported = NativeDecoder::can_decode(file_path);
+    let ffmpeg_available = !native_supported && FfmpegDecoder::is_available();
+    match choose_backend(&preference, native_supported, ffmpeg_available)
+        .map_err(|e| format!("cannot decode {}: {}", file_path, e))?
+    {
+        Backend::Native => Ok(Box::new(NativeDecoder)),
+        Backend::Ffmpeg => Ok(Box::new(FfmpegDecoder)),
+    }
+}
+
+/// Decodes a file with the selected backend into mono samples at `sample_rate`.
+pub fn decode_audio(file_path: &str, sample_rate: i32) -> Result<Vec<f64>, Box<dyn Error>> {
+    decoder_for(file_path)?.decode(file_path, sample_rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    use crate::wav::write_wav_file;
+
+    /// Writes a stereo 16-bit WAV with a 440 Hz sine on the left channel and silence on the right.
+    fn write_stereo_sine(path: &str, sample_rate: i32, seconds: f64) {
+        let frames = (sample_rate as f64 * seconds) as usize;
+        let mut data = Vec::with_capacity(frames * 4);
+        for i in 0..frames {
+            let left = (0.5 * (2.0 * PI * 440.0 * i as f64 / sample_rate as f64).sin() * 32767.0) as i16;
+            data.extend_from_slice(&left.to_le_bytes());
+            data.extend_from_slice(&0i16.to_le_bytes());
+        }
+        write_wav_file(path, &data, sample_rate, 2, 16).unwrap();
+    }
+
+    #[test]
+    fn test_native_decoder_downmixes_and_resamples() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sine.wav");
+        let path = path.to_str().unwrap();
+        write_stereo_sine(path, 22050, 2.0);
+
+        assert!(NativeDecoder::can_decode(path));
+        let info = NativeDecoder.probe(path).unwrap();
+        assert_eq!(info, AudioInfo { channels: 2, sample_rate: 22050, duration: 2.0 });
+
+        let samples = NativeDecoder.decode(path, 11025).unwrap();
+        assert_eq!(samples.len(), 22050);
+        // Averaging with the silent channel halves the amplitude; resampling keeps the pitch.
+        for (i, &sample) in samples.iter().enumerate().skip(100).take(10_000) {
+            let expected = 0.25 * (2.0 * PI * 440.0 * i as f64 / 11025.0).sin();
+            assert!((sample - expected).abs() < 0.01, "sample {} is {} instead of {}", i, sample, expected);
+        }
+    }
+
+    /// Writes `frames` MPEG-1 Layer III frames of digital silence (128 kbit/s, 44.1 kHz,
+    /// stereo) after an optional ID3v2 tag. Zeroed side information decodes to silence.
+    fn write_silent_mp3(path: &str, frames: usize, id3: bool) {
+        let mut bytes = Vec::new();
+        if id3 {
+            bytes.extend_from_slice(b"ID3\x04\x00\x00\x00\x00\x00\x0a");
+            bytes.extend_from_slice(&[0; 10]);
+        }
+        for _ in 0..frames {
+            // 144 * 128000 / 44100 = 417 bytes per frame, header included.
+            bytes.extend_from_slice(&[0xff, 0xfb, 0x90, 0x00]);
+            bytes.extend_from_slice(&[0; 413]);
+        }
+        std::fs::write(path, bytes).unwrap();
+    }
+
+    #[test]
+    fn test_native_decoder_reads_mp3() {
+        let dir = tempfile::tempdir().unwrap();
+        for id3 in [false, true] {
+            let path = dir.path().join(format!("silence-{}.mp3", id3));
+            let path = path.to_str().unwrap();
+            write_silent_mp3(path, 40, id3);
+
+            assert!(NativeDecoder::can_decode(path), "id3 {}", id3);
+            let info = NativeDecoder.probe(path).unwrap();
+            assert_eq!((info.channels, info.sample_rate), (2, 44100));
+            assert!((info.duration - 40.0 * 1152.0 / 44100.0).abs() < 0.1, "{} s", info.duration);
+
+            let samples = NativeDecoder.decode(path, 11025).unwrap();
+            assert!((samples.len() as i64 - 40 * 1152 / 4).abs() <= 1152, "{} samples", samples.len());
+            assert!(samples.iter().all(|&sample| sample.abs() < 1e-6));
+        }
+    }
+
+    #[test]
+    fn test_native_decoder_rejects_unknown_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("song.mp3");
+        std::fs::write(&path, b"ID3\x04\x00\x00not really audio").unwrap();
+        assert!(!NativeDecoder::can_decode(path.to_str().unwrap()));
+    }
+
+    #[test]
+    fn test_choose_backend() {
+        assert_eq!(choose_backend("auto", true, true).unwrap(), Backend::Native);
+        assert_eq!(choose_backend("auto", false, true).unwrap(), Backend::Ffmpeg);
+        assert!(choose_backend("auto", false, false).is_err());
+        assert_eq!(choose_backend("ffmpeg", true, false).unwrap(), Backend::Ffmpeg);
+        assert_eq!(choose_backend("native", false, true).unwrap(), Backend::Native);
+        assert!(choose_backend("gstreamer", true, true).is_err());
+    }
+}
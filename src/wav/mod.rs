@@ -1,4 +1,18 @@
+mod clip;
+pub use clip::*;
 mod convert;
 pub use convert::*;
+mod decoder;
+pub use decoder::*;
+mod pcm;
+pub use pcm::*;
+mod riff;
+pub use riff::*;
+mod scratch;
+pub use scratch::*;
+mod tag_writer;
+pub use tag_writer::*;
+mod tags;
+pub use tags::*;
 mod wav;
 pub use wav::*;
\ No newline at end of file
@@ -0,0 +1,181 @@
+use std::error::Error;
+use std::io::{Cursor, Read};
+
+use crate::wav::{downmix_to_mono, parse_wav_layout, pcm_bytes_to_samples, WavFormat, WAVE_FORMAT_IEEE_FLOAT, WAVE_FORMAT_PCM};
+
+/// Sample encodings accepted for headerless PCM input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PcmEncoding {
+    /// Signed 16-bit little-endian integers.
+    S16le,
+    /// 32-bit little-endian IEEE floats.
+    F32le,
+}
+
+impl PcmEncoding {
+    /// Parses an encoding name as given on the command line ("s16le" or "f32le").
+    pub fn parse(name: &str) -> Result<PcmEncoding, Box<dyn Error>> {
+        match name {
+            "s16le" => Ok(PcmEncoding::S16le),
+            "f32le" => Ok(PcmEncoding::F32le),
+            other => Err(format!("unknown PCM format {:?}: expected s16le or f32le", other).into()),
+        }
+    }
+}
+
+/// How to interpret audio input that has no header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawPcmFormat {
+    pub encoding: PcmEncoding,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl Default for RawPcmFormat {
+    fn default() -> Self {
+        RawPcmFormat { encoding: PcmEncoding::S16le, sample_rate: 44100, channels: 1 }
+    }
+}
+
+impl RawPcmFormat {
+    /// The equivalent `fmt ` chunk contents. Fails if the format is empty or a frame would not
+    /// fit the chunk's 16-bit block size.
+    fn wav_format(&self) -> Result<WavFormat, Box<dyn Error>> {
+        if self.channels == 0 || self.sample_rate == 0 {
+            return Err("raw PCM input needs at least one channel and a non-zero sample rate".into());
+        }
+        let (format_tag, bits_per_sample) = match self.encoding {
+            PcmEncoding::S16le => (WAVE_FORMAT_PCM, 16),
+            PcmEncoding::F32le => (WAVE_FORMAT_IEEE_FLOAT, 32),
+        };
+        let block_align = u16::try_from(self.channels as u32 * bits_per_sample as u32 / 8)
+            .map_err(|_| format!("too many channels for raw PCM input: {}", self.channels))?;
+        Ok(WavFormat {
+            format_tag,
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+            block_align,
+            bits_per_sample,
+            valid_bits_per_sample: bits_per_sample,
+            channel_mask: None,
+        })
+    }
+}
+
+/// Decodes an in-memory audio stream into mono samples and their sample rate. Input that
+/// starts with a RIFF (or RF64) header is read as a WAV file; anything else is taken to be
+/// headerless PCM in the `raw` format, with an incomplete trailing frame dropped.
+pub fn decode_pcm_stream(bytes: &[u8], raw: &RawPcmFormat) -> Result<(Vec<f64>, i32), Box<dyn Error>> {
+    let (format, data) = if bytes.starts_with(b"RIFF") || bytes.starts_with(b"RF64") || bytes.starts_with(b"BW64") {
+        let layout = parse_wav_layout(&mut Cursor::new(bytes))?;
+        let start = layout.data_offset as usize;
+        (layout.format, &bytes[start..start + layout.data_len as usize])
+    } else {
+        (raw.wav_format()?, bytes)
+    };
+    let sample_rate = format.sample_rate as i32;
+
+    // Any incomplete trailing frame stays behind in the decoder and is dropped.
+    let samples = PcmStreamDecoder::with_format(format)?.push(data)?;
+    Ok((samples, sample_rate))
+}
+
+/// Decodes headerless PCM that arrives in arbitrary chunks, such as reads from a live stream,
+/// into mono samples.
+pub struct PcmStreamDecoder {
+    format: WavFormat,
+    /// Bytes of an incomplete frame held back until the rest of it arrives.
+    leftover: Vec<u8>,
+}
+
+impl PcmStreamDecoder {
+    pub fn new(raw: &RawPcmFormat) -> Result<PcmStreamDecoder, Box<dyn Error>> {
+        PcmStreamDecoder::with_format(raw.wav_format()?)
+    }
+
+    fn with_format(format: WavFormat) -> Result<PcmStreamDecoder, Box<dyn Error>> {
+        if format.block_align == 0 || format.channels == 0 {
+            return Err("PCM input needs at least one channel and a non-zero frame size".into());
+        }
+        Ok(PcmStreamDecoder { format, leftover: Vec::new() })
+    }
+
+    /// Decodes the complete frames in `bytes`, together with any partial frame left over from
+    /// the previous call. An incomplete trailing frame is kept for the next call.
+    pub fn push(&mut self, bytes: &[u8]) -> Result<Vec<f64>, Box<dyn Error>> {
+        self.leftover.extend_from_slice(bytes);
+        let frame_bytes = self.format.block_align as usize;
+        let complete = self.leftover.len() - self.leftover.len() % frame_bytes;
+        let samples = pcm_bytes_to_samples(&self.leftover[..complete], &self.format)?;
+        self.leftover.drain(..complete);
+        Ok(downmix_to_mono(&samples, self.format.channels as usize))
+    }
+}
+
+/// Reads `reader` to the end and decodes it with `decode_pcm_stream`.
+pub fn read_pcm_stream(mut reader: impl Read, raw: &RawPcmFormat) -> Result<(Vec<f64>, i32), Box<dyn Error>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    if bytes.is_empty() {
+        return Err("no audio data in the input".into());
+    }
+    decode_pcm_stream(&bytes, raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wav::write_wav_file;
+
+    #[test]
+    fn test_raw_s16le_stereo_is_downmixed() {
+        let raw = RawPcmFormat { encoding: PcmEncoding::S16le, sample_rate: 8000, channels: 2 };
+        let mut bytes = Vec::new();
+        for (left, right) in [(16384i16, 0i16), (-32768, -32768)] {
+            bytes.extend(left.to_le_bytes());
+            bytes.extend(right.to_le_bytes());
+        }
+        // A trailing partial frame is ignored.
+        bytes.push(7);
+        let (samples, rate) = read_pcm_stream(&bytes[..], &raw).unwrap();
+        assert_eq!(rate, 8000);
+        assert_eq!(samples, vec![0.25, -1.0]);
+    }
+
+    #[test]
+    fn test_raw_f32le() {
+        let raw = RawPcmFormat { encoding: PcmEncoding::F32le, ..RawPcmFormat::default() };
+        let bytes: Vec<u8> = [0.5f32, -0.25].iter().flat_map(|s| s.to_le_bytes()).collect();
+        assert_eq!(decode_pcm_stream(&bytes, &raw).unwrap(), (vec![0.5, -0.25], 44100));
+        assert!(PcmEncoding::parse("u8").is_err());
+        // 5000 float channels overflow 16-bit arithmetic but still fit a WAV block; 20000 do not.
+        let wide = RawPcmFormat { channels: 5000, ..raw.clone() };
+        assert_eq!(wide.wav_format().unwrap().block_align, 20000);
+        let too_wide = RawPcmFormat { channels: 20000, ..raw };
+        assert!(decode_pcm_stream(&bytes, &too_wide).is_err());
+    }
+
+    #[test]
+    fn test_stream_decoder_keeps_partial_frames() {
+        let raw = RawPcmFormat { channels: 2, ..RawPcmFormat::default() };
+        let mut decoder = PcmStreamDecoder::new(&raw).unwrap();
+        // Two stereo frames: (16384, -16384) and (32767, 32767), split mid-frame.
+        let bytes: Vec<u8> = [16384i16, -16384, 32767, 32767].iter().flat_map(|s| s.to_le_bytes()).collect();
+        assert_eq!(decoder.push(&bytes[..5]).unwrap(), vec![0.0]);
+        assert_eq!(decoder.push(&bytes[5..]).unwrap(), vec![32767.0 / 32768.0]);
+        assert!(decoder.push(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_wav_header_overrides_raw_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clip.wav");
+        let path = path.to_str().unwrap();
+        write_wav_file(path, &[0, 64, 0, 64, 0, 192, 0, 192], 22050, 2, 16).unwrap();
+
+        let bytes = std::fs::read(path).unwrap();
+        let raw = RawPcmFormat { encoding: PcmEncoding::F32le, sample_rate: 8000, channels: 1 };
+        assert_eq!(decode_pcm_stream(&bytes, &raw).unwrap(), (vec![0.5, -0.5], 22050));
+        assert!(read_pcm_stream(&b""[..], &raw).is_err());
+    }
+}
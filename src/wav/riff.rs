@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+/// `wFormatTag` of integer PCM.
+pub const WAVE_FORMAT_PCM: u16 = 0x0001;
+/// `wFormatTag` of IEEE floating-point samples.
+pub const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+/// `wFormatTag` whose actual format is given by the sub-format GUID of the extended `fmt ` chunk.
+pub const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Bytes 2..16 of every `KSDATAFORMAT_SUBTYPE_*` GUID; the first two bytes hold the format tag.
+const SUBFORMAT_GUID_TAIL: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+
+/// Size field value meaning "see the ds64 chunk" in RF64 files (and "unknown" in streamed WAVs).
+const SIZE_IN_DS64: u32 = 0xFFFF_FFFF;
+
+/// The sample format described by a `fmt ` chunk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WavFormat {
+    /// `WAVE_FORMAT_PCM` or `WAVE_FORMAT_IEEE_FLOAT` (resolved from the sub-format GUID for
+    /// extensible files), or whatever other tag the file declares.
+    pub format_tag: u16,
+    pub channels: u16,
+    pub sample_rate: u32,
+    /// Bytes per frame (one sample of every channel).
+    pub block_align: u16,
+    /// Size of the sample container, in bits.
+    pub bits_per_sample: u16,
+    /// Bits actually used within the container; equal to `bits_per_sample` unless an
+    /// extensible `fmt ` chunk says otherwise.
+    pub valid_bits_per_sample: u16,
+    /// Speaker positions of the channels, from an extensible `fmt ` chunk.
+    pub channel_mask: Option<u32>,
+}
+
+/// A chunk of a RIFF file: its ID, the offset of its header and the size of its body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RiffChunk {
+    pub id: [u8; 4],
+    pub offset: u64,
+    pub size: u64,
+}
+
+impl RiffChunk {
+    /// Offset just past the chunk, including its pad byte.
+    pub fn end(&self) -> u64 {
+        self.offset + 8 + self.size + (self.size & 1)
+    }
+}
+
+/// Where the parts of a RIFF/WAVE (or RF64) file are, found by walking its chunks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WavLayout {
+    pub format: WavFormat,
+    /// Byte offset of the first sample in the file.
+    pub data_offset: u64,
+    /// Length of the sample data in bytes, clamped to what the file actually contains.
+    pub data_len: u64,
+    /// Text tags from a `LIST`/`INFO` chunk, keyed by their four-character ID (e.g. "INAM").
+    pub info: HashMap<String, String>,
+    /// Offset and length of an embedded ID3v2 tag (an `id3 ` or `ID3 ` chunk).
+    pub id3: Option<(u64, u64)>,
+    /// Whether the file uses the RF64 64-bit size extension.
+    pub rf64: bool,
+    /// Every chunk after the RIFF header, in file order.
+    pub chunks: Vec<RiffChunk>,
+}
+
+impl WavLayout {
+    /// Number of complete frames in the data chunk.
+    pub fn frame_count(&self) -> u64 {
+        self.data_len / self.format.block_align.max(1) as u64
+    }
+
+    /// Duration of the audio in seconds.
+    pub fn duration(&self) -> f64 {
+        self.frame_count() as f64 / self.format.sample_rate.max(1) as f64
+    }
+}
+
+/// Parses the chunk structure of a RIFF/WAVE or RF64 file. The `fmt ` and `data` chunks may
+/// appear anywhere and in any order among other chunks (`LIST`, `fact`, `bext`, `JUNK`, ...),
+/// which are skipped, honouring the pad byte after odd-sized chunks. The reader is left at an
+/// unspecified position.
+pub fn parse_wav_layout<R: Read + Seek>(reader: &mut R) -> Result<WavLayout, Box<dyn Error>> {
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    let mut riff_id = [0u8; 4];
+    let mut wave_id = [0u8; 4];
+    reader.read_exact(&mut riff_id).map_err(|_| "invalid WAV file size (too small)")?;
+    let _riff_size = reader.read_u32::<LittleEndian>().map_err(|_| "invalid WAV file size (too small)")?;
+    reader.read_exact(&mut wave_id).map_err(|_| "invalid WAV file size (too small)")?;
+    let rf64 = match &riff_id {
+        b"RIFF" => false,
+        b"RF64" | b"BW64" => true,
+        _ => return Err("not a RIFF file".into()),
+    };
+    if &wave_id != b"WAVE" {
+        return Err("not a WAVE file".into());
+    }
+
+    let mut format = None;
+    let mut data = None;
+    let mut ds64_data_size = None;
+    let mut info = HashMap::new();
+    let mut id3 = None;
+    let mut chunks = Vec::new();
+
+    let mut position = 12u64;
+    while position + 8 <= file_len {
+        reader.seek(SeekFrom::Start(position))?;
+        let mut id = [0u8; 4];
+        reader.read_exact(&mut id)?;
+        let size = reader.read_u32::<LittleEndian>()?;
+        let body = position + 8;
+
+        let size = match &id {
+            b"ds64" => {
+                let _riff_size = reader.read_u64::<LittleEndian>()?;
+                ds64_data_size = Some(reader.read_u64::<LittleEndian>()?);
+                size as u64
+            }
+            b"fmt " => {
+                format = Some(parse_format(reader, size)?);
+                size as u64
+            }
+            b"data" => {
+                let size = match (size, ds64_data_size) {
+                    (SIZE_IN_DS64, Some(size)) if rf64 => size,
+                    // A writer that could not seek back leaves the size unset; take the rest.
+                    (SIZE_IN_DS64, _) => file_len - body,
+                    (size, _) => size as u64,
+                };
+                data = Some((body, size.min(file_len - body)));
+                size
+            }
+            b"LIST" => {
+                let mut list_type = [0u8; 4];
+                if size >= 4 && reader.read_exact(&mut list_type).is_ok() && &list_type == b"INFO" {
+                    let end = (body + size as u64).min(file_len);
+                    parse_info(reader, body + 4, end, &mut info)?;
+                }
+                size as u64
+            }
+            b"id3 " | b"ID3 " => {
+                id3 = Some((body, (size as u64).min(file_len - body)));
+                size as u64
+            }
+            _ => size as u64,
+        };
+        chunks.push(RiffChunk { id, offset: position, size });
+
+        // Chunks are padded to an even length.
+        position = body.saturating_add(size).saturating_add(size & 1);
+    }
+
+    let format = format.ok_or("WAV file has no fmt chunk")?;
+    let (data_offset, data_len) = data.ok_or("WAV file has no data chunk")?;
+    Ok(WavLayout { format, data_offset, data_len, info, id3, rf64, chunks })
+}
+
+/// Parses the body of a `fmt ` chunk of `size` bytes, resolving extensible formats.
+fn parse_format<R: Read>(reader: &mut R, size: u32) -> Result<WavFormat, Box<dyn Error>> {
+    if size < 16 {
+        return Err(format!("fmt chunk is too small ({} bytes)", size).into());
+    }
+    let format_tag = reader.read_u16::<LittleEndian>()?;
+    let channels = reader.read_u16::<LittleEndian>()?;
+    let sample_rate = reader.read_u32::<LittleEndian>()?;
+    let _bytes_per_sec = reader.read_u32::<LittleEndian>()?;
+    let block_align = reader.read_u16::<LittleEndian>()?;
+    let bits_per_sample = reader.read_u16::<LittleEndian>()?;
+    let mut format = WavFormat {
+        format_tag,
+        channels,
+        sample_rate,
+        block_align,
+        bits_per_sample,
+        valid_bits_per_sample: bits_per_sample,
+        channel_mask: None,
+    };
+
+    if format.format_tag == WAVE_FORMAT_EXTENSIBLE {
+        if size < 40 {
+            return Err(format!("extensible fmt chunk is too small ({} bytes)", size).into());
+        }
+        let _extension_size = reader.read_u16::<LittleEndian>()?;
+        let valid_bits = reader.read_u16::<LittleEndian>()?;
+        format.channel_mask = Some(reader.read_u32::<LittleEndian>()?);
+        let mut guid = [0u8; 16];
+        reader.read_exact(&mut guid)?;
+        if guid[2..] != SUBFORMAT_GUID_TAIL {
+            return Err(format!("unsupported WAVE_FORMAT_EXTENSIBLE sub-format {:02x?}", guid).into());
+        }
+        format.format_tag = u16::from_le_bytes([guid[0], guid[1]]);
+        if valid_bits != 0 {
+            format.valid_bits_per_sample = valid_bits;
+        }
+    }
+
+    if format.channels == 0 || format.sample_rate == 0 || format.block_align == 0 {
+        return Err("invalid WAV format: zero channels, sample rate or block size".into());
+    }
+    Ok(format)
+}
+
+/// Reads the `INFO` sub-chunks between `start` and `end`, each a NUL-terminated string.
+fn parse_info<R: Read + Seek>(
+    reader: &mut R,
+    start: u64,
+    end: u64,
+    info: &mut HashMap<String, String>,
+) -> Result<(), Box<dyn Error>> {
+    let mut position = start;
+    while position + 8 <= end {
+        reader.seek(SeekFrom::Start(position))?;
+        let mut id = [0u8; 4];
+        reader.read_exact(&mut id)?;
+        let size = reader.read_u32::<LittleEndian>()? as u64;
+        let mut value = vec![0u8; size.min(end - position - 8) as usize];
+        match reader.read_exact(&mut value) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let text = String::from_utf8_lossy(&value);
+        let text = text.trim_end_matches('\0').trim();
+        if !text.is_empty() {
+            info.insert(String::from_utf8_lossy(&id).into_owned(), text.to_string());
+        }
+        position += 8 + size + (size & 1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        bytes
+    }
+
+    fn pcm_fmt(channels: u16, sample_rate: u32, bits: u16) -> Vec<u8> {
+        let block_align = channels * bits / 8;
+        let mut body = Vec::new();
+        body.extend_from_slice(&WAVE_FORMAT_PCM.to_le_bytes());
+        body.extend_from_slice(&channels.to_le_bytes());
+        body.extend_from_slice(&sample_rate.to_le_bytes());
+        body.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        body.extend_from_slice(&block_align.to_le_bytes());
+        body.extend_from_slice(&bits.to_le_bytes());
+        body
+    }
+
+    fn riff(id: &[u8; 4], chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body: Vec<u8> = chunks.concat();
+        let mut bytes = id.to_vec();
+        bytes.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+        bytes.extend_from_slice(b"WAVE");
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+
+    #[test]
+    fn test_finds_chunks_after_metadata_and_padding() {
+        let list = chunk(b"LIST", &[b"INFO".to_vec(), chunk(b"INAM", b"Song\0"), chunk(b"IART", b"Band\0")].concat());
+        let file = riff(
+            b"RIFF",
+            &[
+                chunk(b"JUNK", &[0; 27]),
+                chunk(b"bext", &[1; 3]),
+                chunk(b"fmt ", &pcm_fmt(2, 48000, 16)),
+                list,
+                chunk(b"fact", &[0; 4]),
+                chunk(b"data", &[0; 400]),
+            ],
+        );
+
+        let layout = parse_wav_layout(&mut Cursor::new(&file)).unwrap();
+        assert_eq!((layout.format.channels, layout.format.sample_rate, layout.format.bits_per_sample), (2, 48000, 16));
+        assert_eq!(layout.data_len, 400);
+        assert_eq!(&file[layout.data_offset as usize - 8..layout.data_offset as usize - 4], b"data");
+        assert_eq!(layout.frame_count(), 100);
+        assert_eq!(layout.info.get("INAM").map(String::as_str), Some("Song"));
+        assert_eq!(layout.info.get("IART").map(String::as_str), Some("Band"));
+        assert!(!layout.rf64);
+    }
+
+    #[test]
+    fn test_data_before_fmt_and_truncated_data() {
+        let mut file = riff(b"RIFF", &[chunk(b"data", &[0; 100]), chunk(b"fmt ", &pcm_fmt(1, 8000, 16))]);
+        let layout = parse_wav_layout(&mut Cursor::new(&file)).unwrap();
+        assert_eq!((layout.data_offset, layout.data_len), (20, 100));
+
+        // A data chunk claiming more bytes than the file holds is clamped.
+        let mut file2 = riff(b"RIFF", &[chunk(b"fmt ", &pcm_fmt(1, 8000, 16)), chunk(b"data", &[0; 100])]);
+        file2.truncate(file2.len() - 40);
+        assert_eq!(parse_wav_layout(&mut Cursor::new(&file2)).unwrap().data_len, 60);
+
+        file.truncate(12);
+        assert!(parse_wav_layout(&mut Cursor::new(&file)).is_err());
+    }
+
+    #[test]
+    fn test_extensible_format() {
+        let mut body = pcm_fmt(2, 96000, 32);
+        body[0..2].copy_from_slice(&WAVE_FORMAT_EXTENSIBLE.to_le_bytes());
+        body.extend_from_slice(&22u16.to_le_bytes());
+        body.extend_from_slice(&24u16.to_le_bytes());
+        body.extend_from_slice(&3u32.to_le_bytes());
+        body.extend_from_slice(&WAVE_FORMAT_IEEE_FLOAT.to_le_bytes());
+        body.extend_from_slice(&SUBFORMAT_GUID_TAIL);
+        let file = riff(b"RIFF", &[chunk(b"fmt ", &body), chunk(b"data", &[0; 16])]);
+
+        let format = parse_wav_layout(&mut Cursor::new(&file)).unwrap().format;
+        assert_eq!(format.format_tag, WAVE_FORMAT_IEEE_FLOAT);
+        assert_eq!((format.bits_per_sample, format.valid_bits_per_sample), (32, 24));
+        assert_eq!(format.channel_mask, Some(3));
+
+        let mut unknown = body.clone();
+        unknown[30] ^= 0xFF;
+        let file = riff(b"RIFF", &[chunk(b"fmt ", &unknown), chunk(b"data", &[0; 16])]);
+        assert!(parse_wav_layout(&mut Cursor::new(&file)).is_err());
+    }
+
+    #[test]
+    fn test_rf64_sizes_come_from_ds64() {
+        let mut ds64 = Vec::new();
+        ds64.extend_from_slice(&0u64.to_le_bytes());
+        ds64.extend_from_slice(&64u64.to_le_bytes());
+        ds64.extend_from_slice(&32u64.to_le_bytes());
+        ds64.extend_from_slice(&0u32.to_le_bytes());
+        let mut data = chunk(b"data", &[0; 64]);
+        data[4..8].copy_from_slice(&SIZE_IN_DS64.to_le_bytes());
+        // Trailing chunk after the data, which a wrong data size would swallow.
+        let file = riff(b"RF64", &[chunk(b"ds64", &ds64), chunk(b"fmt ", &pcm_fmt(1, 44100, 16)), data, chunk(b"JUNK", &[0; 8])]);
+
+        let layout = parse_wav_layout(&mut Cursor::new(&file)).unwrap();
+        assert!(layout.rf64);
+        assert_eq!(layout.data_len, 64);
+        assert_eq!(layout.frame_count(), 32);
+    }
+
+    #[test]
+    fn test_rejects_non_wave_files() {
+        assert!(parse_wav_layout(&mut Cursor::new(b"ID3\x04\x00\x00\x00\x00\x00\x00\x00\x00")).is_err());
+        let no_data = riff(b"RIFF", &[chunk(b"fmt ", &pcm_fmt(1, 8000, 16))]);
+        assert!(parse_wav_layout(&mut Cursor::new(&no_data)).is_err());
+    }
+}
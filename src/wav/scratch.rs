@@ -0,0 +1,80 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::utils;
+use crate::wav::read_wav_info;
+
+/// A private directory under `utils::TMP_DIR` for the intermediate files of one operation,
+/// removed with everything in it when dropped. Every instance gets a unique name, so
+/// concurrent requests never see each other's files.
+pub struct ScratchDir {
+    dir: tempfile::TempDir,
+}
+
+impl ScratchDir {
+    /// Creates a scratch directory under `utils::TMP_DIR`.
+    pub fn new() -> Result<ScratchDir, Box<dyn Error>> {
+        ScratchDir::new_in(utils::TMP_DIR)
+    }
+
+    /// Creates a scratch directory under `root`, creating `root` if needed.
+    pub fn new_in(root: impl AsRef<Path>) -> Result<ScratchDir, Box<dyn Error>> {
+        let root = root.as_ref();
+        fs::create_dir_all(root).map_err(|e| format!("failed to create {}: {}", root.display(), e))?;
+        let dir = tempfile::Builder::new()
+            .prefix("scratch-")
+            .tempdir_in(root)
+            .map_err(|e| format!("failed to create a scratch directory in {}: {}", root.display(), e))?;
+        Ok(ScratchDir { dir })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Returns the path of a file named `file_name` in the directory. Only the last component
+    /// of `file_name` is used, so a name taken from user input cannot escape the directory.
+    pub fn file(&self, file_name: &str) -> String {
+        let name = Path::new(file_name)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "file".to_string());
+        self.dir.path().join(name).to_string_lossy().into_owned()
+    }
+
+    /// Wraps a WAV file written into this directory, tying its lifetime to the directory's.
+    pub(crate) fn into_converted(self, path: String) -> ConvertedWav {
+        ConvertedWav { path, scratch: self }
+    }
+}
+
+/// A WAV file produced in its own scratch directory. The file is deleted when this is dropped,
+/// unless it has been moved out with `persist`.
+pub struct ConvertedWav {
+    path: String,
+    scratch: ScratchDir,
+}
+
+impl ConvertedWav {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Reads the file's samples, averaged down to mono.
+    pub fn samples(&self) -> Result<Vec<f64>, Box<dyn Error>> {
+        read_wav_info(&self.path)?.mono_samples()
+    }
+
+    /// Moves the file to `destination`, copying it when it cannot be renamed (e.g. across
+    /// filesystems). The scratch directory is removed either way.
+    pub fn persist(self, destination: impl AsRef<Path>) -> Result<PathBuf, Box<dyn Error>> {
+        let destination = destination.as_ref();
+        if fs::rename(&self.path, destination).is_err() {
+            fs::copy(&self.path, destination)
+                .map_err(|e| format!("failed to move {} to {}: {}", self.path, destination.display(), e))?;
+        }
+        drop(self.scratch);
+        Ok(destination.to_path_buf())
+    }
+}
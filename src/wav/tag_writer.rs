@@ -0,0 +1,739 @@
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+
+use crate::wav::{
+    parse_wav_layout, read_at, read_wav_tags, syncsafe, AudioTags, MUSICBRAINZ_UFID_OWNER, SONG_ID_TAG, YOUTUBE_ID_TAG,
+};
+
+/// Padding left after the FLAC metadata when it has to be rewritten, so that later edits fit
+/// in place.
+const FLAC_PADDING: usize = 4096;
+
+/// Writes tags into a WAV, FLAC or MP4/M4A file. Fields that are set in `tags` replace the
+/// file's values; the file's other tags are kept. Duration is never written.
+///
+/// WAV files get a `LIST`/`INFO` chunk and an `id3 ` chunk appended, with any earlier tag
+/// chunks turned into `JUNK`, so the audio is never moved. FLAC and MP4 files are edited in
+/// place when the new metadata fits in the old one plus its padding, and rewritten otherwise.
+pub fn write_tags(file_path: &str, tags: &AudioTags) -> Result<(), Box<dyn Error>> {
+    let mut file = OpenOptions::new().read(true).write(true).open(file_path)?;
+    let file_len = file.seek(SeekFrom::End(0))?;
+    let magic = read_at(&mut file, 0, 12.min(file_len))?;
+
+    let result = if magic.starts_with(b"RIFF") || magic.starts_with(b"RF64") || magic.starts_with(b"BW64") {
+        write_wav_tags(&mut file, tags)
+    } else if magic.starts_with(b"fLaC") || magic.starts_with(b"ID3") {
+        write_flac_tags(file_path, &mut file, tags)
+    } else if magic.len() >= 8 && &magic[4..8] == b"ftyp" {
+        write_mp4_tags(file_path, &mut file, tags)
+    } else {
+        Err("writing tags is only supported for WAV, FLAC and MP4 files".into())
+    };
+    result.map_err(|e| format!("failed to write tags to {}: {}", file_path, e).into())
+}
+
+/// The fields of `tags` that are set, as Vorbis comment keys and their values.
+fn tag_fields(tags: &AudioTags) -> Vec<(&'static str, Vec<String>)> {
+    let track_number = tags.track_number.map(|number| number.to_string());
+    let song_id = tags.song_id.map(|id| id.to_string());
+    let fields = [
+        ("TITLE", tags.title.clone().into_iter().collect()),
+        ("ARTIST", tags.artists.clone()),
+        ("ALBUM", tags.album.clone().into_iter().collect()),
+        ("ALBUMARTIST", tags.album_artist.clone().into_iter().collect()),
+        ("TRACKNUMBER", track_number.into_iter().collect()),
+        ("ISRC", tags.isrc.clone().into_iter().collect()),
+        ("MUSICBRAINZ_TRACKID", tags.musicbrainz_track_id.clone().into_iter().collect()),
+        ("MUSICBRAINZ_ALBUMID", tags.musicbrainz_release_id.clone().into_iter().collect()),
+        ("MUSICBRAINZ_ARTISTID", tags.musicbrainz_artist_id.clone().into_iter().collect()),
+        (SONG_ID_TAG, song_id.into_iter().collect()),
+        (YOUTUBE_ID_TAG, tags.youtube_id.clone().into_iter().collect()),
+    ];
+    fields
+        .into_iter()
+        .map(|(key, values): (&'static str, Vec<String>)| (key, values.into_iter().filter(|v| !v.is_empty()).collect::<Vec<_>>()))
+        .filter(|(_, values)| !values.is_empty())
+        .collect()
+}
+
+/// Replaces `start..end` of the file with `replacement` by writing a copy next to it and
+/// renaming the copy over the original.
+fn splice_file(file_path: &str, file: &mut File, start: u64, end: u64, replacement: &[u8]) -> Result<(), Box<dyn Error>> {
+    let temp_path = format!("{}.tagging", file_path);
+    let result = (|| -> Result<(), Box<dyn Error>> {
+        let mut output = io::BufWriter::new(File::create(&temp_path)?);
+        file.seek(SeekFrom::Start(0))?;
+        io::copy(&mut (&mut *file).take(start), &mut output)?;
+        output.write_all(replacement)?;
+        file.seek(SeekFrom::Start(end))?;
+        io::copy(file, &mut output)?;
+        output.flush()?;
+        Ok(())
+    })();
+    match result {
+        Ok(()) => Ok(fs::rename(&temp_path, file_path)?),
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path);
+            Err(e)
+        }
+    }
+}
+
+// --- WAV: LIST/INFO and id3 chunks ---
+
+/// RIFF INFO IDs that are rewritten from the tags; other INFO entries are kept.
+const INFO_IDS: [&str; 5] = ["INAM", "IART", "IPRD", "IPRT", "ITRK"];
+
+fn write_wav_tags(file: &mut File, tags: &AudioTags) -> Result<(), Box<dyn Error>> {
+    let layout = parse_wav_layout(&mut BufReader::new(&mut *file))?;
+    // The new chunks replace the old ones, so carry over whatever is not being changed.
+    let mut merged = tags.clone();
+    merged.fill_from(read_wav_tags(&mut BufReader::new(&mut *file))?);
+
+    let mut info: Vec<(String, String)> = layout
+        .info
+        .iter()
+        .filter(|(id, _)| !INFO_IDS.contains(&id.as_str()))
+        .map(|(id, value)| (id.clone(), value.clone()))
+        .collect();
+    info.sort();
+    let own_info = [
+        ("INAM", merged.title.clone()),
+        ("IART", merged.artist()),
+        ("IPRD", merged.album.clone()),
+        ("IPRT", merged.track_number.map(|number| number.to_string())),
+    ];
+    for (id, value) in own_info {
+        if let Some(value) = value.filter(|value| !value.is_empty()) {
+            info.push((id.to_string(), value));
+        }
+    }
+    let mut list = b"INFO".to_vec();
+    for (id, value) in &info {
+        let mut text = value.as_bytes().to_vec();
+        text.push(0);
+        list.extend(riff_chunk(id.as_bytes(), &text));
+    }
+    let mut new_chunks = riff_chunk(b"LIST", &list);
+    new_chunks.extend(riff_chunk(b"id3 ", &id3v2_tag(&merged)));
+
+    // Tag chunks after the last other chunk are cut off; earlier ones become JUNK so that
+    // nothing has to move.
+    let mut is_tag_chunk = Vec::with_capacity(layout.chunks.len());
+    for chunk in &layout.chunks {
+        let list_type = if &chunk.id == b"LIST" && chunk.size >= 4 { read_at(file, chunk.offset + 8, 4)? } else { Vec::new() };
+        is_tag_chunk.push(matches!(&chunk.id, b"id3 " | b"ID3 ") || list_type == b"INFO");
+    }
+    let keep = is_tag_chunk.iter().rposition(|&tag| !tag).map_or(0, |i| i + 1);
+    let file_len = file.seek(SeekFrom::End(0))?;
+    let kept_len = layout.chunks[..keep].last().map_or(12, |chunk| chunk.end().min(file_len));
+    let new_len = kept_len + (kept_len & 1) + new_chunks.len() as u64;
+    if !layout.rf64 && new_len - 8 > u32::MAX as u64 {
+        return Err("the tagged file would be too large for a RIFF header".into());
+    }
+
+    for (chunk, _) in layout.chunks[..keep].iter().zip(&is_tag_chunk).filter(|(_, tag)| **tag) {
+        file.seek(SeekFrom::Start(chunk.offset))?;
+        file.write_all(b"JUNK")?;
+    }
+    file.set_len(kept_len)?;
+    file.seek(SeekFrom::End(0))?;
+    if kept_len & 1 == 1 {
+        file.write_all(&[0])?;
+    }
+    file.write_all(&new_chunks)?;
+
+    if layout.rf64 {
+        let ds64 = layout.chunks.iter().find(|chunk| &chunk.id == b"ds64").ok_or("RF64 file has no ds64 chunk")?;
+        file.seek(SeekFrom::Start(ds64.offset + 8))?;
+        file.write_all(&(new_len - 8).to_le_bytes())?;
+    } else {
+        file.seek(SeekFrom::Start(4))?;
+        file.write_all(&((new_len - 8) as u32).to_le_bytes())?;
+        // A data chunk that ran to the end of a streamed file needs its real size now that
+        // chunks follow it.
+        if let Some(data) = layout.chunks.iter().find(|chunk| &chunk.id == b"data") {
+            file.seek(SeekFrom::Start(data.offset + 4))?;
+            file.write_all(&(data.size.min(u32::MAX as u64) as u32).to_le_bytes())?;
+        }
+    }
+    file.flush()?;
+    Ok(())
+}
+
+/// Builds a RIFF chunk, padded to an even length.
+fn riff_chunk(id: &[u8], body: &[u8]) -> Vec<u8> {
+    let mut chunk = id.to_vec();
+    chunk.extend((body.len() as u32).to_le_bytes());
+    chunk.extend(body);
+    if body.len() % 2 == 1 {
+        chunk.push(0);
+    }
+    chunk
+}
+
+/// Encodes a 28-bit integer as an ID3v2 "syncsafe" integer.
+fn to_syncsafe(value: u32) -> [u8; 4] {
+    [(value >> 21) as u8 & 0x7F, (value >> 14) as u8 & 0x7F, (value >> 7) as u8 & 0x7F, value as u8 & 0x7F]
+}
+
+/// Builds an ID3v2.4 tag with UTF-8 text frames.
+fn id3v2_tag(tags: &AudioTags) -> Vec<u8> {
+    let frame = |id: &[u8], data: &[u8]| -> Vec<u8> {
+        let mut frame = id.to_vec();
+        frame.extend(to_syncsafe(data.len() as u32));
+        frame.extend([0, 0]);
+        frame.extend(data);
+        frame
+    };
+    let text = |values: &[String]| -> Vec<u8> {
+        let mut data = vec![3];
+        data.extend(values.join("\0").as_bytes());
+        data
+    };
+
+    let mut frames = Vec::new();
+    for (key, values) in tag_fields(tags) {
+        let id: &[u8] = match key {
+            "TITLE" => b"TIT2",
+            "ARTIST" => b"TPE1",
+            "ALBUM" => b"TALB",
+            "ALBUMARTIST" => b"TPE2",
+            "TRACKNUMBER" => b"TRCK",
+            "ISRC" => b"TSRC",
+            "MUSICBRAINZ_TRACKID" => {
+                let mut data = MUSICBRAINZ_UFID_OWNER.as_bytes().to_vec();
+                data.push(0);
+                data.extend(values[0].as_bytes());
+                frames.extend(frame(b"UFID", &data));
+                continue;
+            }
+            _ => {
+                let description = match key {
+                    "MUSICBRAINZ_ALBUMID" => "MusicBrainz Album Id",
+                    "MUSICBRAINZ_ARTISTID" => "MusicBrainz Artist Id",
+                    other => other,
+                };
+                frames.extend(frame(b"TXXX", &text(&[description.to_string(), values[0].clone()])));
+                continue;
+            }
+        };
+        frames.extend(frame(id, &text(&values)));
+    }
+
+    let mut tag = b"ID3\x04\x00\x00".to_vec();
+    tag.extend(to_syncsafe(frames.len() as u32));
+    tag.extend(frames);
+    tag
+}
+
+// --- FLAC: VORBIS_COMMENT block ---
+
+fn write_flac_tags(file_path: &str, file: &mut File, tags: &AudioTags) -> Result<(), Box<dyn Error>> {
+    // Skip an ID3v2 tag in front of the stream marker.
+    let header = read_at(file, 0, 10)?;
+    let stream_start = if header.starts_with(b"ID3") {
+        10 + syncsafe(&header[6..10]) as u64 + if header[5] & 0x10 != 0 { 10 } else { 0 }
+    } else {
+        0
+    };
+    if read_at(file, stream_start, 4)? != b"fLaC" {
+        return Err("not a FLAC file".into());
+    }
+
+    // Keep every block but the comments and padding, which are rebuilt.
+    let mut blocks = Vec::new();
+    let mut comments = None;
+    let mut pos = stream_start + 4;
+    loop {
+        let header = read_at(file, pos, 4)?;
+        let (last, block_type) = (header[0] & 0x80 != 0, header[0] & 0x7F);
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as u64;
+        match block_type {
+            1 => {}
+            4 => comments = Some(read_at(file, pos + 4, len)?),
+            _ => blocks.push((block_type, read_at(file, pos + 4, len)?)),
+        }
+        pos += 4 + len;
+        if last {
+            break;
+        }
+    }
+    let metadata_start = stream_start + 4;
+    let available = (pos - metadata_start) as usize;
+
+    let (vendor, entries) = match comments {
+        Some(block) => parse_comment_entries(&block)?,
+        None => ("acousti-scan".to_string(), Vec::new()),
+    };
+    let fields = tag_fields(tags);
+    let mut block = Vec::new();
+    block.extend((vendor.len() as u32).to_le_bytes());
+    block.extend(vendor.as_bytes());
+    let kept = entries.into_iter().filter(|entry| {
+        let key = entry.split('=').next().unwrap_or_default();
+        !fields.iter().any(|(field, _)| field.eq_ignore_ascii_case(key))
+    });
+    let new_entries: Vec<String> = kept
+        .chain(fields.iter().flat_map(|(key, values)| values.iter().map(move |value| format!("{}={}", key, value))))
+        .collect();
+    block.extend((new_entries.len() as u32).to_le_bytes());
+    for entry in &new_entries {
+        block.extend((entry.len() as u32).to_le_bytes());
+        block.extend(entry.as_bytes());
+    }
+    // STREAMINFO stays first; the comments go after the other kept blocks.
+    blocks.push((4, block));
+
+    let used: usize = blocks.iter().map(|(_, body)| 4 + body.len()).sum();
+    let (padding, rewrite) = if used == available {
+        (None, false)
+    } else if used + 4 <= available {
+        (Some(available - used - 4), false)
+    } else {
+        (Some(FLAC_PADDING), true)
+    };
+    if padding.is_some() {
+        blocks.push((1, vec![0; padding.unwrap_or_default()]));
+    }
+
+    let mut metadata = Vec::new();
+    let count = blocks.len();
+    for (i, (block_type, body)) in blocks.into_iter().enumerate() {
+        if body.len() >= 1 << 24 {
+            return Err("FLAC metadata block is too large".into());
+        }
+        let last = if i + 1 == count { 0x80 } else { 0 };
+        metadata.push(block_type | last);
+        metadata.extend(&(body.len() as u32).to_be_bytes()[1..]);
+        metadata.extend(body);
+    }
+
+    if rewrite {
+        splice_file(file_path, file, metadata_start, pos, &metadata)
+    } else {
+        file.seek(SeekFrom::Start(metadata_start))?;
+        file.write_all(&metadata)?;
+        file.flush()?;
+        Ok(())
+    }
+}
+
+/// Splits a Vorbis comment block into its vendor string and "KEY=value" entries.
+fn parse_comment_entries(bytes: &[u8]) -> Result<(String, Vec<String>), Box<dyn Error>> {
+    let mut pos = 0;
+    let mut next = |len: usize| -> Result<&[u8], Box<dyn Error>> {
+        let field = bytes.get(pos..pos + len).ok_or("truncated Vorbis comment")?;
+        pos += len;
+        Ok(field)
+    };
+    let le_u32 = |b: &[u8]| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize;
+    let vendor_len = le_u32(next(4)?);
+    let vendor = String::from_utf8_lossy(next(vendor_len)?).into_owned();
+    let count = le_u32(next(4)?);
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let len = le_u32(next(4)?);
+        entries.push(String::from_utf8_lossy(next(len)?).into_owned());
+    }
+    Ok((vendor, entries))
+}
+
+// --- MP4: moov/udta/meta/ilst ---
+
+/// Freeform (`----`) item names for the fields that have no iTunes atom of their own.
+fn freeform_name(key: &str) -> &str {
+    match key {
+        "MUSICBRAINZ_TRACKID" => "MusicBrainz Track Id",
+        "MUSICBRAINZ_ALBUMID" => "MusicBrainz Album Id",
+        "MUSICBRAINZ_ARTISTID" => "MusicBrainz Artist Id",
+        other => other,
+    }
+}
+
+/// Builds an MP4 atom with a 32-bit size.
+fn mp4_atom(atom_type: &[u8], body: &[u8]) -> Vec<u8> {
+    let mut atom = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+    atom.extend(atom_type);
+    atom.extend(body);
+    atom
+}
+
+/// Splits a buffer into atoms: (type, start, body start, end), all relative to the buffer.
+fn child_atoms(bytes: &[u8]) -> Vec<([u8; 4], usize, usize, usize)> {
+    let mut atoms = Vec::new();
+    let mut pos = 0;
+    while pos + 8 <= bytes.len() {
+        let atom_type = [bytes[pos + 4], bytes[pos + 5], bytes[pos + 6], bytes[pos + 7]];
+        let (header_len, size) = match u32::from_be_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]]) {
+            0 => (8, bytes.len() - pos),
+            1 if pos + 16 <= bytes.len() => (16, u64::from_be_bytes(bytes[pos + 8..pos + 16].try_into().unwrap_or_default()) as usize),
+            size => (8, size as usize),
+        };
+        if size < header_len || size > bytes.len() - pos {
+            break;
+        }
+        atoms.push((atom_type, pos, pos + header_len, pos + size));
+        pos += size;
+    }
+    atoms
+}
+
+/// Returns `body` with the first `atom_type` child's body replaced by `child_body` (and any
+/// other such children dropped), or with the child appended if there was none.
+fn replace_child(body: &[u8], atom_type: &[u8; 4], child_body: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut replaced = false;
+    for (child_type, start, _, end) in child_atoms(body) {
+        if &child_type != atom_type {
+            output.extend(&body[start..end]);
+        } else if !replaced {
+            output.extend(mp4_atom(atom_type, child_body));
+            replaced = true;
+        }
+    }
+    if !replaced {
+        output.extend(mp4_atom(atom_type, child_body));
+    }
+    output
+}
+
+fn find_child<'a>(body: &'a [u8], atom_type: &[u8; 4]) -> Option<&'a [u8]> {
+    child_atoms(body).into_iter().find(|(t, ..)| t == atom_type).map(|(_, _, body_start, end)| &body[body_start..end])
+}
+
+/// Returns the `ilst` body with the items for the set fields replaced.
+fn ilst_with_tags(ilst: &[u8], tags: &AudioTags) -> Vec<u8> {
+    let fields = tag_fields(tags);
+    let item_type = |key: &str| -> Option<&'static [u8; 4]> {
+        match key {
+            "TITLE" => Some(b"\xa9nam"),
+            "ARTIST" => Some(b"\xa9ART"),
+            "ALBUM" => Some(b"\xa9alb"),
+            "ALBUMARTIST" => Some(b"aART"),
+            "TRACKNUMBER" => Some(b"trkn"),
+            _ => None,
+        }
+    };
+    let freeform_names: Vec<&str> =
+        fields.iter().filter(|(key, _)| item_type(key).is_none()).map(|(key, _)| freeform_name(key)).collect();
+    let data = |type_indicator: u8, value: &[u8]| mp4_atom(b"data", &[&[0, 0, 0, type_indicator, 0, 0, 0, 0][..], value].concat());
+
+    let mut output = Vec::new();
+    for (item, start, body_start, end) in child_atoms(ilst) {
+        let replaced = match &item {
+            b"----" => find_child(&ilst[body_start..end], b"name")
+                .filter(|name| name.len() >= 4)
+                .is_some_and(|name| freeform_names.contains(&String::from_utf8_lossy(&name[4..]).as_ref())),
+            _ => fields.iter().any(|(key, _)| item_type(key) == Some(&item)),
+        };
+        if !replaced {
+            output.extend(&ilst[start..end]);
+        }
+    }
+    for (key, values) in &fields {
+        match item_type(key) {
+            Some(b"trkn") => {
+                let number: u16 = values[0].parse().unwrap_or_default();
+                let [high, low] = number.to_be_bytes();
+                output.extend(mp4_atom(b"trkn", &data(0, &[0, 0, high, low, 0, 0, 0, 0])));
+            }
+            Some(item) => output.extend(mp4_atom(item, &data(1, values.join(", ").as_bytes()))),
+            None => {
+                let body = [
+                    mp4_atom(b"mean", b"\x00\x00\x00\x00com.apple.iTunes"),
+                    mp4_atom(b"name", &[&[0, 0, 0, 0][..], freeform_name(key).as_bytes()].concat()),
+                    data(1, values[0].as_bytes()),
+                ]
+                .concat();
+                output.extend(mp4_atom(b"----", &body));
+            }
+        }
+    }
+    output
+}
+
+/// Returns the `moov` body with the tags written into `udta/meta/ilst`, creating those atoms
+/// as needed.
+fn moov_with_tags(moov: &[u8], tags: &AudioTags) -> Vec<u8> {
+    let udta = find_child(moov, b"udta").unwrap_or_default();
+    let (meta_prefix, meta_children) = match find_child(udta, b"meta") {
+        // QuickTime files have no version and flags before the children of `meta`.
+        Some(meta) if meta.get(4..8) == Some(b"hdlr") => (&[][..], meta.to_vec()),
+        Some(meta) if meta.len() >= 4 => (&meta[..4], meta[4..].to_vec()),
+        _ => {
+            let handler = [&[0u8; 8][..], b"mdirappl", &[0; 9]].concat();
+            (&[0, 0, 0, 0][..], mp4_atom(b"hdlr", &handler))
+        }
+    };
+    let ilst = ilst_with_tags(find_child(&meta_children, b"ilst").unwrap_or_default(), tags);
+    let meta = [meta_prefix, &replace_child(&meta_children, b"ilst", &ilst)].concat();
+    replace_child(moov, b"udta", &replace_child(udta, b"meta", &meta))
+}
+
+/// Adds `delta` to the chunk offsets in the `stco`/`co64` tables under `body` that point at
+/// or past `from`.
+fn shift_chunk_offsets(body: &mut [u8], from: u64, delta: i64) -> Result<(), Box<dyn Error>> {
+    for (atom_type, _, body_start, end) in child_atoms(body) {
+        let child = &mut body[body_start..end];
+        match &atom_type {
+            b"trak" | b"mdia" | b"minf" | b"stbl" => shift_chunk_offsets(child, from, delta)?,
+            b"stco" | b"co64" if child.len() >= 8 => {
+                let width = if &atom_type == b"stco" { 4 } else { 8 };
+                let count = u32::from_be_bytes([child[4], child[5], child[6], child[7]]) as usize;
+                for entry in child[8..].chunks_exact_mut(width).take(count) {
+                    let offset = if width == 4 {
+                        u32::from_be_bytes(entry.try_into()?) as u64
+                    } else {
+                        u64::from_be_bytes(entry.try_into()?)
+                    };
+                    if offset < from {
+                        continue;
+                    }
+                    let shifted = offset.checked_add_signed(delta).ok_or("chunk offset out of range")?;
+                    if width == 4 {
+                        entry.copy_from_slice(&u32::try_from(shifted).map_err(|_| "chunk offset out of range")?.to_be_bytes());
+                    } else {
+                        entry.copy_from_slice(&shifted.to_be_bytes());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn write_mp4_tags(file_path: &str, file: &mut File, tags: &AudioTags) -> Result<(), Box<dyn Error>> {
+    // Top-level atoms: (type, start, end).
+    let file_len = file.seek(SeekFrom::End(0))?;
+    let mut atoms = Vec::new();
+    let mut pos = 0;
+    while pos + 8 <= file_len {
+        let header = read_at(file, pos, 8)?;
+        let (header_len, size) = match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64 {
+            0 => (8, file_len - pos),
+            // A 64-bit size follows the type, if there is room for it.
+            1 if pos + 16 <= file_len => (16, u64::from_be_bytes(read_at(file, pos + 8, 8)?.try_into().unwrap_or_default())),
+            1 => break,
+            size => (8, size),
+        };
+        if size < header_len {
+            break;
+        }
+        let atom_end = pos.checked_add(size).map_or(file_len, |atom_end| atom_end.min(file_len));
+        atoms.push(([header[4], header[5], header[6], header[7]], pos, atom_end));
+        pos = atom_end;
+    }
+    let index = atoms.iter().position(|(t, ..)| t == b"moov").ok_or("MP4 file has no moov atom")?;
+    let (_, moov_start, moov_end) = atoms[index];
+
+    let moov = read_at(file, moov_start, moov_end - moov_start)?;
+    let header_len = if u32::from_be_bytes([moov[0], moov[1], moov[2], moov[3]]) == 1 { 16 } else { 8 };
+    let mut body = moov_with_tags(&moov[header_len..], tags);
+
+    // The new moov may take the space of the old one and of free atoms right after it.
+    let region_end = atoms[index + 1..]
+        .iter()
+        .take_while(|(t, ..)| t == b"free" || t == b"skip")
+        .last()
+        .map_or(moov_end, |(_, _, end)| *end);
+    let new_len = body.len() as u64 + 8;
+    let available = region_end - moov_start;
+
+    if region_end == file_len {
+        // Nothing follows, so the moov can simply grow or shrink.
+        file.set_len(moov_start)?;
+        file.seek(SeekFrom::Start(moov_start))?;
+        file.write_all(&mp4_atom(b"moov", &body))?;
+    } else if new_len == available || new_len + 8 <= available {
+        file.seek(SeekFrom::Start(moov_start))?;
+        file.write_all(&mp4_atom(b"moov", &body))?;
+        if new_len < available {
+            file.write_all(&mp4_atom(b"free", &vec![0; (available - new_len - 8) as usize]))?;
+        }
+    } else {
+        // Everything after the old moov moves, so the media offsets have to follow.
+        let delta = new_len as i64 - (moov_end - moov_start) as i64;
+        shift_chunk_offsets(&mut body, moov_end, delta)?;
+        return splice_file(file_path, file, moov_start, moov_end, &mp4_atom(b"moov", &body));
+    }
+    file.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wav::{read_tags, write_wav_file};
+
+    fn sample_tags() -> AudioTags {
+        AudioTags {
+            title: Some("Song".to_string()),
+            artists: vec!["Artist".to_string()],
+            album: Some("Album".to_string()),
+            album_artist: Some("Artist".to_string()),
+            song_id: Some(42),
+            youtube_id: Some("dQw4w9WgXcQ".to_string()),
+            ..AudioTags::default()
+        }
+    }
+
+    #[test]
+    fn test_wav_tags_round_trip_without_touching_audio() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("song.wav");
+        let path = path.to_str().unwrap();
+        let audio: Vec<u8> = (0..4000u32).map(|i| (i % 251) as u8).collect();
+        write_wav_file(path, &audio, 8000, 1, 16).unwrap();
+
+        write_tags(path, &sample_tags()).unwrap();
+        // A second write replaces only what it sets.
+        let update = AudioTags { title: Some("New Title".to_string()), track_number: Some(2), ..AudioTags::default() };
+        write_tags(path, &update).unwrap();
+
+        let tags = read_tags(path).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("New Title"));
+        assert_eq!(tags.artists, vec!["Artist"]);
+        assert_eq!(tags.album_artist.as_deref(), Some("Artist"));
+        assert_eq!(tags.track_number, Some(2));
+        assert_eq!(tags.song_id, Some(42));
+        assert_eq!(tags.youtube_id.as_deref(), Some("dQw4w9WgXcQ"));
+        assert_eq!(tags.duration, Some(0.25));
+
+        let layout = parse_wav_layout(&mut File::open(path).unwrap()).unwrap();
+        let ids: Vec<&[u8]> = layout.chunks.iter().map(|chunk| &chunk.id[..]).collect();
+        assert_eq!(ids, vec![&b"fmt "[..], b"data", b"LIST", b"id3 "]);
+        let bytes = fs::read(path).unwrap();
+        assert_eq!(&bytes[layout.data_offset as usize..][..audio.len()], &audio[..]);
+        assert_eq!(u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize, bytes.len() - 8);
+    }
+
+    #[test]
+    fn test_wav_tag_chunks_before_data_become_junk() {
+        let info = riff_chunk(b"LIST", &[&b"INFO"[..], &riff_chunk(b"INAM", b"Old\0"), &riff_chunk(b"ISFT", b"Lavf\0")].concat());
+        let fmt = [&1u16.to_le_bytes()[..], &1u16.to_le_bytes(), &8000u32.to_le_bytes(), &16000u32.to_le_bytes(), &2u16.to_le_bytes(), &16u16.to_le_bytes()].concat();
+        let body = [&b"WAVE"[..], &riff_chunk(b"fmt ", &fmt), &info, &riff_chunk(b"data", &[1; 100])].concat();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("song.wav");
+        fs::write(&path, [&b"RIFF"[..], &(body.len() as u32).to_le_bytes(), &body].concat()).unwrap();
+        let path = path.to_str().unwrap();
+
+        write_tags(path, &sample_tags()).unwrap();
+        let layout = parse_wav_layout(&mut File::open(path).unwrap()).unwrap();
+        let ids: Vec<&[u8]> = layout.chunks.iter().map(|chunk| &chunk.id[..]).collect();
+        assert_eq!(ids, vec![&b"fmt "[..], b"JUNK", b"data", b"LIST", b"id3 "]);
+        assert_eq!(layout.info.get("INAM").map(String::as_str), Some("Song"));
+        assert_eq!(layout.info.get("ISFT").map(String::as_str), Some("Lavf"));
+    }
+
+    fn flac_file(comment_entries: &[&str], padding: usize) -> Vec<u8> {
+        let mut streaminfo = vec![0u8; 34];
+        streaminfo[10..14].copy_from_slice(&[0x0A, 0xC4, 0x42, 0xF0]);
+        streaminfo[14..18].copy_from_slice(&44100u32.to_be_bytes());
+        let mut comments = 4u32.to_le_bytes().to_vec();
+        comments.extend(b"test");
+        comments.extend((comment_entries.len() as u32).to_le_bytes());
+        for entry in comment_entries {
+            comments.extend((entry.len() as u32).to_le_bytes());
+            comments.extend(entry.as_bytes());
+        }
+        let mut file = b"fLaC\x00\x00\x00\x22".to_vec();
+        file.extend(streaminfo);
+        file.extend([4, 0, 0, comments.len() as u8]);
+        file.extend(comments);
+        file.extend([0x81, 0, (padding >> 8) as u8, padding as u8]);
+        file.extend(vec![0; padding]);
+        file.extend(b"audio frames");
+        file
+    }
+
+    #[test]
+    fn test_flac_comments_in_place_and_rewritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("song.flac");
+        let path = path.to_str().unwrap();
+
+        fs::write(path, flac_file(&["TITLE=Old", "GENRE=Rock"], 512)).unwrap();
+        let before = fs::metadata(path).unwrap().len();
+        write_tags(path, &sample_tags()).unwrap();
+        assert_eq!(fs::metadata(path).unwrap().len(), before);
+        let tags = read_tags(path).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Song"));
+        assert_eq!(tags.song_id, Some(42));
+        assert_eq!(tags.duration, Some(1.0));
+
+        // Without padding to grow into, the file is rewritten with fresh padding.
+        fs::write(path, flac_file(&["GENRE=Rock"], 0)).unwrap();
+        write_tags(path, &sample_tags()).unwrap();
+        let bytes = fs::read(path).unwrap();
+        assert!(bytes.ends_with(b"audio frames"));
+        assert!(String::from_utf8_lossy(&bytes).contains("GENRE=Rock"));
+        assert_eq!(read_tags(path).unwrap().youtube_id.as_deref(), Some("dQw4w9WgXcQ"));
+    }
+
+    /// An M4A with the moov before the mdat, whose single chunk offset points into the mdat.
+    fn mp4_file(free: usize) -> Vec<u8> {
+        let ftyp = mp4_atom(b"ftyp", b"M4A \x00\x00\x00\x00");
+        let mut mvhd = vec![0u8; 100];
+        mvhd[12..16].copy_from_slice(&1000u32.to_be_bytes());
+        mvhd[16..20].copy_from_slice(&3000u32.to_be_bytes());
+        let moov_len = |offset: u32| {
+            let stco = mp4_atom(b"stco", &[&[0, 0, 0, 0, 0, 0, 0, 1][..], &offset.to_be_bytes()].concat());
+            let trak = mp4_atom(b"trak", &mp4_atom(b"mdia", &mp4_atom(b"minf", &mp4_atom(b"stbl", &stco))));
+            mp4_atom(b"moov", &[mp4_atom(b"mvhd", &mvhd), trak].concat())
+        };
+        let free_atom = if free > 0 { mp4_atom(b"free", &vec![0; free - 8]) } else { Vec::new() };
+        let offset = (ftyp.len() + moov_len(0).len() + free_atom.len() + 8) as u32;
+        [ftyp, moov_len(offset), free_atom, mp4_atom(b"mdat", b"media!")].concat()
+    }
+
+    /// Follows the chunk offset of `mp4_file` and returns the bytes it points at.
+    fn first_chunk(bytes: &[u8]) -> &[u8] {
+        let stco = bytes.windows(4).position(|w| w == b"stco").unwrap();
+        let offset = u32::from_be_bytes(bytes[stco + 12..stco + 16].try_into().unwrap()) as usize;
+        &bytes[offset..offset + 6]
+    }
+
+    #[test]
+    fn test_mp4_tags_fill_free_space_or_move_media() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("song.m4a");
+        let path = path.to_str().unwrap();
+
+        fs::write(path, mp4_file(1024)).unwrap();
+        let before = fs::metadata(path).unwrap().len();
+        write_tags(path, &sample_tags()).unwrap();
+        let bytes = fs::read(path).unwrap();
+        assert_eq!(bytes.len() as u64, before);
+        assert_eq!(first_chunk(&bytes), b"media!");
+
+        fs::write(path, mp4_file(0)).unwrap();
+        write_tags(path, &sample_tags()).unwrap();
+        let bytes = fs::read(path).unwrap();
+        assert_eq!(first_chunk(&bytes), b"media!");
+
+        let tags = read_tags(path).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Song"));
+        assert_eq!(tags.album_artist.as_deref(), Some("Artist"));
+        assert_eq!(tags.song_id, Some(42));
+        assert_eq!(tags.youtube_id.as_deref(), Some("dQw4w9WgXcQ"));
+        assert_eq!(tags.duration, Some(3.0));
+    }
+
+    #[test]
+    fn test_mp4_with_bad_64_bit_sizes_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("song.m4a");
+        let path = path.to_str().unwrap();
+        let ftyp = mp4_atom(b"ftyp", b"M4A \x00\x00\x00\x00");
+        let large_mdat = |size: &[u8]| [ftyp.clone(), [&1u32.to_be_bytes()[..], b"mdat", size].concat()].concat();
+
+        for bytes in [large_mdat(&u64::MAX.to_be_bytes()), large_mdat(&[0; 4])] {
+            fs::write(path, &bytes).unwrap();
+            assert!(write_tags(path, &sample_tags()).is_err());
+            assert_eq!(fs::read(path).unwrap(), bytes);
+        }
+    }
+}
@@ -0,0 +1,889 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+
+use serde::Serialize;
+
+use crate::wav::parse_wav_layout;
+
+/// Largest metadata structure read into memory; bigger ones (usually embedded artwork) are
+/// truncated or skipped.
+const MAX_TAG_BYTES: u64 = 16 << 20;
+
+/// MusicBrainz's owner identifier in ID3 `UFID` frames.
+pub(crate) const MUSICBRAINZ_UFID_OWNER: &str = "http://musicbrainz.org";
+
+/// Custom tag holding the song's ID in the database that fingerprinted it (an ID3 `TXXX`
+/// description, Vorbis comment key or MP4 freeform name).
+pub const SONG_ID_TAG: &str = "SONG_ID";
+/// Custom tag holding the ID of the YouTube video the song was downloaded from.
+pub const YOUTUBE_ID_TAG: &str = "YOUTUBE_ID";
+
+/// The descriptive tags of an audio file, read natively from its container.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct AudioTags {
+    pub title: Option<String>,
+    pub artists: Vec<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub track_number: Option<u32>,
+    /// Duration in seconds, from the tags or the stream headers.
+    pub duration: Option<f64>,
+    pub isrc: Option<String>,
+    /// MusicBrainz recording ID.
+    pub musicbrainz_track_id: Option<String>,
+    /// MusicBrainz release ID.
+    pub musicbrainz_release_id: Option<String>,
+    pub musicbrainz_artist_id: Option<String>,
+    /// The song's ID in the database, as written by the downloader.
+    pub song_id: Option<u32>,
+    pub youtube_id: Option<String>,
+}
+
+impl AudioTags {
+    /// Returns the artists joined with ", ", if there are any.
+    pub fn artist(&self) -> Option<String> {
+        (!self.artists.is_empty()).then(|| self.artists.join(", "))
+    }
+
+    /// Sets the field named by a Vorbis-comment style key (case-insensitive; spaces count as
+    /// underscores). The other formats map their own identifiers onto these keys. Fields that
+    /// are already set are kept, except that every distinct artist is collected.
+    fn set(&mut self, key: &str, value: &str) {
+        let value = value.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+        if value.is_empty() {
+            return;
+        }
+        let field = match key.to_ascii_lowercase().replace(' ', "_").as_str() {
+            "title" => &mut self.title,
+            "artist" => {
+                if !self.artists.iter().any(|artist| artist == value) {
+                    self.artists.push(value.to_string());
+                }
+                return;
+            }
+            "album" => &mut self.album,
+            "albumartist" | "album_artist" => &mut self.album_artist,
+            "tracknumber" | "track" => {
+                // Track numbers are often written as "3/12".
+                let number = value.split('/').next().unwrap_or_default().trim();
+                if self.track_number.is_none() {
+                    self.track_number = number.parse().ok();
+                }
+                return;
+            }
+            "isrc" => &mut self.isrc,
+            "musicbrainz_trackid" | "musicbrainz_track_id" => &mut self.musicbrainz_track_id,
+            "musicbrainz_albumid" | "musicbrainz_album_id" => &mut self.musicbrainz_release_id,
+            "musicbrainz_artistid" | "musicbrainz_artist_id" => &mut self.musicbrainz_artist_id,
+            "song_id" => {
+                if self.song_id.is_none() {
+                    self.song_id = value.parse().ok();
+                }
+                return;
+            }
+            "youtube_id" => &mut self.youtube_id,
+            _ => return,
+        };
+        if field.is_none() {
+            *field = Some(value.to_string());
+        }
+    }
+
+    /// Fills the fields that are still empty from `other`.
+    pub fn fill_from(&mut self, other: AudioTags) {
+        if self.artists.is_empty() {
+            self.artists = other.artists;
+        }
+        self.title = self.title.take().or(other.title);
+        self.album = self.album.take().or(other.album);
+        self.album_artist = self.album_artist.take().or(other.album_artist);
+        self.track_number = self.track_number.or(other.track_number);
+        self.duration = self.duration.or(other.duration);
+        self.isrc = self.isrc.take().or(other.isrc);
+        self.musicbrainz_track_id = self.musicbrainz_track_id.take().or(other.musicbrainz_track_id);
+        self.musicbrainz_release_id = self.musicbrainz_release_id.take().or(other.musicbrainz_release_id);
+        self.musicbrainz_artist_id = self.musicbrainz_artist_id.take().or(other.musicbrainz_artist_id);
+        self.song_id = self.song_id.or(other.song_id);
+        self.youtube_id = self.youtube_id.take().or(other.youtube_id);
+    }
+}
+
+/// Reads the tags of an MP3 (ID3v2/ID3v1), FLAC or Ogg Vorbis/Opus (Vorbis comments), MP4/M4A
+/// (iTunes-style atoms) or WAV (RIFF INFO) file. The container is detected from the file's
+/// contents, not its extension.
+pub fn read_tags(file_path: &str) -> Result<AudioTags, Box<dyn Error>> {
+    let mut reader = BufReader::new(File::open(file_path)?);
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    let magic = read_at(&mut reader, 0, 12.min(file_len))?;
+
+    let tags = if magic.starts_with(b"ID3") {
+        // FLAC files sometimes carry an ID3v2 tag in front of the stream marker.
+        let (tags, tag_end) = read_id3v2(&mut reader, 0)?;
+        if file_len >= tag_end + 4 && read_at(&mut reader, tag_end, 4)? == b"fLaC" {
+            read_flac_tags(&mut reader, tag_end)
+        } else {
+            read_mp3_tags(&mut reader, tags, tag_end, file_len)
+        }
+    } else if magic.starts_with(b"fLaC") {
+        read_flac_tags(&mut reader, 0)
+    } else if magic.starts_with(b"OggS") {
+        read_ogg_tags(&mut reader, file_len)
+    } else if magic.starts_with(b"RIFF") || magic.starts_with(b"RF64") || magic.starts_with(b"BW64") {
+        read_wav_tags(&mut reader)
+    } else if magic.len() >= 8 && &magic[4..8] == b"ftyp" {
+        read_mp4_tags(&mut reader, file_len)
+    } else if magic.len() >= 2 && magic[0] == 0xFF && magic[1] & 0xE0 == 0xE0 {
+        read_mp3_tags(&mut reader, AudioTags::default(), 0, file_len)
+    } else {
+        return Err(format!("unrecognized audio container in {}", file_path).into());
+    };
+    tags.map_err(|e| format!("failed to read tags from {}: {}", file_path, e).into())
+}
+
+/// Reads `len` bytes at `offset`.
+pub(crate) fn read_at<R: Read + Seek>(reader: &mut R, offset: u64, len: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+    if len > MAX_TAG_BYTES {
+        return Err(format!("metadata block of {} bytes is too large", len).into());
+    }
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut bytes = vec![0u8; len as usize];
+    reader.read_exact(&mut bytes).map_err(|_| "file is truncated")?;
+    Ok(bytes)
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Decodes a 28-bit ID3v2 "syncsafe" integer (7 bits per byte).
+pub(crate) fn syncsafe(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |acc, &b| (acc << 7) | (b & 0x7F) as u32)
+}
+
+// --- MP3: ID3v2, ID3v1 and the MPEG stream ---
+
+fn read_mp3_tags<R: Read + Seek>(
+    reader: &mut R,
+    mut tags: AudioTags,
+    audio_start: u64,
+    file_len: u64,
+) -> Result<AudioTags, Box<dyn Error>> {
+    let mut audio_end = file_len;
+    if file_len >= audio_start + 128 {
+        let v1 = read_at(reader, file_len - 128, 128)?;
+        if v1.starts_with(b"TAG") {
+            tags.fill_from(parse_id3v1(&v1));
+            audio_end -= 128;
+        }
+    }
+    if tags.duration.is_none() {
+        tags.duration = mp3_duration(reader, audio_start, audio_end)?;
+    }
+    Ok(tags)
+}
+
+/// Parses the ID3v2 tag at `offset`, returning its tags and the offset just past it.
+fn read_id3v2<R: Read + Seek>(reader: &mut R, offset: u64) -> Result<(AudioTags, u64), Box<dyn Error>> {
+    let header = read_at(reader, offset, 10)?;
+    let (major, flags) = (header[3], header[5]);
+    let size = syncsafe(&header[6..10]) as u64;
+    let footer = if flags & 0x10 != 0 { 10 } else { 0 };
+    let tag_end = offset + 10 + size + footer;
+
+    let mut body = read_at(reader, offset + 10, size.min(MAX_TAG_BYTES))?;
+    if flags & 0x80 != 0 && major < 4 {
+        body = remove_unsynchronisation(&body);
+    }
+    let mut tags = AudioTags::default();
+    if !(2..=4).contains(&major) {
+        return Ok((tags, tag_end));
+    }
+
+    let mut pos = 0;
+    if flags & 0x40 != 0 && major >= 3 && body.len() >= 4 {
+        // Skip the extended header; v2.3 gives its size without, v2.4 with, the size field.
+        pos = if major == 3 { be_u32(&body) as usize + 4 } else { syncsafe(&body[..4]) as usize };
+    }
+
+    let (id_len, header_len) = if major == 2 { (3, 6) } else { (4, 10) };
+    while pos + header_len <= body.len() && body[pos] != 0 {
+        let id = String::from_utf8_lossy(&body[pos..pos + id_len]).into_owned();
+        let size_bytes = &body[pos + id_len..];
+        let size = match major {
+            2 => u32::from_be_bytes([0, size_bytes[0], size_bytes[1], size_bytes[2]]),
+            3 => be_u32(size_bytes),
+            _ => syncsafe(&size_bytes[..4]),
+        } as usize;
+        let format_flags = if major == 2 { 0 } else { body[pos + 9] };
+        let start = pos + header_len;
+        let end = (start + size).min(body.len());
+        pos = end;
+
+        // Compressed or encrypted frames are skipped.
+        let (skip, unsync, length_indicator) = match major {
+            2 => (false, false, false),
+            3 => (format_flags & 0xC0 != 0, false, false),
+            _ => (format_flags & 0x0C != 0, format_flags & 0x02 != 0, format_flags & 0x01 != 0),
+        };
+        if skip {
+            continue;
+        }
+        let mut data = body[start..end].to_vec();
+        if length_indicator && data.len() >= 4 {
+            data.drain(..4);
+        }
+        if unsync {
+            data = remove_unsynchronisation(&data);
+        }
+        parse_id3v2_frame(&id, &data, &mut tags);
+    }
+    Ok((tags, tag_end))
+}
+
+/// Undoes ID3v2 unsynchronisation, which inserts a zero byte after every 0xFF.
+fn remove_unsynchronisation(bytes: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(bytes.len());
+    for (i, &b) in bytes.iter().enumerate() {
+        if !(b == 0 && i > 0 && bytes[i - 1] == 0xFF) {
+            output.push(b);
+        }
+    }
+    output
+}
+
+fn parse_id3v2_frame(id: &str, data: &[u8], tags: &mut AudioTags) {
+    if data.is_empty() {
+        return;
+    }
+    let key = match id {
+        "TIT2" | "TT2" => "title",
+        "TPE1" | "TP1" => "artist",
+        "TALB" | "TAL" => "album",
+        "TPE2" | "TP2" => "albumartist",
+        "TRCK" | "TRK" => "tracknumber",
+        "TSRC" | "TRC" => "isrc",
+        "TLEN" | "TLE" => {
+            let values = decode_id3_text(data[0], &data[1..]);
+            let ms = values.first().and_then(|value| value.trim().parse::<f64>().ok());
+            if let Some(ms) = ms.filter(|&ms| ms > 0.0) {
+                tags.duration = Some(ms / 1000.0);
+            }
+            return;
+        }
+        "TXXX" | "TXX" => {
+            // A description followed by the value.
+            let values = decode_id3_text(data[0], &data[1..]);
+            if let [description, value, ..] = values.as_slice() {
+                tags.set(description, value);
+            }
+            return;
+        }
+        "UFID" | "UFI" => {
+            if let Some(nul) = data.iter().position(|&b| b == 0)
+                && &data[..nul] == MUSICBRAINZ_UFID_OWNER.as_bytes()
+            {
+                tags.set("musicbrainz_trackid", &String::from_utf8_lossy(&data[nul + 1..]));
+            }
+            return;
+        }
+        _ => return,
+    };
+    // ID3v2.4 separates multiple values with NULs.
+    for value in decode_id3_text(data[0], &data[1..]) {
+        tags.set(key, &value);
+    }
+}
+
+/// Decodes the NUL-separated strings of an ID3v2 text field in the given encoding:
+/// 0 = ISO-8859-1, 1 = UTF-16 with BOM, 2 = UTF-16BE, 3 = UTF-8.
+fn decode_id3_text(encoding: u8, bytes: &[u8]) -> Vec<String> {
+    let wide = encoding == 1 || encoding == 2;
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+    let step = if wide { 2 } else { 1 };
+    while i + step <= bytes.len() {
+        if bytes[i..i + step].iter().all(|&b| b == 0) {
+            parts.push(&bytes[start..i]);
+            start = i + step;
+        }
+        i += step;
+    }
+    parts.push(&bytes[start..]);
+
+    parts
+        .into_iter()
+        .map(|part| match encoding {
+            0 => part.iter().map(|&b| b as char).collect(),
+            1 | 2 => {
+                let (big_endian, text) = match part {
+                    [0xFE, 0xFF, rest @ ..] => (true, rest),
+                    [0xFF, 0xFE, rest @ ..] => (false, rest),
+                    _ => (encoding == 2, part),
+                };
+                let units: Vec<u16> = text
+                    .chunks_exact(2)
+                    .map(|c| if big_endian { u16::from_be_bytes([c[0], c[1]]) } else { u16::from_le_bytes([c[0], c[1]]) })
+                    .collect();
+                String::from_utf16_lossy(&units)
+            }
+            _ => String::from_utf8_lossy(part).into_owned(),
+        })
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+/// Parses a 128-byte ID3v1 (or v1.1, with a track number) tag.
+fn parse_id3v1(tag: &[u8]) -> AudioTags {
+    let text = |range: std::ops::Range<usize>| -> String { tag[range].iter().map(|&b| b as char).collect() };
+    let mut tags = AudioTags::default();
+    tags.set("title", &text(3..33));
+    tags.set("artist", &text(33..63));
+    tags.set("album", &text(63..93));
+    if tag[125] == 0 && tag[126] != 0 {
+        tags.track_number = Some(tag[126] as u32);
+    }
+    tags
+}
+
+/// Estimates the duration of an MPEG audio stream from its first frame: exactly from a Xing,
+/// Info or VBRI header when there is one, otherwise assuming a constant bitrate.
+fn mp3_duration<R: Read + Seek>(reader: &mut R, start: u64, end: u64) -> Result<Option<f64>, Box<dyn Error>> {
+    let window = read_at(reader, start, end.saturating_sub(start).min(64 * 1024))?;
+    let Some((offset, frame)) = (0..window.len().saturating_sub(4))
+        .find_map(|i| parse_mpeg_header(&window[i..i + 4]).map(|frame| (i, frame)))
+    else {
+        return Ok(None);
+    };
+
+    let frames = |at: usize| window.get(at..at + 4).map(be_u32);
+    let xing = offset + 4 + frame.side_info_len;
+    let frame_count = match window.get(xing..xing + 4) {
+        Some(b"Xing") | Some(b"Info") if frames(xing + 4).is_some_and(|flags| flags & 1 != 0) => frames(xing + 8),
+        _ if window.get(offset + 36..offset + 40) == Some(b"VBRI") => frames(offset + 50),
+        _ => None,
+    };
+    let duration = match frame_count {
+        Some(count) => count as f64 * frame.samples_per_frame as f64 / frame.sample_rate as f64,
+        None => (end - start - offset as u64) as f64 * 8.0 / (frame.bitrate_kbps as f64 * 1000.0),
+    };
+    Ok(Some(duration))
+}
+
+struct MpegFrame {
+    bitrate_kbps: u32,
+    sample_rate: u32,
+    samples_per_frame: u32,
+    /// Length of the side information between the header and a Xing/Info header.
+    side_info_len: usize,
+}
+
+/// Parses a 4-byte MPEG audio frame header, rejecting reserved and free-format values.
+fn parse_mpeg_header(header: &[u8]) -> Option<MpegFrame> {
+    const BITRATES_V1: [[u32; 15]; 3] = [
+        [0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448],
+        [0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384],
+        [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320],
+    ];
+    const BITRATES_V2: [[u32; 15]; 2] = [
+        [0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256],
+        [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+    ];
+
+    if header[0] != 0xFF || header[1] & 0xE0 != 0xE0 {
+        return None;
+    }
+    let version = (header[1] >> 3) & 3; // 0 = MPEG 2.5, 2 = MPEG 2, 3 = MPEG 1
+    let layer = (header[1] >> 1) & 3; // 1 = III, 2 = II, 3 = I
+    let bitrate_index = (header[2] >> 4) as usize;
+    let rate_index = ((header[2] >> 2) & 3) as usize;
+    if version == 1 || layer == 0 || bitrate_index == 0 || bitrate_index == 15 || rate_index == 3 {
+        return None;
+    }
+
+    let mpeg1 = version == 3;
+    let bitrate_kbps = match (mpeg1, layer) {
+        (true, _) => BITRATES_V1[(3 - layer) as usize][bitrate_index],
+        (false, 3) => BITRATES_V2[0][bitrate_index],
+        (false, _) => BITRATES_V2[1][bitrate_index],
+    };
+    let base_rate = [44100, 48000, 32000][rate_index];
+    let sample_rate = match version {
+        3 => base_rate,
+        2 => base_rate / 2,
+        _ => base_rate / 4,
+    };
+    let samples_per_frame = match layer {
+        3 => 384,
+        2 => 1152,
+        _ if mpeg1 => 1152,
+        _ => 576,
+    };
+    let mono = header[3] >> 6 == 3;
+    let side_info_len = match (mpeg1, mono) {
+        (true, true) => 17,
+        (true, false) => 32,
+        (false, true) => 9,
+        (false, false) => 17,
+    };
+    Some(MpegFrame { bitrate_kbps, sample_rate, samples_per_frame, side_info_len })
+}
+
+// --- FLAC and Ogg: Vorbis comments ---
+
+/// Reads the STREAMINFO and VORBIS_COMMENT metadata blocks of the FLAC stream at `offset`.
+fn read_flac_tags<R: Read + Seek>(reader: &mut R, offset: u64) -> Result<AudioTags, Box<dyn Error>> {
+    let mut tags = AudioTags::default();
+    let mut pos = offset + 4;
+    loop {
+        let header = read_at(reader, pos, 4)?;
+        let (last, block_type) = (header[0] & 0x80 != 0, header[0] & 0x7F);
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as u64;
+        match block_type {
+            0 => {
+                let info = read_at(reader, pos + 4, len)?;
+                if info.len() >= 18 {
+                    let sample_rate = ((info[10] as u32) << 12) | ((info[11] as u32) << 4) | (info[12] as u32 >> 4);
+                    let total_samples = (((info[13] & 0x0F) as u64) << 32) | be_u32(&info[14..18]) as u64;
+                    if sample_rate > 0 && total_samples > 0 {
+                        tags.duration = Some(total_samples as f64 / sample_rate as f64);
+                    }
+                }
+            }
+            4 => parse_vorbis_comments(&read_at(reader, pos + 4, len)?, &mut tags)?,
+            _ => {}
+        }
+        pos += 4 + len;
+        if last {
+            break;
+        }
+    }
+    Ok(tags)
+}
+
+/// Parses a Vorbis comment block: a vendor string followed by "KEY=value" entries.
+fn parse_vorbis_comments(bytes: &[u8], tags: &mut AudioTags) -> Result<(), Box<dyn Error>> {
+    let mut pos = 0;
+    let mut next = |len: usize| -> Result<&[u8], Box<dyn Error>> {
+        let field = bytes.get(pos..pos + len).ok_or("truncated Vorbis comment")?;
+        pos += len;
+        Ok(field)
+    };
+    let vendor_len = le_u32(next(4)?) as usize;
+    next(vendor_len)?;
+    let count = le_u32(next(4)?);
+    for _ in 0..count {
+        let len = le_u32(next(4)?) as usize;
+        let entry = String::from_utf8_lossy(next(len)?);
+        if let Some((key, value)) = entry.split_once('=') {
+            tags.set(key, value);
+        }
+    }
+    Ok(())
+}
+
+/// Reads the comment header and the length of the first Vorbis or Opus stream of an Ogg file.
+fn read_ogg_tags<R: Read + Seek>(reader: &mut R, file_len: u64) -> Result<AudioTags, Box<dyn Error>> {
+    // Reassemble the identification and comment packets of the first logical stream.
+    let mut packets: Vec<Vec<u8>> = Vec::new();
+    let mut packet = Vec::new();
+    let mut serial = None;
+    let mut pos = 0;
+    while packets.len() < 2 && pos + 27 <= file_len {
+        let header = read_at(reader, pos, 27)?;
+        if !header.starts_with(b"OggS") {
+            return Err("invalid Ogg page".into());
+        }
+        let page_serial = le_u32(&header[14..18]);
+        let segments = read_at(reader, pos + 27, header[26] as u64)?;
+        let body_len: u64 = segments.iter().map(|&len| len as u64).sum();
+        let body_start = pos + 27 + segments.len() as u64;
+        pos = body_start + body_len;
+
+        if *serial.get_or_insert(page_serial) != page_serial {
+            continue;
+        }
+        let body = read_at(reader, body_start, body_len)?;
+        let mut offset = 0;
+        for &len in &segments {
+            packet.extend_from_slice(&body[offset..offset + len as usize]);
+            offset += len as usize;
+            // A segment shorter than 255 bytes ends the packet.
+            if len < 255 {
+                packets.push(std::mem::take(&mut packet));
+            }
+            if packet.len() as u64 > MAX_TAG_BYTES {
+                return Err("Ogg header packet is too large".into());
+            }
+        }
+    }
+    let [ident, comments, ..] = packets.as_slice() else {
+        return Err("Ogg stream has no comment header".into());
+    };
+
+    let mut tags = AudioTags::default();
+    let (sample_rate, pre_skip) = if ident.starts_with(b"\x01vorbis") && ident.len() >= 16 {
+        parse_vorbis_comments(comments.strip_prefix(b"\x03vorbis").ok_or("missing Vorbis comment header")?, &mut tags)?;
+        (le_u32(&ident[12..16]) as u64, 0)
+    } else if ident.starts_with(b"OpusHead") && ident.len() >= 12 {
+        parse_vorbis_comments(comments.strip_prefix(b"OpusTags").ok_or("missing Opus comment header")?, &mut tags)?;
+        // Opus granule positions always count 48 kHz samples.
+        (48000, u16::from_le_bytes([ident[10], ident[11]]) as u64)
+    } else {
+        return Err("unsupported Ogg codec".into());
+    };
+
+    // The granule position of the stream's last page is its length in samples.
+    let tail_len = file_len.min(64 * 1024);
+    let tail = read_at(reader, file_len - tail_len, tail_len)?;
+    let last_granule = (0..tail.len().saturating_sub(27))
+        .rev()
+        .filter(|&i| tail[i..].starts_with(b"OggS") && Some(le_u32(&tail[i + 14..i + 18])) == serial)
+        .map(|i| u64::from_le_bytes(tail[i + 6..i + 14].try_into().unwrap_or_default()))
+        .find(|&granule| granule != u64::MAX);
+    if let Some(granule) = last_granule.filter(|_| sample_rate > 0) {
+        tags.duration = Some(granule.saturating_sub(pre_skip) as f64 / sample_rate as f64);
+    }
+    Ok(tags)
+}
+
+// --- MP4: iTunes-style metadata atoms ---
+
+/// An atom's type and the byte range of its body.
+type Atom = ([u8; 4], u64, u64);
+
+/// Lists the atoms between `start` and `end`.
+fn mp4_atoms<R: Read + Seek>(reader: &mut R, start: u64, end: u64) -> Result<Vec<Atom>, Box<dyn Error>> {
+    let mut atoms = Vec::new();
+    let mut pos = start;
+    while pos + 8 <= end {
+        let header = read_at(reader, pos, 8)?;
+        let atom_type = [header[4], header[5], header[6], header[7]];
+        let (header_len, size) = match be_u32(&header) as u64 {
+            0 => (8, end - pos),
+            // A 64-bit size follows the type, if there is room for it.
+            1 if pos + 16 <= end => (16, u64::from_be_bytes(read_at(reader, pos + 8, 8)?.try_into().unwrap_or_default())),
+            1 => break,
+            size => (8, size),
+        };
+        if size < header_len {
+            break;
+        }
+        let atom_end = pos.checked_add(size).map_or(end, |atom_end| atom_end.min(end));
+        atoms.push((atom_type, pos + header_len, atom_end));
+        pos = atom_end;
+    }
+    Ok(atoms)
+}
+
+fn find_mp4_atom<R: Read + Seek>(reader: &mut R, start: u64, end: u64, atom_type: &[u8; 4]) -> Result<Option<Atom>, Box<dyn Error>> {
+    Ok(mp4_atoms(reader, start, end)?.into_iter().find(|(t, _, _)| t == atom_type))
+}
+
+/// Reads the duration from `moov/mvhd` and the tags from `moov/udta/meta/ilst`.
+fn read_mp4_tags<R: Read + Seek>(reader: &mut R, file_len: u64) -> Result<AudioTags, Box<dyn Error>> {
+    let mut tags = AudioTags::default();
+    let (_, moov_start, moov_end) = find_mp4_atom(reader, 0, file_len, b"moov")?.ok_or("MP4 file has no moov atom")?;
+
+    if let Some((_, start, end)) = find_mp4_atom(reader, moov_start, moov_end, b"mvhd")? {
+        let mvhd = read_at(reader, start, (end - start).min(32))?;
+        let (timescale, duration) = match mvhd.first() {
+            Some(1) if mvhd.len() >= 32 => (be_u32(&mvhd[20..24]) as u64, u64::from_be_bytes(mvhd[24..32].try_into()?)),
+            Some(_) if mvhd.len() >= 20 => (be_u32(&mvhd[12..16]) as u64, be_u32(&mvhd[16..20]) as u64),
+            _ => (0, 0),
+        };
+        if timescale > 0 {
+            tags.duration = Some(duration as f64 / timescale as f64);
+        }
+    }
+
+    let Some((_, udta_start, udta_end)) = find_mp4_atom(reader, moov_start, moov_end, b"udta")? else {
+        return Ok(tags);
+    };
+    let Some((_, meta_start, meta_end)) = find_mp4_atom(reader, udta_start, udta_end, b"meta")? else {
+        return Ok(tags);
+    };
+    // `meta` is a full atom (4 bytes of version and flags) in MP4, but not in QuickTime files.
+    let children_start = if read_at(reader, meta_start + 4, 4)? == b"hdlr" { meta_start } else { meta_start + 4 };
+    let Some((_, ilst_start, ilst_end)) = find_mp4_atom(reader, children_start, meta_end, b"ilst")? else {
+        return Ok(tags);
+    };
+
+    for (item, start, end) in mp4_atoms(reader, ilst_start, ilst_end)? {
+        let key = match &item {
+            b"\xa9nam" => "title",
+            b"\xa9ART" => "artist",
+            b"\xa9alb" => "album",
+            b"aART" => "albumartist",
+            b"trkn" => "trkn",
+            b"----" => "----",
+            _ => continue,
+        };
+        let mut name = None;
+        let mut values = Vec::new();
+        for (child, child_start, child_end) in mp4_atoms(reader, start, end)? {
+            // `name` and `data` start with 4 bytes of version and flags; `data` then has 4 more
+            // bytes of locale.
+            match &child {
+                b"name" => name = Some(read_at(reader, child_start + 4, child_end.saturating_sub(child_start + 4))?),
+                b"data" => values.push(read_at(reader, child_start + 8, child_end.saturating_sub(child_start + 8))?),
+                _ => {}
+            }
+        }
+        for value in values {
+            match key {
+                "trkn" if value.len() >= 4 => tags.track_number = Some(u16::from_be_bytes([value[2], value[3]]) as u32),
+                "trkn" => {}
+                // Freeform iTunes atoms, e.g. "ISRC" or "MusicBrainz Track Id".
+                "----" => {
+                    if let Some(name) = &name {
+                        tags.set(&String::from_utf8_lossy(name), &String::from_utf8_lossy(&value));
+                    }
+                }
+                _ => tags.set(key, &String::from_utf8_lossy(&value)),
+            }
+        }
+    }
+    Ok(tags)
+}
+
+// --- WAV: RIFF INFO ---
+
+/// Reads the RIFF INFO tags of a WAV file and, where they are missing, those of an embedded
+/// ID3v2 chunk.
+pub(crate) fn read_wav_tags<R: Read + Seek>(reader: &mut R) -> Result<AudioTags, Box<dyn Error>> {
+    let layout = parse_wav_layout(reader)?;
+    let mut tags = AudioTags { duration: Some(layout.duration()), ..AudioTags::default() };
+    for (id, key) in [("INAM", "title"), ("IART", "artist"), ("IPRD", "album"), ("IPRT", "track"), ("ITRK", "track")] {
+        if let Some(value) = layout.info.get(id) {
+            tags.set(key, value);
+        }
+    }
+    if let Some((offset, len)) = layout.id3
+        && len >= 10
+        && read_at(reader, offset, 3)? == b"ID3"
+    {
+        tags.fill_from(read_id3v2(reader, offset)?.0);
+    }
+    Ok(tags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn write_temp(dir: &tempfile::TempDir, name: &str, bytes: &[u8]) -> String {
+        let path = dir.path().join(name);
+        std::fs::write(&path, bytes).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn id3v23_frame(id: &str, data: &[u8]) -> Vec<u8> {
+        let mut frame = id.as_bytes().to_vec();
+        frame.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(data);
+        frame
+    }
+
+    fn vorbis_comments(entries: &[&str]) -> Vec<u8> {
+        let mut block = 4u32.to_le_bytes().to_vec();
+        block.extend_from_slice(b"test");
+        block.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+        for entry in entries {
+            block.extend_from_slice(&(entry.len() as u32).to_le_bytes());
+            block.extend_from_slice(entry.as_bytes());
+        }
+        block
+    }
+
+    fn atom(atom_type: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut atom = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        atom.extend_from_slice(atom_type);
+        atom.extend_from_slice(body);
+        atom
+    }
+
+    fn ogg_page(serial: u32, granule: u64, packets: &[&[u8]]) -> Vec<u8> {
+        let mut segments = Vec::new();
+        let mut body = Vec::new();
+        for packet in packets {
+            let mut len = packet.len();
+            while len >= 255 {
+                segments.push(255u8);
+                len -= 255;
+            }
+            segments.push(len as u8);
+            body.extend_from_slice(packet);
+        }
+        let mut page = b"OggS\x00\x00".to_vec();
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&serial.to_le_bytes());
+        page.extend_from_slice(&[0; 8]);
+        page.push(segments.len() as u8);
+        page.extend_from_slice(&segments);
+        page.extend_from_slice(&body);
+        page
+    }
+
+    #[test]
+    fn test_mp3_id3v2_with_id3v1_fallback_and_cbr_duration() {
+        let mut frames = id3v23_frame("TIT2", b"\x00Song Title");
+        // UTF-16 with a byte order mark.
+        let mut artist = vec![1, 0xFF, 0xFE];
+        artist.extend("Ärtist".encode_utf16().flat_map(|u| u.to_le_bytes()));
+        frames.extend(id3v23_frame("TPE1", &artist));
+        frames.extend(id3v23_frame("TRCK", b"\x003/12"));
+        frames.extend(id3v23_frame("TSRC", b"\x00USRC17607839"));
+        frames.extend(id3v23_frame("TXXX", b"\x00MusicBrainz Album Id\x00release-id"));
+        frames.extend(id3v23_frame("UFID", b"http://musicbrainz.org\x00recording-id"));
+        frames.extend([0; 16]);
+
+        let mut file = b"ID3\x03\x00\x00".to_vec();
+        let size = frames.len() as u32;
+        file.extend([(size >> 21) as u8 & 0x7F, (size >> 14) as u8 & 0x7F, (size >> 7) as u8 & 0x7F, size as u8 & 0x7F]);
+        file.extend(&frames);
+        // 128 kbps, 44.1 kHz MPEG-1 Layer III frames: 16000 bytes of audio last one second.
+        let audio_start = file.len();
+        file.extend([0xFF, 0xFB, 0x90, 0x64]);
+        file.resize(audio_start + 16_000, 0);
+        let mut v1 = vec![0u8; 128];
+        v1[..3].copy_from_slice(b"TAG");
+        v1[63..68].copy_from_slice(b"Album");
+        file.extend(v1);
+
+        let dir = tempfile::tempdir().unwrap();
+        let tags = read_tags(&write_temp(&dir, "song.mp3", &file)).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Song Title"));
+        assert_eq!(tags.artists, vec!["Ärtist"]);
+        assert_eq!(tags.album.as_deref(), Some("Album"));
+        assert_eq!(tags.track_number, Some(3));
+        assert_eq!(tags.isrc.as_deref(), Some("USRC17607839"));
+        assert_eq!(tags.musicbrainz_release_id.as_deref(), Some("release-id"));
+        assert_eq!(tags.musicbrainz_track_id.as_deref(), Some("recording-id"));
+        assert!((tags.duration.unwrap() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_flac_vorbis_comments_and_streaminfo() {
+        let mut streaminfo = vec![0u8; 34];
+        // 44100 Hz, stereo, 16 bits, 88200 samples.
+        streaminfo[10..14].copy_from_slice(&[0x0A, 0xC4, 0x42, 0xF0]);
+        streaminfo[14..18].copy_from_slice(&88200u32.to_be_bytes());
+        let comments = vorbis_comments(&["TITLE=Song", "ARTIST=One", "ARTIST=Two", "TRACKNUMBER=7", "MUSICBRAINZ_ARTISTID=artist-id"]);
+
+        let mut file = b"fLaC".to_vec();
+        file.extend([0, 0, 0, 34]);
+        file.extend(&streaminfo);
+        file.extend([0x84, 0, 0, comments.len() as u8]);
+        file.extend(&comments);
+
+        let dir = tempfile::tempdir().unwrap();
+        let tags = read_tags(&write_temp(&dir, "song.flac", &file)).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Song"));
+        assert_eq!(tags.artist().as_deref(), Some("One, Two"));
+        assert_eq!(tags.track_number, Some(7));
+        assert_eq!(tags.musicbrainz_artist_id.as_deref(), Some("artist-id"));
+        assert_eq!(tags.duration, Some(2.0));
+    }
+
+    #[test]
+    fn test_ogg_vorbis_comments_and_duration() {
+        let mut ident = b"\x01vorbis\x00\x00\x00\x00\x02".to_vec();
+        ident.extend(48000u32.to_le_bytes());
+        ident.resize(30, 0);
+        let mut comment = b"\x03vorbis".to_vec();
+        // Long enough to span several lacing segments.
+        comment.extend(vorbis_comments(&[&format!("TITLE={}", "x".repeat(600)), "ISRC=GBAYE0000351"]));
+
+        let mut file = ogg_page(7, 0, &[&ident]);
+        file.extend(ogg_page(7, 0, &[&comment]));
+        file.extend(ogg_page(7, 144_000, &[b"audio"]));
+
+        let dir = tempfile::tempdir().unwrap();
+        let tags = read_tags(&write_temp(&dir, "song.ogg", &file)).unwrap();
+        assert_eq!(tags.title.as_ref().map(String::len), Some(600));
+        assert_eq!(tags.isrc.as_deref(), Some("GBAYE0000351"));
+        assert_eq!(tags.duration, Some(3.0));
+    }
+
+    #[test]
+    fn test_mp4_ilst_atoms() {
+        let data = |value: &[u8]| atom(b"data", &[&[0, 0, 0, 1, 0, 0, 0, 0][..], value].concat());
+        let mut ilst = atom(b"\xa9nam", &data(b"Song"));
+        ilst.extend(atom(b"\xa9ART", &data(b"Artist")));
+        ilst.extend(atom(b"trkn", &data(&[0, 0, 0, 5, 0, 9, 0, 0])));
+        let freeform = [
+            atom(b"mean", b"\x00\x00\x00\x00com.apple.iTunes"),
+            atom(b"name", b"\x00\x00\x00\x00MusicBrainz Track Id"),
+            data(b"recording-id"),
+        ]
+        .concat();
+        ilst.extend(atom(b"----", &freeform));
+
+        let meta = atom(b"meta", &[&[0, 0, 0, 0][..], &atom(b"hdlr", &[0; 25]), &atom(b"ilst", &ilst)].concat());
+        let mut mvhd = vec![0u8; 100];
+        mvhd[12..16].copy_from_slice(&1000u32.to_be_bytes());
+        mvhd[16..20].copy_from_slice(&2500u32.to_be_bytes());
+        let moov = atom(b"moov", &[atom(b"mvhd", &mvhd), atom(b"udta", &meta)].concat());
+        let file = [atom(b"ftyp", b"M4A \x00\x00\x00\x00"), atom(b"mdat", &[0; 64]), moov].concat();
+
+        let dir = tempfile::tempdir().unwrap();
+        let tags = read_tags(&write_temp(&dir, "song.m4a", &file)).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Song"));
+        assert_eq!(tags.artists, vec!["Artist"]);
+        assert_eq!(tags.track_number, Some(5));
+        assert_eq!(tags.musicbrainz_track_id.as_deref(), Some("recording-id"));
+        assert_eq!(tags.duration, Some(2.5));
+    }
+
+    #[test]
+    fn test_mp4_atoms_with_bad_64_bit_sizes() {
+        let ftyp = atom(b"ftyp", b"M4A \x00\x00\x00\x00");
+        // A 64-bit size that overflows the file position is cut off at the end of the parent.
+        let huge = [ftyp.clone(), [&1u32.to_be_bytes()[..], b"mdat", &u64::MAX.to_be_bytes()].concat()].concat();
+        let atoms = mp4_atoms(&mut Cursor::new(&huge), 0, huge.len() as u64).unwrap();
+        assert_eq!(atoms[1], (*b"mdat", huge.len() as u64, huge.len() as u64));
+
+        // A 64-bit size field that runs past the end of the parent ends the list.
+        let truncated = [ftyp, [&1u32.to_be_bytes()[..], b"mdat", &[0; 4]].concat()].concat();
+        assert_eq!(mp4_atoms(&mut Cursor::new(&truncated), 0, truncated.len() as u64).unwrap().len(), 1);
+
+        let dir = tempfile::tempdir().unwrap();
+        assert!(read_tags(&write_temp(&dir, "huge.m4a", &huge)).is_err());
+        assert!(read_tags(&write_temp(&dir, "truncated.m4a", &truncated)).is_err());
+    }
+
+    #[test]
+    fn test_wav_info_and_unknown_containers() {
+        let mut fmt = 1u16.to_le_bytes().to_vec();
+        fmt.extend(1u16.to_le_bytes());
+        fmt.extend(8000u32.to_le_bytes());
+        fmt.extend(16000u32.to_le_bytes());
+        fmt.extend(2u16.to_le_bytes());
+        fmt.extend(16u16.to_le_bytes());
+        let chunk = |id: &[u8], body: &[u8]| {
+            let padding: &[u8] = if body.len() % 2 == 1 { &[0] } else { &[] };
+            [id, &(body.len() as u32).to_le_bytes(), body, padding].concat()
+        };
+        let info = [&b"INFO"[..], &chunk(b"INAM", b"Title\x00"), &chunk(b"IART", b"Band\x00"), &chunk(b"IPRT", b"4\x00")].concat();
+        let body = [&b"WAVE"[..], &chunk(b"fmt ", &fmt), &chunk(b"LIST", &info), &chunk(b"data", &[0; 8000])].concat();
+        let file = [&b"RIFF"[..], &(body.len() as u32).to_le_bytes(), &body].concat();
+
+        let tags = read_wav_tags(&mut Cursor::new(file)).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Title"));
+        assert_eq!(tags.artists, vec!["Band"]);
+        assert_eq!(tags.track_number, Some(4));
+        assert_eq!(tags.duration, Some(0.5));
+
+        let dir = tempfile::tempdir().unwrap();
+        let err = read_tags(&write_temp(&dir, "notes.txt", b"just some text")).unwrap_err();
+        assert!(err.to_string().contains("unrecognized audio container"));
+    }
+}
@@ -1,13 +1,15 @@
 use std::collections::HashMap;
 use std::error::Error;
 use std::fs::File;
-use std::io::{self, Cursor, Read, Write};
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::path::Path;
 use std::process::Command;
 
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{LittleEndian, WriteBytesExt};
 use serde::{Deserialize, Serialize};
 
+use crate::wav::{parse_wav_layout, WavFormat, WAVE_FORMAT_IEEE_FLOAT, WAVE_FORMAT_PCM};
+
 /// Represents the header of a WAV file.
 pub struct WavHeader {
     pub chunk_id: [u8; 4],
@@ -107,54 +109,105 @@ pub fn write_wav_file(
 pub struct WavInfo {
     pub channels: i32,
     pub sample_rate: i32,
+    /// The raw interleaved sample data, in the layout described by `format`.
     pub data: Vec<u8>,
     pub duration: f64,
+    pub format: WavFormat,
+    /// Text tags from the file's `LIST`/`INFO` chunk, keyed by four-character ID (e.g. "INAM").
+    pub info: HashMap<String, String>,
+}
+
+impl WavInfo {
+    /// Decodes the sample data and averages the channels down to mono, scaled to [-1, 1].
+    pub fn mono_samples(&self) -> Result<Vec<f64>, Box<dyn Error>> {
+        let samples = pcm_bytes_to_samples(&self.data, &self.format)?;
+        Ok(downmix_to_mono(&samples, self.format.channels as usize))
+    }
 }
 
 /// Reads a WAV file and extracts header information along with the PCM data.
 pub fn read_wav_info(filename: &str) -> Result<WavInfo, Box<dyn Error>> {
-    let data = std::fs::read(filename)?;
-    if data.len() < 44 {
-        return Err("invalid WAV file size (too small)".into());
-    }
-
-    let mut rdr = Cursor::new(&data[..44]);
-
-    let mut chunk_id = [0u8; 4];
-    rdr.read_exact(&mut chunk_id)?;
-    let _chunk_size = rdr.read_u32::<LittleEndian>()?;
-    let mut format = [0u8; 4];
-    rdr.read_exact(&mut format)?;
-    let mut subchunk1_id = [0u8; 4];
-    rdr.read_exact(&mut subchunk1_id)?;
-    let _subchunk1_size = rdr.read_u32::<LittleEndian>()?;
-    let audio_format = rdr.read_u16::<LittleEndian>()?;
-    let num_channels = rdr.read_u16::<LittleEndian>()?;
-    let sample_rate = rdr.read_u32::<LittleEndian>()?;
-    let _bytes_per_sec = rdr.read_u32::<LittleEndian>()?;
-    let _block_align = rdr.read_u16::<LittleEndian>()?;
-    let bits_per_sample = rdr.read_u16::<LittleEndian>()?;
-    let mut subchunk2_id = [0u8; 4];
-    rdr.read_exact(&mut subchunk2_id)?;
-    let _subchunk2_size = rdr.read_u32::<LittleEndian>()?;
-
-    if &chunk_id != b"RIFF" || &format != b"WAVE" || audio_format != 1 {
-        return Err("invalid WAV header format".into());
-    }
-
-    let mut info = WavInfo {
-        channels: num_channels as i32,
-        sample_rate: sample_rate as i32,
-        data: data[44..].to_vec(),
-        duration: 0.0,
+    let mut reader = io::BufReader::new(File::open(filename)?);
+    let layout = parse_wav_layout(&mut reader)?;
+    check_sample_format(&layout.format)?;
+
+    reader.seek(SeekFrom::Start(layout.data_offset))?;
+    let mut data = Vec::with_capacity(layout.data_len as usize);
+    reader.take(layout.data_len).read_to_end(&mut data)?;
+
+    Ok(WavInfo {
+        channels: layout.format.channels as i32,
+        sample_rate: layout.format.sample_rate as i32,
+        data,
+        duration: layout.duration(),
+        format: layout.format,
+        info: layout.info,
+    })
+}
+
+/// Checks that the samples are 8, 16, 24 or 32-bit integer PCM or 32 or 64-bit float, and
+/// that each frame holds one such sample per channel.
+fn check_sample_format(format: &WavFormat) -> Result<(), Box<dyn Error>> {
+    let supported = match format.format_tag {
+        WAVE_FORMAT_PCM => matches!(format.bits_per_sample, 8 | 16 | 24 | 32),
+        WAVE_FORMAT_IEEE_FLOAT => matches!(format.bits_per_sample, 32 | 64),
+        _ => return Err(format!("unsupported WAV sample format {:#06x}", format.format_tag).into()),
     };
+    if !supported {
+        return Err(format!("unsupported bits per sample format: {}", format.bits_per_sample).into());
+    }
+    if format.block_align as usize != format.channels as usize * bytes_per_sample(format) {
+        return Err(format!(
+            "WAV block size {} does not match {} channels of {}-bit samples",
+            format.block_align, format.channels, format.bits_per_sample
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn bytes_per_sample(format: &WavFormat) -> usize {
+    format.bits_per_sample as usize / 8
+}
+
+/// Reads the samples of a WAV file a chunk at a time, downmixed to mono, so long recordings
+/// can be processed without loading them into memory.
+pub struct WavStream {
+    pub channels: i32,
+    pub sample_rate: i32,
+    format: WavFormat,
+    reader: io::Take<io::BufReader<File>>,
+}
 
-    if bits_per_sample == 16 {
-        info.duration = info.data.len() as f64 / (num_channels as f64 * 2.0 * sample_rate as f64);
-    } else {
-        return Err("unsupported bits per sample format".into());
+impl WavStream {
+    /// Opens a WAV file, locates its format and data chunks and positions the stream at the
+    /// first sample.
+    pub fn open(filename: &str) -> Result<Self, Box<dyn Error>> {
+        let mut reader = io::BufReader::new(File::open(filename)?);
+        let layout = parse_wav_layout(&mut reader)?;
+        check_sample_format(&layout.format)?;
+        reader.seek(SeekFrom::Start(layout.data_offset))?;
+        Ok(WavStream {
+            channels: layout.format.channels as i32,
+            sample_rate: layout.format.sample_rate as i32,
+            format: layout.format,
+            reader: reader.take(layout.data_len),
+        })
+    }
+
+    /// Returns up to `max_frames` mono samples scaled to [-1, 1], or `None` at the end of the data.
+    pub fn read_chunk(&mut self, max_frames: usize) -> Result<Option<Vec<f64>>, Box<dyn Error>> {
+        let frame_bytes = self.format.block_align as usize;
+        let mut bytes = Vec::with_capacity(max_frames * frame_bytes);
+        (&mut self.reader).take((max_frames * frame_bytes) as u64).read_to_end(&mut bytes)?;
+        // A trailing partial frame cannot be decoded.
+        bytes.truncate(bytes.len() - bytes.len() % frame_bytes);
+        if bytes.is_empty() {
+            return Ok(None);
+        }
+        let samples = pcm_bytes_to_samples(&bytes, &self.format)?;
+        Ok(Some(downmix_to_mono(&samples, self.format.channels as usize)))
     }
-    Ok(info)
 }
 
 /// Converts a slice of 16-bit PCM bytes to a vector of f64 samples scaled in the range [-1, 1].
@@ -170,6 +223,48 @@ pub fn wav_bytes_to_samples(input: &[u8]) -> Result<Vec<f64>, Box<dyn Error>> {
     }
     Ok(output)
 }
+
+/// Converts interleaved sample bytes in the given format to f64 samples scaled to [-1, 1].
+/// 8-bit samples are unsigned, wider integers signed, and float samples are taken as is.
+/// This is the reading counterpart of `utils::floats_to_bytes`.
+pub fn pcm_bytes_to_samples(input: &[u8], format: &WavFormat) -> Result<Vec<f64>, Box<dyn Error>> {
+    check_sample_format(format)?;
+    let width = bytes_per_sample(format);
+    if !input.len().is_multiple_of(width) {
+        return Err("invalid input length".into());
+    }
+    let samples = input.chunks_exact(width);
+    let output = match (format.format_tag, format.bits_per_sample) {
+        (WAVE_FORMAT_PCM, 8) => samples.map(|b| (b[0] as f64 - 128.0) / 128.0).collect(),
+        (WAVE_FORMAT_PCM, 16) => samples.map(|b| i16::from_le_bytes([b[0], b[1]]) as f64 / 32768.0).collect(),
+        // Place the three bytes in the top of an i32 so the sign extends.
+        (WAVE_FORMAT_PCM, 24) => samples
+            .map(|b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f64 / 8388608.0)
+            .collect(),
+        (WAVE_FORMAT_PCM, 32) => samples
+            .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64 / 2147483648.0)
+            .collect(),
+        (WAVE_FORMAT_IEEE_FLOAT, 32) => samples.map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64).collect(),
+        (WAVE_FORMAT_IEEE_FLOAT, 64) => samples
+            .map(|b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+            .collect(),
+        _ => unreachable!("sample format was checked above"),
+    };
+    Ok(output)
+}
+
+/// Averages interleaved samples of `channels` channels into one channel. A trailing partial
+/// frame is dropped.
+pub fn downmix_to_mono(samples: &[f64], channels: usize) -> Vec<f64> {
+    if channels <= 1 {
+        return samples.to_vec();
+    }
+    samples
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f64>() / channels as f64)
+        .collect()
+}
+
 fn default_start_time() -> String {
     "0".to_string()
 }
@@ -232,3 +327,78 @@ pub fn get_metadata(file_path: &str) -> Result<FFmpegMetadata, Box<dyn Error>> {
     let metadata: FFmpegMetadata = serde_json::from_slice(&output.stdout)?;
     Ok(metadata)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::floats_to_bytes;
+
+    fn format(format_tag: u16, channels: u16, bits_per_sample: u16) -> WavFormat {
+        WavFormat {
+            format_tag,
+            channels,
+            sample_rate: 8000,
+            block_align: channels * bits_per_sample / 8,
+            bits_per_sample,
+            valid_bits_per_sample: bits_per_sample,
+            channel_mask: None,
+        }
+    }
+
+    #[test]
+    fn test_integer_depths_round_trip_through_floats_to_bytes() {
+        let samples = [0.0, 0.5, -0.5, 0.99, -0.99];
+        for bits in [8, 16, 24, 32] {
+            let bytes = floats_to_bytes(&samples, bits as i32).unwrap();
+            let decoded = pcm_bytes_to_samples(&bytes, &format(WAVE_FORMAT_PCM, 1, bits)).unwrap();
+            let tolerance = 2.0 / 2f64.powi(bits as i32 - 1);
+            for (&original, &read) in samples.iter().zip(&decoded) {
+                assert!((original - read).abs() <= tolerance, "{}-bit: {} read back as {}", bits, original, read);
+            }
+        }
+        // Full-scale negative values keep their sign.
+        let min24 = pcm_bytes_to_samples(&[0x00, 0x00, 0x80], &format(WAVE_FORMAT_PCM, 1, 24)).unwrap();
+        assert_eq!(min24, vec![-1.0]);
+    }
+
+    #[test]
+    fn test_float_samples() {
+        let bytes32: Vec<u8> = [0.25f32, -1.0].iter().flat_map(|s| s.to_le_bytes()).collect();
+        let bytes64: Vec<u8> = [0.25f64, -1.0].iter().flat_map(|s| s.to_le_bytes()).collect();
+        assert_eq!(pcm_bytes_to_samples(&bytes32, &format(WAVE_FORMAT_IEEE_FLOAT, 1, 32)).unwrap(), vec![0.25, -1.0]);
+        assert_eq!(pcm_bytes_to_samples(&bytes64, &format(WAVE_FORMAT_IEEE_FLOAT, 1, 64)).unwrap(), vec![0.25, -1.0]);
+        assert!(pcm_bytes_to_samples(&bytes32, &format(WAVE_FORMAT_IEEE_FLOAT, 1, 16)).is_err());
+    }
+
+    #[test]
+    fn test_read_24_bit_stereo_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stereo24.wav");
+        let path = path.to_str().unwrap();
+        // One second at 8 kHz; the left channel at 0.5 and the right at -0.25.
+        let interleaved: Vec<f64> = (0..16000).map(|i| if i % 2 == 0 { 0.5 } else { -0.25 }).collect();
+        let data = floats_to_bytes(&interleaved, 24).unwrap();
+        write_wav_file(path, &data, 8000, 2, 24).unwrap();
+
+        let info = read_wav_info(path).unwrap();
+        assert_eq!((info.channels, info.sample_rate), (2, 8000));
+        assert!((info.duration - 1.0).abs() < 1e-12);
+        let mono = info.mono_samples().unwrap();
+        assert_eq!(mono.len(), 8000);
+        assert!(mono.iter().all(|&s| (s - 0.125).abs() < 1e-6));
+
+        let mut stream = WavStream::open(path).unwrap();
+        let mut streamed = Vec::new();
+        while let Some(chunk) = stream.read_chunk(3001).unwrap() {
+            assert!(chunk.len() <= 3001);
+            streamed.extend(chunk);
+        }
+        assert_eq!(streamed, mono);
+    }
+
+    #[test]
+    fn test_downmix_to_mono() {
+        assert_eq!(downmix_to_mono(&[1.0, 0.0, 0.5, 0.5, 0.25], 2), vec![0.5, 0.5]);
+        assert_eq!(downmix_to_mono(&[0.1, 0.2], 1), vec![0.1, 0.2]);
+    }
+}